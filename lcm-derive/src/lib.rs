@@ -54,7 +54,7 @@ pub fn lcm_message(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
         impl ::lcm::Message for #name
         {
             const HASH: u64 = {
-                const PRE_HASH: u64 = #hash #(+ <#hash_included_fields as ::lcm::Message>::HASH)*;
+                const PRE_HASH: u64 = #hash #(.wrapping_add(<#hash_included_fields as ::lcm::Message>::HASH))*;
                 (PRE_HASH << 1) + ((PRE_HASH >> 63) & 1)
             };
         }
@@ -78,6 +78,9 @@ impl Field {
         if self.dims.is_empty() {
             quote! {let #name = ::lcm::Marshall::decode(&mut buffer)?; }
         } else {
+            // Keep track of whether the tokens evaluate to a `Result` or to a
+            // plain value. Fixed size arrays unwrap their elements directly,
+            // while variable sized arrays collect into a `Result`.
             let mut tokens = quote! { ::lcm::Marshall::decode(&mut buffer) };
             let mut need_q_mark = true;
             for d in self.dims.iter().rev() {
@@ -88,18 +91,39 @@ impl Field {
                         need_q_mark = false;
 
                         if old_q_mark {
-                            quote! { Ok([ #(#inner?,)* ]) }
+                            quote! { [ #(#inner?,)* ] }
                         } else {
                             quote! { [ #(#inner,)* ] }
                         }
                     }
                     Dim::Variable(ref s) => {
                         let dim_name = syn::Ident::from(s as &str);
+                        let old_q_mark = need_q_mark;
                         need_q_mark = true;
+
+                        let tokens = if old_q_mark {
+                            tokens
+                        } else {
+                            quote! { Ok(#tokens) }
+                        };
+                        // Running out of bytes partway through is reported
+                        // against the array, rather than as a bare I/O error.
                         quote! {
                             (0..#dim_name)
                                 .map(|_| #tokens)
                                 .collect::<Result<_, ::lcm::error::DecodeError>>()
+                                .map_err(|e| match e {
+                                    ::lcm::error::DecodeError::IoError(ref e)
+                                        if e.kind() == ::std::io::ErrorKind::UnexpectedEof =>
+                                    {
+                                        ::lcm::error::DecodeError::Truncated {
+                                            field: stringify!(#name),
+                                            size_var: stringify!(#dim_name),
+                                            expected: #dim_name as i64,
+                                        }
+                                    }
+                                    e => e,
+                                })
                         }
                     }
                 };
@@ -156,7 +180,7 @@ impl Field {
         if self.dims.is_empty() {
             quote! { ::lcm::Marshall::size(&self.#name)}
         } else {
-            let mut tokens = quote! { ::lcm::Marshall::size(&item) };
+            let mut tokens = quote! { ::lcm::Marshall::size(item) };
             for _ in self.dims.iter().skip(1).rev() {
                 tokens = quote!{ item.iter().map(|item| #tokens).sum::<usize>() }
             }
@@ -266,8 +290,10 @@ fn type_to_string(t: &syn::Type) -> String {
 /// Represents the data type of the field.
 ///
 /// This type can either be one of LCM's primitives or a "user defined" type.
-/// Note that this means that any unsigned integers will be considered
-/// user-defined, but they should fail appropriately at compile time.
+/// LCM's only unsigned type is `byte`, so `u16` and `u32` are extensions
+/// that other LCM implementations won't be able to decode. Any other
+/// unsigned integers will be considered user-defined, but they should fail
+/// appropriately at compile time.
 #[derive(Clone, Debug)]
 pub enum Ty {
     /// `int8_t`
@@ -282,6 +308,15 @@ pub enum Ty {
     /// `int64_t`
     Int64,
 
+    /// `byte`
+    UInt8,
+
+    /// `uint16_t`, which is not part of LCM.
+    UInt16,
+
+    /// `uint32_t`, which is not part of LCM.
+    UInt32,
+
     /// `float`
     Float,
 
@@ -313,6 +348,9 @@ impl Ty {
             Ty::Int16 => "int16_t",
             Ty::Int32 => "int32_t",
             Ty::Int64 => "int64_t",
+            Ty::UInt8 => "byte",
+            Ty::UInt16 => "uint16_t",
+            Ty::UInt32 => "uint32_t",
             Ty::Float => "float",
             Ty::Double => "double",
             Ty::String => "string",
@@ -331,6 +369,9 @@ impl Ty {
             Ty::Int16 => ::std::mem::size_of::<i16>(),
             Ty::Int32 => ::std::mem::size_of::<i32>(),
             Ty::Int64 => ::std::mem::size_of::<i64>(),
+            Ty::UInt8 => ::std::mem::size_of::<u8>(),
+            Ty::UInt16 => ::std::mem::size_of::<u16>(),
+            Ty::UInt32 => ::std::mem::size_of::<u32>(),
             Ty::Float => ::std::mem::size_of::<f32>(),
             Ty::Double => ::std::mem::size_of::<f64>(),
             Ty::Boolean => ::std::mem::size_of::<i8>(),
@@ -353,6 +394,9 @@ impl Ty {
                     "i16" => Ty::Int16,
                     "i32" => Ty::Int32,
                     "i64" => Ty::Int64,
+                    "u8" => Ty::UInt8,
+                    "u16" => Ty::UInt16,
+                    "u32" => Ty::UInt32,
                     "f32" => Ty::Float,
                     "f64" => Ty::Double,
                     "bool" => Ty::Boolean,
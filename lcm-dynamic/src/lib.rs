@@ -0,0 +1,238 @@
+//! Decoding of LCM messages whose types are only known at runtime.
+//!
+//! The `lcm` crate decodes messages into Rust types generated ahead of
+//! time. Tools such as loggers, bridges, and inspectors instead load
+//! `.lcm` schemas at runtime, and need to decode messages by walking
+//! the fields of a parsed `lcm_gen::ast::Struct`. This crate decodes
+//! such messages into a `DynMessage`, which is a tree of field names
+//! and values.
+
+#[macro_use]
+extern crate failure;
+extern crate lcm;
+extern crate lcm_gen;
+
+use lcm::Marshall;
+use lcm::error::DecodeError;
+use lcm_gen::ast::{Module, Multiplicity, Namespace, Struct, Type};
+use lcm_gen::hash;
+use std::io::Read;
+
+/// A decoded message.
+///
+/// The fields are stored in the order they are declared in the schema.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DynMessage {
+    /// The name of the struct this message was decoded as.
+    pub name: String,
+
+    /// The names and values of the fields.
+    pub fields: Vec<(String, Value)>,
+}
+impl DynMessage {
+    /// Returns the value of the field with the given name.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields
+            .iter()
+            .find(|&&(ref field, _)| field == name)
+            .map(|&(_, ref value)| value)
+    }
+}
+
+/// The value of a single field.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// `int8_t`
+    Int8(i8),
+
+    /// `int16_t`
+    Int16(i16),
+
+    /// `int32_t`
+    Int32(i32),
+
+    /// `int64_t`
+    Int64(i64),
+
+    /// `float`
+    Float(f32),
+
+    /// `double`
+    Double(f64),
+
+    /// `string`
+    String(String),
+
+    /// `boolean`
+    Boolean(bool),
+
+    /// `byte`
+    Byte(u8),
+
+    /// One dimension of an array.
+    Array(Vec<Value>),
+
+    /// A nested struct.
+    Struct(DynMessage),
+}
+impl Value {
+    /// Returns the value as an array length, if it is an integer.
+    fn as_length(&self) -> Option<i64> {
+        match *self {
+            Value::Int8(v) => Some(i64::from(v)),
+            Value::Int16(v) => Some(i64::from(v)),
+            Value::Int32(v) => Some(i64::from(v)),
+            Value::Int64(v) => Some(v),
+            Value::Byte(v) => Some(i64::from(v)),
+            _ => None,
+        }
+    }
+}
+
+/// An error that occurred while decoding a message.
+#[derive(Debug, Fail)]
+pub enum Error {
+    /// The message itself could not be decoded.
+    #[fail(display = "Unable to decode the message.")]
+    Decode(#[cause] DecodeError),
+
+    /// The schema refers to a type that is not in the module.
+    #[fail(display = "Unknown type \"{}\".", _0)]
+    UnknownType(String),
+
+    /// An array length refers to a field that is missing or that is not
+    /// an integer.
+    #[fail(display = "Invalid length field \"{}\".", _0)]
+    InvalidLengthField(String),
+}
+impl From<DecodeError> for Error {
+    fn from(err: DecodeError) -> Self {
+        Error::Decode(err)
+    }
+}
+
+/// Decodes a message of the type `name`, which is in the module at `path`.
+///
+/// The buffer should *not* start with the message hash. Struct members
+/// are resolved within `module` in the same way as `hash::fingerprint`.
+pub fn decode(
+    module: &Module,
+    path: &[Namespace],
+    name: &str,
+    buffer: &mut Read,
+) -> Result<DynMessage, Error> {
+    let s = module
+        .get_struct(path, name)
+        .ok_or_else(|| Error::UnknownType(name.into()))?;
+    decode_struct(module, path, s, buffer)
+}
+
+/// Decodes a message of the type `name`, and also checks that the hash at
+/// the beginning is correct.
+pub fn decode_with_hash(
+    module: &Module,
+    path: &[Namespace],
+    name: &str,
+    mut buffer: &mut Read,
+) -> Result<DynMessage, Error> {
+    let expected =
+        hash::fingerprint(module, path, name).ok_or_else(|| Error::UnknownType(name.into()))?;
+    let found: u64 = Marshall::decode(&mut buffer)?;
+    if found != expected {
+        return Err(DecodeError::HashMismatch { expected, found }.into());
+    }
+    decode(module, path, name, buffer)
+}
+
+/// Decodes the fields of a struct, which is in the module at `path`.
+pub fn decode_struct(
+    module: &Module,
+    path: &[Namespace],
+    s: &Struct,
+    buffer: &mut Read,
+) -> Result<DynMessage, Error> {
+    let mut fields: Vec<(String, Value)> = Vec::with_capacity(s.fields.len());
+
+    for field in &s.fields {
+        // Resolve the dimensions first, since they can only refer to
+        // fields that have already been decoded.
+        let mut dims = Vec::with_capacity(field.multiplicity.len());
+        for multiplicity in &field.multiplicity {
+            let len = match *multiplicity {
+                Multiplicity::Constant(len) => len,
+                Multiplicity::Variable(ref len_name) => {
+                    let len = fields
+                        .iter()
+                        .find(|&&(ref name, _)| name == len_name)
+                        .and_then(|&(_, ref value)| value.as_length())
+                        .ok_or_else(|| Error::InvalidLengthField(len_name.clone()))?;
+                    if len < 0 {
+                        return Err(DecodeError::InvalidSize(len).into());
+                    }
+                    len as usize
+                }
+            };
+            dims.push(len);
+        }
+
+        let value = decode_array(module, path, &field.ty, &dims, buffer)?;
+        fields.push((field.name.clone(), value));
+    }
+
+    Ok(DynMessage {
+        name: s.name.clone(),
+        fields,
+    })
+}
+
+/// Decodes a value with the given dimensions.
+fn decode_array(
+    module: &Module,
+    path: &[Namespace],
+    ty: &Type,
+    dims: &[usize],
+    buffer: &mut Read,
+) -> Result<Value, Error> {
+    match dims.split_first() {
+        None => decode_value(module, path, ty, buffer),
+        Some((&len, rest)) => {
+            // Until fallable allocation is stable, we can't use
+            // Vec::with_capacity because an invalid input could cause a
+            // panic.
+            let mut values = Vec::new();
+            for _ in 0..len {
+                values.push(decode_array(module, path, ty, rest, buffer)?);
+            }
+            Ok(Value::Array(values))
+        }
+    }
+}
+
+/// Decodes a single value of the given type.
+fn decode_value(
+    module: &Module,
+    path: &[Namespace],
+    ty: &Type,
+    buffer: &mut Read,
+) -> Result<Value, Error> {
+    let value = match *ty {
+        Type::Int8 => Value::Int8(Marshall::decode(buffer)?),
+        Type::Int16 => Value::Int16(Marshall::decode(buffer)?),
+        Type::Int32 => Value::Int32(Marshall::decode(buffer)?),
+        Type::Int64 => Value::Int64(Marshall::decode(buffer)?),
+        Type::Float => Value::Float(Marshall::decode(buffer)?),
+        Type::Double => Value::Double(Marshall::decode(buffer)?),
+        Type::String => Value::String(Marshall::decode(buffer)?),
+        Type::Boolean => Value::Boolean(Marshall::decode(buffer)?),
+        Type::Byte => Value::Byte(Marshall::decode(buffer)?),
+        Type::Struct(ref namespaces, ref name) => {
+            let path = if namespaces.is_empty() {
+                path
+            } else {
+                namespaces
+            };
+            Value::Struct(decode(module, path, name, buffer)?)
+        }
+    };
+    Ok(value)
+}
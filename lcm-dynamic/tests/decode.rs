@@ -0,0 +1,125 @@
+extern crate lcm;
+extern crate lcm_dynamic;
+extern crate lcm_gen;
+
+use lcm::Marshall;
+use lcm_dynamic::{DynMessage, Value};
+use lcm_gen::{ast, parser};
+
+fn parse_module(data: &str) -> ast::Module {
+    let file = parser::parse_file(data).expect("Failed to parse file.");
+    let mut module = ast::Module::default();
+    for s in file.structs {
+        module.add_struct(&file.namespaces, s);
+    }
+    module
+}
+
+const TEMPERATURE_T: &str = "
+struct temperature_t
+{
+    int64_t   utime;
+    double    degCelsius;
+}
+";
+
+fn temperature_payload() -> Vec<u8> {
+    let mut buffer = Vec::new();
+    1_234_567i64.encode(&mut buffer).unwrap();
+    21.5f64.encode(&mut buffer).unwrap();
+    buffer
+}
+
+#[test]
+fn decode_temperature() {
+    let module = parse_module(TEMPERATURE_T);
+    let payload = temperature_payload();
+
+    let message =
+        lcm_dynamic::decode(&module, &[], "temperature_t", &mut payload.as_slice()).unwrap();
+
+    assert_eq!(
+        message,
+        DynMessage {
+            name: "temperature_t".into(),
+            fields: vec![
+                ("utime".into(), Value::Int64(1_234_567)),
+                ("degCelsius".into(), Value::Double(21.5)),
+            ],
+        }
+    );
+    assert_eq!(message.get("degCelsius"), Some(&Value::Double(21.5)));
+    assert_eq!(message.get("missing"), None);
+}
+
+#[test]
+fn decode_temperature_with_hash() {
+    let module = parse_module(TEMPERATURE_T);
+
+    let mut buffer = Vec::new();
+    0xa07fa3d64cbea6eau64.encode(&mut buffer).unwrap();
+    buffer.extend(temperature_payload());
+    let message =
+        lcm_dynamic::decode_with_hash(&module, &[], "temperature_t", &mut buffer.as_slice())
+            .unwrap();
+    assert_eq!(message.get("utime"), Some(&Value::Int64(1_234_567)));
+
+    buffer[0] ^= 0xFF;
+    assert!(
+        lcm_dynamic::decode_with_hash(&module, &[], "temperature_t", &mut buffer.as_slice())
+            .is_err()
+    );
+}
+
+#[test]
+fn decode_arrays_and_structs() {
+    let module = parse_module(
+        "
+        struct point_t { double x, y; }
+        struct path_t
+        {
+            string name;
+            int16_t npoints;
+            point_t points[npoints];
+            byte flags[2][npoints];
+        }
+        ",
+    );
+
+    let mut buffer = Vec::new();
+    String::from("route").encode(&mut buffer).unwrap();
+    2i16.encode(&mut buffer).unwrap();
+    for &v in &[1.0f64, 2.0, 3.0, 4.0] {
+        v.encode(&mut buffer).unwrap();
+    }
+    buffer.extend(&[1, 2, 3, 4]);
+
+    let message = lcm_dynamic::decode(&module, &[], "path_t", &mut buffer.as_slice()).unwrap();
+
+    let point = |x, y| {
+        Value::Struct(DynMessage {
+            name: "point_t".into(),
+            fields: vec![("x".into(), Value::Double(x)), ("y".into(), Value::Double(y))],
+        })
+    };
+    assert_eq!(message.get("name"), Some(&Value::String("route".into())));
+    assert_eq!(
+        message.get("points"),
+        Some(&Value::Array(vec![point(1.0, 2.0), point(3.0, 4.0)]))
+    );
+    assert_eq!(
+        message.get("flags"),
+        Some(&Value::Array(vec![
+            Value::Array(vec![Value::Byte(1), Value::Byte(2)]),
+            Value::Array(vec![Value::Byte(3), Value::Byte(4)]),
+        ]))
+    );
+}
+
+#[test]
+fn decode_truncated() {
+    let module = parse_module(TEMPERATURE_T);
+    let payload = temperature_payload();
+
+    assert!(lcm_dynamic::decode(&module, &[], "temperature_t", &mut &payload[..12]).is_err());
+}
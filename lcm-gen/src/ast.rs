@@ -2,12 +2,24 @@ use std::collections::HashMap;
 
 #[derive(Default)]
 pub struct Module {
+    /// The comments describing this module, taken from the files
+    /// that declare its package.
+    pub comments: Vec<Comment>,
+    /// The version of the schemas in this module, from their
+    /// `@version("...")` annotations.
+    pub version: Option<String>,
     pub submodules: HashMap<Namespace, Module>,
     pub structs: Vec<Struct>,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct File {
+    /// The comment before the package declaration, which describes
+    /// the file as a whole.
+    pub comment: Option<Comment>,
+    /// The version from a `@version("...")` line in a comment outside
+    /// of the structs.
+    pub version: Option<String>,
     pub namespaces: Vec<Namespace>,
     pub structs: Vec<Struct>,
 }
@@ -29,6 +41,9 @@ pub struct Field {
     pub name: String,
     pub ty: Type,
     pub multiplicity: Vec<Multiplicity>,
+    /// The name to use for the field in JSON, from a `@json("name")`
+    /// line in its comment.
+    pub json_name: Option<String>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -82,6 +97,52 @@ impl Module {
             }
         }
     }
+
+    /// Add a comment to either this module or the appropriate
+    /// submodule.
+    pub fn add_comment(&mut self, path: &[Namespace], comment: Comment) {
+        match path.first() {
+            None => {
+                self.comments.push(comment);
+            }
+            Some(namespace) => {
+                self.submodules
+                    .entry(namespace.clone())
+                    .or_insert_with(Default::default)
+                    .add_comment(&path[1..], comment);
+            }
+        }
+    }
+
+    /// Set the schema version of either this module or the appropriate
+    /// submodule.
+    ///
+    /// If a different version was already set, it is returned as an
+    /// error and nothing changes.
+    pub fn set_version(&mut self, path: &[Namespace], version: String) -> Result<(), String> {
+        match path.first() {
+            None => match self.version {
+                Some(ref existing) if *existing != version => Err(existing.clone()),
+                _ => {
+                    self.version = Some(version);
+                    Ok(())
+                }
+            },
+            Some(namespace) => self.submodules
+                .entry(namespace.clone())
+                .or_insert_with(Default::default)
+                .set_version(&path[1..], version),
+        }
+    }
+
+    /// Find a struct in either this module or the appropriate
+    /// submodule.
+    pub fn get_struct(&self, path: &[Namespace], name: &str) -> Option<&Struct> {
+        match path.first() {
+            None => self.structs.iter().find(|s| s.name == name),
+            Some(namespace) => self.submodules.get(namespace)?.get_struct(&path[1..], name),
+        }
+    }
 }
 
 impl File {
@@ -94,6 +155,8 @@ impl File {
 #[test]
 fn add_package_prefix() {
     let mut file = File {
+        comment: None,
+        version: None,
         namespaces: vec![Namespace("ns".into())],
         structs: vec![],
     };
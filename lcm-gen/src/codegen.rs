@@ -1,7 +1,8 @@
 use Config;
 use ast;
+use hash;
 use itertools::Itertools;
-use std::fmt::{self, Display, Formatter};
+use std::fmt::{self, Display, Formatter, Write};
 
 pub fn generate(module: &ast::Module) -> String {
     generate_with_config(module, &Config::default())
@@ -9,29 +10,49 @@ pub fn generate(module: &ast::Module) -> String {
 
 pub fn generate_with_config(module: &ast::Module, config: &Config) -> String {
     let mut buffer = String::new();
+    write_with_config(module, config, &mut buffer).expect("Writing to a String can't fail");
+    buffer
+}
+
+/// Generate the code for a module, writing it as it is generated.
+///
+/// If writing fails, no more code is generated.
+pub fn write_with_config(module: &ast::Module, config: &Config, out: &mut Write) -> fmt::Result {
+    let mut failed = false;
     {
-        let mut generator = CodeGenerator::new(&mut buffer, config);
+        let mut generator = CodeGenerator::new(out, &mut failed, config);
         generator.generate_module(module);
+        if !config.channels.is_empty() {
+            generator.generate_channels(&config.channels);
+        }
+    }
+    if failed {
+        Err(fmt::Error)
+    } else {
+        Ok(())
     }
-    buffer
 }
 
-/// A wrapper around a String that keeps track of indentation.
+/// A wrapper around the output that keeps track of indentation.
 ///
 /// To increase indentation, create a new instance of this type using
 /// the `indent` method. To decrease indentation, let that instance go
 /// out of scope.
 struct CodeGenerator<'a> {
-    buffer: &'a mut String,
+    buffer: &'a mut Write,
+    /// Set once writing to the buffer fails, after which nothing more
+    /// is written.
+    failed: &'a mut bool,
     indent: usize,
     start: bool,
     config: &'a Config,
 }
 
 impl<'a> CodeGenerator<'a> {
-    fn new(buffer: &'a mut String, config: &'a Config) -> CodeGenerator<'a> {
+    fn new(buffer: &'a mut Write, failed: &'a mut bool, config: &'a Config) -> CodeGenerator<'a> {
         CodeGenerator {
             buffer,
+            failed,
             indent: 0,
             start: true,
             config,
@@ -46,6 +67,7 @@ impl<'a> CodeGenerator<'a> {
     fn indent(&mut self) -> CodeGenerator {
         CodeGenerator {
             buffer: self.buffer,
+            failed: self.failed,
             indent: self.indent + 1,
             start: true,
             config: self.config,
@@ -55,24 +77,41 @@ impl<'a> CodeGenerator<'a> {
     /// Add a string without adding a newline.
     fn push(&mut self, s: &str) {
         if self.start {
+            let config = self.config;
             for _ in 0..self.indent {
-                self.buffer.push_str("    ");
+                self.write(&config.indent);
             }
             self.start = false;
         }
-        self.buffer.push_str(s);
+        self.write(s);
     }
 
     /// Add a string including a newline.
     fn push_line(&mut self, s: &str) {
         self.push(s);
-        self.buffer.push('\n');
+        self.write("\n");
         self.start = true;
     }
+
+    /// Write to the buffer, unless an earlier write failed.
+    fn write(&mut self, s: &str) {
+        if !*self.failed && self.buffer.write_str(s).is_err() {
+            *self.failed = true;
+        }
+    }
 }
 
 impl<'a> CodeGenerator<'a> {
     fn generate_module(&mut self, module: &ast::Module) {
+        for comment in &module.comments {
+            self.generate_module_comment(comment);
+        }
+        if let Some(ref version) = module.version {
+            self.generate_comment(&ast::Comment(
+                " The version of the schemas in this module.".into(),
+            ));
+            self.push_line(&format!("pub const SCHEMA_VERSION: &str = {:?};", version));
+        }
         for s in &module.structs {
             self.generate_struct(s);
         }
@@ -94,19 +133,276 @@ impl<'a> CodeGenerator<'a> {
         derives.sort();
         let derives = derives.into_iter().join(", ");
         self.push_line(&format!("#[derive({})]", derives));
+        if self.config.non_exhaustive {
+            self.push_line("#[non_exhaustive]");
+        }
         self.push_line(&format!("pub struct {} {{", struct_name));
         for field in &s.fields {
             self.indent().generate_field(field);
         }
         self.push_line("}");
 
-        if !s.constants.is_empty() {
+        if !s.constants.is_empty() || self.config.field_info || self.config.content_hash
+            || self.config.reset || self.uses_builder()
+        {
             self.push_line(&format!("impl {} {{", struct_name));
             for constant in &s.constants {
                 self.indent().generate_constant(constant);
             }
+            if self.config.field_info {
+                self.indent().generate_fields_fn(&s.fields);
+            }
+            if self.config.content_hash {
+                self.indent().generate_content_hash_fn();
+            }
+            if self.config.reset {
+                self.indent().generate_reset_fn(&s.fields);
+            }
+            if self.uses_builder() {
+                self.indent().generate_builder_fn(&struct_name);
+            }
             self.push_line("}");
         }
+
+        if self.uses_builder() {
+            self.generate_builder(&struct_name, &s.fields);
+        }
+
+        if self.config.byte_conversions {
+            self.generate_byte_conversions(&struct_name);
+        }
+    }
+
+    fn generate_byte_conversions(&mut self, struct_name: &str) {
+        self.push_line(&format!(
+            "impl<'a> ::std::convert::TryFrom<&'a {}> for Vec<u8> {{",
+            struct_name
+        ));
+        {
+            let mut indented = self.indent();
+            indented.push_line("type Error = ::lcm::error::EncodeError;");
+            indented.push_line(&format!(
+                "fn try_from(message: &'a {}) -> Result<Self, Self::Error> {{",
+                struct_name
+            ));
+            indented
+                .indent()
+                .push_line("::lcm::Message::encode_with_hash(message)");
+            indented.push_line("}");
+        }
+        self.push_line("}");
+
+        self.push_line(&format!(
+            "impl<'a> ::std::convert::TryFrom<&'a [u8]> for {} {{",
+            struct_name
+        ));
+        {
+            let mut indented = self.indent();
+            indented.push_line("type Error = ::lcm::error::DecodeError;");
+            indented.push_line("fn try_from(mut buffer: &'a [u8]) -> Result<Self, Self::Error> {");
+            {
+                let mut indented = indented.indent();
+                indented.push_line("let message = ::lcm::Message::decode_with_hash(&mut buffer)?;");
+                indented.push_line("if !buffer.is_empty() {");
+                indented.indent().push_line(
+                    "return Err(::lcm::error::DecodeError::TrailingBytes(buffer.len()));",
+                );
+                indented.push_line("}");
+                indented.push_line("Ok(message)");
+            }
+            indented.push_line("}");
+        }
+        self.push_line("}");
+    }
+
+    fn generate_builder_fn(&mut self, struct_name: &str) {
+        self.generate_comment(&ast::Comment(
+            " Starts building a message, with every field unset.".into(),
+        ));
+        self.push_line(&format!("pub fn builder() -> {}Builder {{", struct_name));
+        self.indent()
+            .push_line(&format!("{}Builder::default()", struct_name));
+        self.push_line("}");
+    }
+
+    fn generate_builder(&mut self, struct_name: &str, fields: &[ast::Field]) {
+        // The fields that give the length of an array, along with the
+        // array and the dimension they are the length of.
+        let mut lengths: Vec<(&str, &ast::Field, usize)> = Vec::new();
+        for field in fields {
+            for (depth, mult) in field.multiplicity.iter().enumerate() {
+                if let ast::Multiplicity::Variable(ref len) = *mult {
+                    if !lengths.iter().any(|&(name, _, _)| name == len)
+                        && fields.iter().any(|f| f.name == *len)
+                    {
+                        lengths.push((len, field, depth));
+                    }
+                }
+            }
+        }
+
+        self.generate_comment(&ast::Comment(format!(
+            " Builds a `{}`, filling in any fields that aren't set.",
+            struct_name
+        )));
+        // The lengths always come from the arrays, so they can't be set.
+        let is_length = |field: &ast::Field| lengths.iter().any(|l| l.0 == field.name);
+
+        self.push_line("#[derive(Clone, Debug, Default)]");
+        self.push_line(&format!("pub struct {}Builder {{", struct_name));
+        for field in fields.iter().filter(|f| !is_length(f)) {
+            self.indent().push_line(&format!(
+                "{}: Option<{}>,",
+                field.name,
+                make_field_type(field)
+            ));
+        }
+        self.push_line("}");
+
+        self.push_line(&format!("impl {}Builder {{", struct_name));
+        for field in fields.iter().filter(|f| !is_length(f)) {
+            let mut indented = self.indent();
+            indented.push_line(&format!(
+                "pub fn {0}(mut self, {0}: {1}) -> Self {{",
+                field.name,
+                make_field_type(field)
+            ));
+            {
+                let mut indented = indented.indent();
+                indented.push_line(&format!("self.{0} = Some({0});", field.name));
+                indented.push_line("self");
+            }
+            indented.push_line("}");
+        }
+        {
+            let mut indented = self.indent();
+            indented.generate_comment(&ast::Comment(
+                " Unset fields are zero or empty, and array lengths are taken from the arrays."
+                    .into(),
+            ));
+            indented.push_line(&format!("pub fn build(self) -> {} {{", struct_name));
+            {
+                let mut indented = indented.indent();
+                for field in fields.iter().filter(|f| !is_length(f)) {
+                    let value = if has_default(&field.ty, &field.multiplicity) {
+                        "unwrap_or_default()".to_owned()
+                    } else {
+                        format!(
+                            "unwrap_or_else(|| {})",
+                            make_default(&field.ty, &field.multiplicity)
+                        )
+                    };
+                    indented.push_line(&format!("let {0} = self.{0}.{1};", field.name, value));
+                }
+                for &(name, array, depth) in &lengths {
+                    let ty = &fields
+                        .iter()
+                        .find(|f| f.name == name)
+                        .expect("Only fields are lengths")
+                        .ty;
+                    let len = if depth == 0 {
+                        format!("{}.len()", array.name)
+                    } else {
+                        format!(
+                            "{}.first(){}.map_or(0, |x| x.len())",
+                            array.name,
+                            ".and_then(|x| x.first())".repeat(depth - 1)
+                        )
+                    };
+                    // An array that is too long for its length field makes
+                    // the length as large as possible, so that encoding the
+                    // message fails instead of writing a wrapped length.
+                    indented.push_line(&format!(
+                        "let {0} = ::std::cmp::min({1}, {2}::MAX as usize) as {2};",
+                        name, len, ty
+                    ));
+                }
+                indented.push_line(&format!("{} {{", struct_name));
+                for field in fields {
+                    indented.indent().push_line(&format!("{},", field.name));
+                }
+                indented.push_line("}");
+            }
+            indented.push_line("}");
+        }
+        self.push_line("}");
+    }
+
+    fn generate_reset_fn(&mut self, fields: &[ast::Field]) {
+        self.generate_comment(&ast::Comment(
+            " Sets every field to zero or empty, keeping the memory allocated by strings and vectors."
+                .into(),
+        ));
+        self.push_line("pub fn reset(&mut self) {");
+        for field in fields {
+            let place = format!("self.{}", field.name);
+            self.indent()
+                .generate_reset(&place, &field.ty, &field.multiplicity);
+        }
+        self.push_line("}");
+    }
+
+    /// Resets the value at `place`, which has the dimensions `dims`.
+    /// Array elements are reached through an `item` reference.
+    fn generate_reset(&mut self, place: &str, ty: &ast::Type, dims: &[ast::Multiplicity]) {
+        use ast::Type::*;
+
+        match dims.first() {
+            Some(&ast::Multiplicity::Variable(_)) => {
+                self.push_line(&format!("{}.clear();", place));
+            }
+            Some(&ast::Multiplicity::Constant(_)) => {
+                self.push_line(&format!("for item in {}.iter_mut() {{", place));
+                self.indent().generate_reset("item", ty, &dims[1..]);
+                self.push_line("}");
+            }
+            None => {
+                // Method calls auto-deref, but assignments need the `*`.
+                let target = if place == "item" { "*item" } else { place };
+                match *ty {
+                    Int8 | Int16 | Int32 | Int64 | Byte => {
+                        self.push_line(&format!("{} = 0;", target));
+                    }
+                    Float | Double => self.push_line(&format!("{} = 0.0;", target)),
+                    Boolean => self.push_line(&format!("{} = false;", target)),
+                    String => self.push_line(&format!("{}.clear();", place)),
+                    Struct(..) => self.push_line(&format!("{}.reset();", place)),
+                }
+            }
+        }
+    }
+
+    fn generate_content_hash_fn(&mut self) {
+        self.generate_comment(&ast::Comment(
+            " A hash of the encoded contents of the message, such as for detecting changes.".into(),
+        ));
+        self.push_line("pub fn content_hash(&self) -> Result<u64, ::lcm::error::EncodeError> {");
+        self.indent().push_line("::lcm::content_hash(self)");
+        self.push_line("}");
+    }
+
+    fn generate_fields_fn(&mut self, fields: &[ast::Field]) {
+        self.generate_comment(&ast::Comment(
+            " The name and LCM type of each field, in the order they are encoded.".into(),
+        ));
+        self.push_line("pub fn fields() -> &'static [(&'static str, &'static str)] {");
+        {
+            let mut indented = self.indent();
+            if fields.is_empty() {
+                indented.push_line("&[]");
+            } else {
+                indented.push_line("&[");
+                for field in fields {
+                    indented.indent().push_line(&format!(
+                        "(\"{}\", \"{}\"),",
+                        field.name,
+                        make_lcm_type_name(field)
+                    ));
+                }
+                indented.push_line("]");
+            }
+        }
+        self.push_line("}");
     }
 
     fn generate_field(&mut self, field: &ast::Field) {
@@ -124,44 +420,114 @@ impl<'a> CodeGenerator<'a> {
                 .join(", ");
             self.push_line(&format!("#[lcm({})]", lengths));
         }
-        self.push(&format!("pub {}: ", field.name));
-        for multiplicity in &field.multiplicity {
-            match *multiplicity {
-                ast::Multiplicity::Constant(_) => {
-                    self.push("[");
-                }
-                ast::Multiplicity::Variable(_) => {
-                    self.push("Vec<");
-                }
-            }
-        }
-        self.push(&format!("{}", field.ty));
-        for multiplicity in field.multiplicity.iter().rev() {
-            match *multiplicity {
-                ast::Multiplicity::Constant(len) => {
-                    self.push(&format!("; {}]", len));
-                }
-                ast::Multiplicity::Variable(_) => {
-                    self.push(">");
-                }
+        if let Some(ref json_name) = field.json_name {
+            if self.uses_serde() {
+                self.push_line(&format!("#[serde(rename = {:?})]", json_name));
             }
         }
-        self.push_line(",");
+        self.push_line(&format!("pub {}: {},", field.name, make_field_type(field)));
     }
 
     fn generate_constant(&mut self, constant: &ast::Constant) {
         if let Some(ref comment) = constant.comment {
             self.generate_comment(comment);
         }
+        let value = if self.config.hex_constants && is_bit_pattern(&constant.name) {
+            constant
+                .value
+                .parse::<u64>()
+                .map(|value| format!("{:#x}", value))
+                .unwrap_or_else(|_| constant.value.clone())
+        } else {
+            constant.value.clone()
+        };
         self.push_line(&format!(
             "pub const {}: {} = {};",
-            constant.name, constant.ty, constant.value
+            constant.name, constant.ty, value
         ));
     }
 
+    fn generate_channels(&mut self, channels: &[(String, String)]) {
+        let variants: Vec<_> = channels
+            .iter()
+            .map(|&(ref channel, _)| make_variant_name(channel))
+            .collect();
+
+        self.push_line("#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]");
+        self.push_line("pub enum Channel {");
+        for (variant, &(_, ref type_name)) in variants.iter().zip(channels) {
+            let mut indented = self.indent();
+            indented.generate_comment(&ast::Comment(format!(
+                " Carries `{}` messages.",
+                make_type(type_name)
+            )));
+            indented.push_line(&format!("{},", variant));
+        }
+        self.push_line("}");
+
+        self.push_line("impl Channel {");
+        {
+            let mut indented = self.indent();
+            indented.push_line("pub fn as_str(&self) -> &'static str {");
+            {
+                let mut indented = indented.indent();
+                indented.push_line("match *self {");
+                for (variant, &(ref channel, _)) in variants.iter().zip(channels) {
+                    indented
+                        .indent()
+                        .push_line(&format!("Channel::{} => {:?},", variant, channel));
+                }
+                indented.push_line("}");
+            }
+            indented.push_line("}");
+        }
+        self.push_line("}");
+
+        self.push_line("impl ::std::str::FromStr for Channel {");
+        {
+            let mut indented = self.indent();
+            indented.push_line("type Err = ();");
+            indented.push_line("fn from_str(s: &str) -> Result<Self, ()> {");
+            {
+                let mut indented = indented.indent();
+                indented.push_line("match s {");
+                for (variant, &(ref channel, _)) in variants.iter().zip(channels) {
+                    indented
+                        .indent()
+                        .push_line(&format!("{:?} => Ok(Channel::{}),", channel, variant));
+                }
+                indented.indent().push_line("_ => Err(()),");
+                indented.push_line("}");
+            }
+            indented.push_line("}");
+        }
+        self.push_line("}");
+    }
+
+    /// Whether the structs derive serde's `Serialize` or `Deserialize`,
+    /// which is needed for `#[serde(...)]` attributes to be accepted.
+    /// Structs marked `#[non_exhaustive]` can only be built by other crates
+    /// through their builders, so they always have one.
+    fn uses_builder(&self) -> bool {
+        self.config.builder || self.config.non_exhaustive
+    }
+
+    fn uses_serde(&self) -> bool {
+        self.config.additional_traits.iter().any(|t| {
+            let name = t.rsplit("::").next().unwrap_or(t);
+            name == "Serialize" || name == "Deserialize"
+        })
+    }
+
     fn generate_comment(&mut self, comment: &ast::Comment) {
         self.push_line(&format!("#[doc = r#\"{}\"#]", comment.0));
     }
+
+    fn generate_module_comment(&mut self, comment: &ast::Comment) {
+        for line in comment.0.lines() {
+            self.push_line(&format!("//!{}", line));
+        }
+    }
 }
 
 /// Convert a struct name to Rust naming conventions.
@@ -179,6 +545,120 @@ fn make_struct_name(original: &str) -> String {
     original.to_camel_case()
 }
 
+/// Returns the type of a field as it is written in LCM, including any
+/// array dimensions, such as "double[npoints][2]".
+fn make_lcm_type_name(field: &ast::Field) -> String {
+    let mut name = match field.ty {
+        ast::Type::Struct(ref namespaces, ref struct_name) => namespaces
+            .iter()
+            .map(|ns| ns.0.as_str())
+            .chain(Some(struct_name.as_str()))
+            .join("."),
+        ref ty => hash::primitive_name(ty)
+            .expect("only structs lack a primitive name")
+            .into(),
+    };
+    for multiplicity in &field.multiplicity {
+        match *multiplicity {
+            ast::Multiplicity::Constant(len) => name.push_str(&format!("[{}]", len)),
+            ast::Multiplicity::Variable(ref len) => name.push_str(&format!("[{}]", len)),
+        }
+    }
+    name
+}
+
+/// Convert a channel name to an enum variant name.
+///
+/// Any characters that can't be part of an identifier, such as the
+/// dots in "camera.front", separate words. The result isn't always a
+/// valid identifier, so the channels are checked before generating code.
+pub(crate) fn make_variant_name(channel: &str) -> String {
+    use heck::CamelCase;
+
+    let words: String = channel
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    words.to_camel_case()
+}
+
+/// The Rust type of a field, including its dimensions, such as
+/// `Vec<[f64; 2]>`.
+fn make_field_type(field: &ast::Field) -> String {
+    let mut ty = field.ty.to_string();
+    for multiplicity in field.multiplicity.iter().rev() {
+        ty = match *multiplicity {
+            ast::Multiplicity::Constant(len) => format!("[{}; {}]", ty, len),
+            ast::Multiplicity::Variable(_) => format!("Vec<{}>", ty),
+        };
+    }
+    ty
+}
+
+/// Whether the type with the given dimensions implements `Default`.
+/// Generated structs don't, and neither do arrays of more than 32
+/// elements.
+fn has_default(ty: &ast::Type, dims: &[ast::Multiplicity]) -> bool {
+    match dims.first() {
+        Some(&ast::Multiplicity::Variable(_)) => true,
+        Some(&ast::Multiplicity::Constant(len)) => len <= 32 && has_default(ty, &dims[1..]),
+        None => match *ty {
+            ast::Type::Struct(..) => false,
+            _ => true,
+        },
+    }
+}
+
+/// An expression for the zero or empty value of the type with the given
+/// dimensions. Structs are made by their builders.
+fn make_default(ty: &ast::Type, dims: &[ast::Multiplicity]) -> String {
+    use ast::Type::*;
+
+    match dims.first() {
+        Some(&ast::Multiplicity::Variable(_)) => "Vec::new()".into(),
+        Some(&ast::Multiplicity::Constant(len)) => {
+            let rest = &dims[1..];
+            let item = make_default(ty, rest);
+            let is_copy = match *ty {
+                String | Struct(..) => false,
+                _ => rest.iter().all(|mult| match *mult {
+                    ast::Multiplicity::Constant(_) => true,
+                    ast::Multiplicity::Variable(_) => false,
+                }),
+            };
+            if is_copy {
+                format!("[{}; {}]", item, len)
+            } else {
+                format!("::std::array::from_fn(|_| {})", item)
+            }
+        }
+        None => match *ty {
+            Int8 | Int16 | Int32 | Int64 | Byte => "0".into(),
+            Float | Double => "0.0".into(),
+            Boolean => "false".into(),
+            String => "String::new()".into(),
+            Struct(..) => format!("{}Builder::default().build()", ty),
+        },
+    }
+}
+
+/// Whether a constant's name suggests that it is a mask or a set of
+/// flags, which read better in hexadecimal.
+fn is_bit_pattern(name: &str) -> bool {
+    name.contains("MASK") || name.contains("FLAG")
+}
+
+/// Convert the full name of an LCM type, such as "mit.pose_t", to the
+/// path of the generated Rust type.
+fn make_type(type_name: &str) -> ast::Type {
+    let mut namespaces: Vec<_> = type_name
+        .split('.')
+        .map(|ns| ast::Namespace(ns.into()))
+        .collect();
+    let name = namespaces.pop().expect("split always returns at least one item");
+    ast::Type::Struct(namespaces, name.0)
+}
+
 impl Display for ast::Type {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match *self {
@@ -0,0 +1,161 @@
+//! Calculation of LCM type fingerprints.
+//!
+//! This mirrors the hash calculation done by `lcm-derive`, but works on
+//! the parsed schema instead of on Rust types. This makes it possible
+//! to compute the fingerprint of a type that is only known at runtime.
+
+use ast::{Module, Multiplicity, Namespace, Struct, Type};
+use std::collections::BTreeMap;
+use std::ptr;
+
+/// Computes the fingerprint of the struct `name` in the module at `path`.
+///
+/// The result is the same value as `Message::HASH` for the generated
+/// type. Members that are themselves structs are resolved within
+/// `module`: types without a package are looked up next to the struct
+/// that uses them, and types with a package are looked up from the
+/// root of the module.
+///
+/// Returns `None` if the struct, or any of the types it depends on,
+/// cannot be found.
+pub fn fingerprint(module: &Module, path: &[Namespace], name: &str) -> Option<u64> {
+    fingerprint_recursive(module, path, name, &mut Vec::new())
+}
+
+/// Computes the fingerprint of every struct in `module` and its
+/// submodules.
+///
+/// The structs are keyed by their full names, such as `mit.pose_t`.
+/// Structs whose fingerprints can't be computed are left out.
+pub fn fingerprints(module: &Module) -> BTreeMap<String, u64> {
+    let mut fingerprints = BTreeMap::new();
+    collect_fingerprints(module, module, &mut Vec::new(), &mut fingerprints);
+    fingerprints
+}
+
+fn collect_fingerprints(
+    root: &Module,
+    module: &Module,
+    path: &mut Vec<Namespace>,
+    fingerprints: &mut BTreeMap<String, u64>,
+) {
+    for s in &module.structs {
+        if let Some(hash) = fingerprint(root, path, &s.name) {
+            let name = path.iter()
+                .map(|ns| ns.0.as_str())
+                .chain(Some(s.name.as_str()))
+                .collect::<Vec<_>>()
+                .join(".");
+            fingerprints.insert(name, hash);
+        }
+    }
+
+    for (namespace, submodule) in &module.submodules {
+        path.push(namespace.clone());
+        collect_fingerprints(root, submodule, path, fingerprints);
+        path.pop();
+    }
+}
+
+fn fingerprint_recursive<'a>(
+    module: &'a Module,
+    path: &[Namespace],
+    name: &str,
+    parents: &mut Vec<&'a Struct>,
+) -> Option<u64> {
+    let s = module.get_struct(path, name)?;
+
+    // Like the C version of lcmgen, a type that contains itself does
+    // not contribute to its own hash.
+    if parents.iter().any(|&parent| ptr::eq(parent, s)) {
+        return Some(0);
+    }
+
+    parents.push(s);
+    let mut hash = base_hash(s);
+    for field in &s.fields {
+        if let Type::Struct(ref namespaces, ref type_name) = field.ty {
+            let field_path = if namespaces.is_empty() {
+                path
+            } else {
+                namespaces
+            };
+            hash = hash.wrapping_add(fingerprint_recursive(
+                module,
+                field_path,
+                type_name,
+                parents,
+            )?);
+        }
+    }
+    parents.pop();
+
+    Some((hash << 1).wrapping_add((hash >> 63) & 1))
+}
+
+/// Calculates the hash of a struct using only its own fields.
+///
+/// This does not include the names of the struct or of any of the user
+/// defined types it contains.
+fn base_hash(s: &Struct) -> u64 {
+    /// Make the hash dependent on the value of the given character.
+    ///
+    /// The order that this function is called in *is* important. This
+    /// function was copied from the C version of lcmgen.
+    fn hash_update(v: i64, c: i8) -> i64 {
+        ((v << 8) ^ (v >> 55)).wrapping_add(i64::from(c))
+    }
+
+    /// Make the hash dependent on each character in a string.
+    fn hash_string_update(v: i64, s: &[u8]) -> i64 {
+        s.iter()
+            .fold(hash_update(v, s.len() as i8), |acc, &c| {
+                hash_update(acc, c as i8)
+            })
+    }
+
+    let mut v = 0x12345678i64;
+
+    for field in &s.fields {
+        // Hash the field name
+        v = hash_string_update(v, field.name.as_bytes());
+
+        // Hash the type information *only* if it is a primitive type
+        if let Some(ty) = primitive_name(&field.ty) {
+            v = hash_string_update(v, ty.as_bytes());
+        }
+
+        // Hash the dimension information
+        v = hash_update(v, field.multiplicity.len() as i8);
+        for multiplicity in &field.multiplicity {
+            match *multiplicity {
+                Multiplicity::Constant(len) => {
+                    v = hash_update(v, 0);
+                    v = hash_string_update(v, len.to_string().as_bytes());
+                }
+                Multiplicity::Variable(ref len) => {
+                    v = hash_update(v, 1);
+                    v = hash_string_update(v, len.as_bytes());
+                }
+            }
+        }
+    }
+
+    v as u64
+}
+
+/// Returns the LCM name of a primitive type, or `None` for structs.
+pub(crate) fn primitive_name(ty: &Type) -> Option<&'static str> {
+    match *ty {
+        Type::Int8 => Some("int8_t"),
+        Type::Int16 => Some("int16_t"),
+        Type::Int32 => Some("int32_t"),
+        Type::Int64 => Some("int64_t"),
+        Type::Float => Some("float"),
+        Type::Double => Some("double"),
+        Type::String => Some("string"),
+        Type::Boolean => Some("boolean"),
+        Type::Byte => Some("byte"),
+        Type::Struct(..) => None,
+    }
+}
@@ -27,6 +27,10 @@ struct Options {
                 raw(multiple = "true"), help = "Additional traits to derive.")]
     custom_derives: Vec<String>,
 
+    #[structopt(long = "fingerprints", parse(from_os_str),
+                help = "A JSON file of type fingerprints to check the schemas against.")]
+    fingerprints: Option<PathBuf>,
+
     #[structopt(parse(from_os_str), raw(required = "true"), help = "A list of .lcm files.")]
     input_files: Vec<PathBuf>,
 }
@@ -56,6 +60,8 @@ fn run() -> Result<(), Error> {
         package_prefix: options.package_prefix,
         output_file: Some(options.output_file),
         additional_traits: options.custom_derives,
+        fingerprints: options.fingerprints,
+        ..lcm_gen::Config::default()
     };
     config.generate(&options.input_files)
 }
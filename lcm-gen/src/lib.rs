@@ -5,15 +5,20 @@ extern crate itertools;
 extern crate pest;
 #[macro_use]
 extern crate pest_derive;
+extern crate serde_json;
 
-use failure::{Error, ResultExt};
-use std::fmt::Debug;
-use std::fs::File;
-use std::io::{Read, Write};
+use failure::{Error, Fail, ResultExt};
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::{self, Debug};
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 
 pub mod ast;
 pub mod codegen;
+pub mod hash;
 pub mod parser;
 
 /// Generate Rust types from the given LCM schemas using the default
@@ -39,6 +44,66 @@ pub struct Config {
     pub package_prefix: Option<String>,
     pub output_file: Option<PathBuf>,
     pub additional_traits: Vec<String>,
+    /// Pairs of channel names and the full names of the LCM types
+    /// published on them, such as `("POSE", "mit.pose_t")`.
+    ///
+    /// If this is not empty, a `Channel` enum with a variant for each
+    /// channel is generated in the root module.
+    pub channels: Vec<(String, String)>,
+    /// If true, each struct gets a `fields()` function that returns the
+    /// name and LCM type of each of its fields, such as
+    /// `("points", "double[npoints][2]")`.
+    pub field_info: bool,
+    /// If true, each struct gets a `content_hash()` method that hashes
+    /// its encoded contents with `lcm::content_hash`.
+    pub content_hash: bool,
+    /// If true, each struct gets a `reset()` method that sets its
+    /// fields back to zero, clearing strings and vectors in place so
+    /// that the message can be reused without new allocations.
+    pub reset: bool,
+    /// If true, each struct gets a builder with a setter for each field,
+    /// such as `Point2dList::builder().points(points).build()`. Fields
+    /// that aren't set are zero or empty. The lengths of variable sized
+    /// arrays are always taken from the arrays, so they have no setters.
+    /// Generation fails if a field is named `build` or `default`, which
+    /// would clash with the builder's own methods.
+    pub builder: bool,
+    /// If true, integer constants whose names contain `MASK` or `FLAG`
+    /// are written in hexadecimal, such as `0xff` instead of `255`.
+    /// Negative values are left in decimal.
+    pub hex_constants: bool,
+    /// The string used for each level of indentation in the generated
+    /// code. This is four spaces by default.
+    pub indent: String,
+    /// If true, generation fails when a field refers to a struct that
+    /// is not defined in any of the schemas, instead of producing code
+    /// that refers to a type that doesn't exist.
+    pub strict: bool,
+    /// If true, each struct is marked `#[non_exhaustive]`, so that code
+    /// in other crates can't build it with a struct expression or match
+    /// it exhaustively, and fields can be added without breaking them.
+    /// The builder is then the only way for those crates to make one, so
+    /// this also turns on `builder`.
+    pub non_exhaustive: bool,
+    /// If true, each struct can be converted to and from bytes, with
+    /// `Vec::<u8>::try_from(&message)` and `Struct::try_from(&bytes[..])`.
+    /// The bytes start with the fingerprint, as with `encode_with_hash`.
+    /// Encoding fails if an array's length doesn't match its length
+    /// field, and decoding fails if any bytes are left over.
+    pub byte_conversions: bool,
+    /// A JSON file that records the fingerprint of each type, such as
+    /// `{"mit.pose_t": "0x4f85d1e7da2fc594"}`.
+    ///
+    /// If set, generation fails when the fingerprint of a recorded type
+    /// has changed. This happens whenever its fields change, even if
+    /// they are only reordered, and means that it can no longer talk to
+    /// programs built from the old schemas. Types that aren't recorded
+    /// yet are added to the file, which is created if it doesn't exist.
+    /// Generation also fails when a recorded type is missing from the
+    /// schemas, so the same file can't be shared by different sets of
+    /// schemas. To accept an incompatible change or a removed type,
+    /// remove the type from the file.
+    pub fingerprints: Option<PathBuf>,
 }
 
 impl Default for Config {
@@ -47,6 +112,17 @@ impl Default for Config {
             package_prefix: None,
             output_file: None,
             additional_traits: vec![],
+            channels: vec![],
+            field_info: false,
+            content_hash: false,
+            reset: false,
+            builder: false,
+            hex_constants: false,
+            indent: "    ".into(),
+            strict: false,
+            non_exhaustive: false,
+            byte_conversions: false,
+            fingerprints: None,
         }
     }
 }
@@ -61,15 +137,61 @@ impl Config {
     /// ```ignore
     /// include!(concat!(env!("OUT_DIR"), "/mod.rs"));
     /// ```
+    ///
+    /// A hash of the schemas and the configuration is saved next to
+    /// the output, in a file named after it with a `.lcm-gen-cache`
+    /// suffix, such as `.mod.rs.lcm-gen-cache`. If nothing has changed
+    /// since the output was written, it is left untouched.
     pub fn generate<P: AsRef<Path> + Debug>(&mut self, lcm_files: &[P]) -> Result<(), Error> {
-        let output = self.generate_string(lcm_files)?;
-
-        let output_file = self.output_file
+        let output_path = self.output_file
             .clone()
             .unwrap_or_else(|| PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("mod.rs"));
-        let mut output_file =
-            File::create(&output_file).context(format_err!("Opening {:?}", output_file))?;
-        write!(output_file, "{}", output).context("Writing output")?;
+        let cache_path = cache_path(&output_path);
+
+        let hash = format!("{:016x}\n", self.input_hash(lcm_files)?);
+        if output_path.exists() && fs::read_to_string(&cache_path).ok().as_ref() == Some(&hash) {
+            return Ok(());
+        }
+
+        // Remove the old hash first, so that it can't match the output
+        // if generation fails partway through.
+        if cache_path.exists() {
+            fs::remove_file(&cache_path).context(format_err!("Removing {:?}", cache_path))?;
+        }
+
+        // Generate everything before touching the output, so that the old
+        // code is left in place if anything goes wrong.
+        let mut output = Vec::new();
+        self.generate_to(lcm_files, &mut output)?;
+        fs::write(&output_path, output).context(format_err!("Writing {:?}", output_path))?;
+
+        fs::write(&cache_path, hash).context(format_err!("Writing {:?}", cache_path))?;
+
+        Ok(())
+    }
+
+    /// Generate Rust types from the given LCM schemas and write the
+    /// results to `writer`.
+    ///
+    /// The code is written as it is generated, rather than being
+    /// collected in memory first. The schemas are all parsed before
+    /// anything is written, so nothing is written if any of them are
+    /// invalid.
+    pub fn generate_to<P, W>(&mut self, lcm_files: &[P], writer: W) -> Result<(), Error>
+    where
+        P: AsRef<Path> + Debug,
+        W: Write,
+    {
+        let module = self.parse_files(lcm_files)?;
+
+        let mut writer = IoWriter {
+            inner: writer,
+            error: None,
+        };
+        if codegen::write_with_config(&module, self, &mut writer).is_err() {
+            let error = writer.error.take().expect("Only writing to the output can fail");
+            return Err(error.context("Writing output").into());
+        }
 
         Ok(())
     }
@@ -85,6 +207,41 @@ impl Config {
         &mut self,
         lcm_files: &[P],
     ) -> Result<String, Error> {
+        let module = self.parse_files(lcm_files)?;
+        Ok(codegen::generate_with_config(&module, self))
+    }
+
+    /// Hash the contents of the given LCM schemas along with everything
+    /// that affects the generated code.
+    fn input_hash<P: AsRef<Path> + Debug>(&self, lcm_files: &[P]) -> Result<u64, Error> {
+        let mut hasher = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        self.package_prefix.hash(&mut hasher);
+        self.additional_traits.hash(&mut hasher);
+        self.channels.hash(&mut hasher);
+        self.field_info.hash(&mut hasher);
+        self.content_hash.hash(&mut hasher);
+        self.reset.hash(&mut hasher);
+        self.builder.hash(&mut hasher);
+        self.hex_constants.hash(&mut hasher);
+        self.indent.hash(&mut hasher);
+        self.strict.hash(&mut hasher);
+        self.non_exhaustive.hash(&mut hasher);
+        self.byte_conversions.hash(&mut hasher);
+        if let Some(ref path) = self.fingerprints {
+            path.hash(&mut hasher);
+            fs::read(path).ok().hash(&mut hasher);
+        }
+        for path in lcm_files {
+            let contents = fs::read(path).context(format_err!("Opening file {:?}", path))?;
+            path.as_ref().hash(&mut hasher);
+            contents.hash(&mut hasher);
+        }
+        Ok(hasher.finish())
+    }
+
+    /// Parse the given LCM schemas into a single module tree.
+    fn parse_files<P: AsRef<Path> + Debug>(&self, lcm_files: &[P]) -> Result<ast::Module, Error> {
         let mut root_module = ast::Module::default();
 
         for path in lcm_files {
@@ -99,11 +256,226 @@ impl Config {
                 lcm_file.add_package_prefix(prefix);
             }
 
+            if let Some(comment) = lcm_file.comment {
+                root_module.add_comment(&lcm_file.namespaces, comment);
+            }
+            if let Some(version) = lcm_file.version {
+                root_module
+                    .set_version(&lcm_file.namespaces, version.clone())
+                    .map_err(|existing| {
+                        format_err!(
+                            "Schema version \"{}\" in {:?} conflicts with \"{}\" from another file",
+                            version,
+                            path,
+                            existing
+                        )
+                    })?;
+            }
             for s in lcm_file.structs {
+                if self.builder || self.non_exhaustive {
+                    let clash = s.fields
+                        .iter()
+                        .find(|field| field.name == "build" || field.name == "default");
+                    if let Some(field) = clash {
+                        bail!(
+                            "The field {:?} of {:?} in {:?} clashes with a method of its builder",
+                            field.name,
+                            s.name,
+                            path
+                        );
+                    }
+                }
                 root_module.add_struct(&lcm_file.namespaces, s);
             }
         }
 
-        Ok(codegen::generate_with_config(&root_module, self))
+        for &(ref channel, ref type_name) in &self.channels {
+            let mut path: Vec<_> = type_name
+                .split('.')
+                .map(|ns| ast::Namespace(ns.into()))
+                .collect();
+            let name = path.pop().expect("split always returns at least one item");
+            if root_module.get_struct(&path, &name.0).is_none() {
+                bail!("Unknown type {:?} for channel {:?}", type_name, channel);
+            }
+        }
+
+        let mut variants = BTreeMap::new();
+        for &(ref channel, _) in &self.channels {
+            let variant = codegen::make_variant_name(channel);
+            match variant.chars().next() {
+                Some(c) if c.is_alphabetic() && variant != "Self" => {}
+                _ => bail!(
+                    "Channel {:?} can't be turned into an enum variant (got {:?})",
+                    channel,
+                    variant
+                ),
+            }
+            if let Some(other) = variants.insert(variant.clone(), channel) {
+                bail!(
+                    "Channels {:?} and {:?} both become the enum variant {}",
+                    other,
+                    channel,
+                    variant
+                );
+            }
+        }
+
+        if self.strict {
+            let mut unknown = Vec::new();
+            find_unknown_types(&root_module, &root_module, &mut Vec::new(), &mut unknown);
+            if !unknown.is_empty() {
+                unknown.sort();
+                bail!("Unknown types in the schemas:\n{}", unknown.join("\n"));
+            }
+        }
+
+        if let Some(ref path) = self.fingerprints {
+            check_fingerprints(path, &root_module)?;
+        }
+
+        Ok(root_module)
+    }
+}
+
+/// Find the fields in `module`, and in its submodules, whose struct
+/// types are not defined anywhere in `root`.
+///
+/// Like the fingerprint calculation, types without a package are looked
+/// up next to the struct that uses them, and types with a package are
+/// looked up from the root.
+fn find_unknown_types(
+    root: &ast::Module,
+    module: &ast::Module,
+    path: &mut Vec<ast::Namespace>,
+    unknown: &mut Vec<String>,
+) {
+    let full_name = |path: &[ast::Namespace], name: &str| {
+        path.iter()
+            .map(|ns| ns.0.as_str())
+            .chain(Some(name))
+            .collect::<Vec<_>>()
+            .join(".")
+    };
+
+    for s in &module.structs {
+        for field in &s.fields {
+            if let ast::Type::Struct(ref namespaces, ref type_name) = field.ty {
+                let type_path = if namespaces.is_empty() {
+                    &path[..]
+                } else {
+                    &namespaces[..]
+                };
+                if root.get_struct(type_path, type_name).is_none() {
+                    unknown.push(format!(
+                        "Unknown type {:?} for field {:?} of {:?}",
+                        full_name(type_path, type_name),
+                        field.name,
+                        full_name(path, &s.name)
+                    ));
+                }
+            }
+        }
+    }
+
+    for (namespace, submodule) in &module.submodules {
+        path.push(namespace.clone());
+        find_unknown_types(root, submodule, path, unknown);
+        path.pop();
+    }
+}
+
+/// Compare the fingerprints of the types in `module` with those
+/// recorded in the JSON file at `path`, recording any new types.
+fn check_fingerprints(path: &Path, module: &ast::Module) -> Result<(), Error> {
+    let recorded: BTreeMap<String, String> = if path.exists() {
+        let contents = fs::read_to_string(path).context(format_err!("Opening file {:?}", path))?;
+        serde_json::from_str(&contents).context(format_err!("Parsing file {:?}", path))?
+    } else {
+        BTreeMap::new()
+    };
+    let mut fingerprints = BTreeMap::new();
+    for (name, hash) in recorded {
+        let value = u64::from_str_radix(hash.trim_start_matches("0x"), 16)
+            .map_err(|_| format_err!("Invalid fingerprint {:?} for {:?} in {:?}", hash, name, path))?;
+        fingerprints.insert(name, value);
+    }
+
+    let current = hash::fingerprints(module);
+    let missing: Vec<_> = fingerprints
+        .keys()
+        .filter(|name| !current.contains_key(*name))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "Types recorded in {:?} are missing from the schemas:\n{}",
+            path,
+            missing.join("\n")
+        );
+    }
+
+    let mut changed = Vec::new();
+    let mut added = false;
+    for (name, hash) in current {
+        match fingerprints.entry(name) {
+            Entry::Occupied(entry) => if *entry.get() != hash {
+                changed.push(format!(
+                    "{} changed from {:#018x} to {:#018x}",
+                    entry.key(),
+                    entry.get(),
+                    hash
+                ));
+            },
+            Entry::Vacant(entry) => {
+                entry.insert(hash);
+                added = true;
+            }
+        }
+    }
+    if !changed.is_empty() {
+        bail!(
+            "Fingerprints differ from those recorded in {:?}:\n{}",
+            path,
+            changed.join("\n")
+        );
+    }
+
+    if added {
+        let fingerprints: BTreeMap<_, _> = fingerprints
+            .into_iter()
+            .map(|(name, hash)| (name, format!("{:#018x}", hash)))
+            .collect();
+        let json = serde_json::to_string_pretty(&fingerprints)? + "\n";
+        fs::write(path, json).context(format_err!("Writing {:?}", path))?;
+    }
+    Ok(())
+}
+
+/// Returns the path of the file that records what the output at
+/// `output_path` was generated from.
+fn cache_path(output_path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(output_path.file_name().unwrap_or_default());
+    name.push(".lcm-gen-cache");
+    output_path.with_file_name(name)
+}
+
+/// Adapts an `io::Write` for the code generator, which writes with
+/// `fmt::Write`.
+///
+/// Formatting errors can't carry any details, so the first I/O error
+/// is kept here instead.
+struct IoWriter<W> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> fmt::Write for IoWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
     }
 }
@@ -20,6 +20,8 @@ pub fn parse_file(input: &str) -> Result<ast::File, Error> {
         .expect("Exactly one file should have been parsed")
         .into_inner();
 
+    let mut comment = None;
+    let mut version = None;
     let mut namespaces = Vec::new();
     let mut structs = Vec::new();
     let mut last_comment = None;
@@ -28,21 +30,30 @@ pub fn parse_file(input: &str) -> Result<ast::File, Error> {
         match pair.as_rule() {
             Rule::lcm_package => {
                 namespaces = pair.into_inner().map(|p| parse_namespace(&p)).collect();
-                // Any comments before the package line should not be
-                // associated with the next struct.
-                last_comment = None;
+                // Any comments before the package line describe the
+                // file, and should not be associated with the next
+                // struct.
+                comment = last_comment.take();
             }
             Rule::lcm_struct => {
-                structs.push(parse_struct(last_comment.take(), pair));
+                structs.push(parse_struct(last_comment.take(), pair)?);
             }
             Rule::comment => {
-                last_comment = Some(parse_comment(pair));
+                // A version annotation anywhere outside of a struct
+                // applies to the whole file.
+                let (rest, annotation) = take_annotation(&parse_comment(pair), "version");
+                if annotation.is_some() {
+                    version = annotation;
+                }
+                last_comment = rest;
             }
             _ => unreachable!(),
         }
     }
 
     Ok(ast::File {
+        comment,
+        version,
         namespaces,
         structs,
     })
@@ -52,7 +63,7 @@ fn parse_namespace(pair: &Pair<Rule>) -> ast::Namespace {
     ast::Namespace(pair.as_str().into())
 }
 
-fn parse_struct(comment: Option<ast::Comment>, pair: Pair<Rule>) -> ast::Struct {
+fn parse_struct(comment: Option<ast::Comment>, pair: Pair<Rule>) -> Result<ast::Struct, Error> {
     let mut pairs = pair.into_inner();
     let name = match pairs.next() {
         Some(ref pair) if pair.as_rule() == Rule::struct_name => pair.as_str().into(),
@@ -66,13 +77,13 @@ fn parse_struct(comment: Option<ast::Comment>, pair: Pair<Rule>) -> ast::Struct
     for pair in pairs {
         match pair.as_rule() {
             Rule::member_group => {
-                fields.extend(parse_fields(&last_comment.take(), pair));
+                fields.extend(parse_fields(&last_comment.take(), pair)?);
             }
             Rule::constant_group => {
                 let mut pairs = pair.into_inner();
                 let ty = parse_type(pairs.next().expect("Guaranteed by grammar"));
                 for pair in pairs {
-                    constants.push(parse_constant(last_comment.take(), ty.clone(), pair));
+                    constants.push(parse_constant(last_comment.take(), ty.clone(), pair)?);
                 }
             }
             Rule::comment => {
@@ -82,19 +93,24 @@ fn parse_struct(comment: Option<ast::Comment>, pair: Pair<Rule>) -> ast::Struct
         }
     }
 
-    ast::Struct {
+    Ok(ast::Struct {
         comment,
         name,
         fields,
         constants,
-    }
+    })
 }
 
-fn parse_fields(comment: &Option<ast::Comment>, pair: Pair<Rule>) -> Vec<ast::Field> {
+fn parse_fields(comment: &Option<ast::Comment>, pair: Pair<Rule>) -> Result<Vec<ast::Field>, Error> {
     let mut pairs = pair.into_inner();
     let ty = parse_type(pairs.next().expect("Guaranteed by grammar"));
 
-    pairs
+    let (comment, json_name) = match *comment {
+        Some(ref comment) => take_annotation(comment, "json"),
+        None => (None, None),
+    };
+
+    let fields: Vec<_> = pairs
         .map(|pair| {
             let mut pairs = pair.into_inner();
             let name = parse_name(&pairs.next().expect("Guaranteed by grammar"));
@@ -105,21 +121,85 @@ fn parse_fields(comment: &Option<ast::Comment>, pair: Pair<Rule>) -> Vec<ast::Fi
                 name,
                 ty: ty.clone(),
                 multiplicity,
+                json_name: json_name.clone(),
+            }
+        })
+        .collect();
+
+    // Each field needs its own JSON name, so the annotation can only be
+    // used on a declaration of a single field.
+    if json_name.is_some() && fields.len() > 1 {
+        let names: Vec<_> = fields.iter().map(|field| field.name.as_str()).collect();
+        bail!(
+            "The @json annotation can't be used on the fields {}, which are declared together",
+            names.join(", ")
+        );
+    }
+    Ok(fields)
+}
+
+/// Splits an annotation such as `@json("name")` out of a comment,
+/// returning the rest of the comment and the annotation's value.
+///
+/// The annotation must be on a line of its own. It is removed from the
+/// comment, which is dropped entirely if nothing else is left.
+fn take_annotation(comment: &ast::Comment, name: &str) -> (Option<ast::Comment>, Option<String>) {
+    let prefix = format!("@{}(\"", name);
+    let mut value = None;
+    let lines: Vec<&str> = comment
+        .0
+        .lines()
+        .filter(|line| {
+            let line = line.trim();
+            if line.starts_with(&prefix) && line.ends_with("\")") {
+                value = Some(line[prefix.len()..line.len() - 2].to_owned());
+                false
+            } else {
+                true
             }
         })
-        .collect()
+        .collect();
+
+    if value.is_none() {
+        (Some(comment.clone()), None)
+    } else if lines.iter().all(|line| line.trim().is_empty()) {
+        (None, value)
+    } else {
+        (Some(ast::Comment(lines.join("\n"))), value)
+    }
 }
 
-fn parse_constant(comment: Option<ast::Comment>, ty: ast::Type, pair: Pair<Rule>) -> ast::Constant {
+fn parse_constant(
+    comment: Option<ast::Comment>,
+    ty: ast::Type,
+    pair: Pair<Rule>,
+) -> Result<ast::Constant, Error> {
     let mut pairs = pair.into_inner();
     let name = parse_name(&pairs.next().expect("Guaranteed by grammar"));
-    let value = parse_value(&pairs.next().expect("Guaranteed by grammar"));
-    ast::Constant {
+    let value_pair = pairs.next().expect("Guaranteed by grammar");
+    let value = parse_value(&value_pair);
+
+    let is_boolean_literal = value_pair
+        .into_inner()
+        .next()
+        .map(|pair| pair.as_rule()) == Some(Rule::boolean_literal);
+    if is_boolean_literal && ty != ast::Type::Boolean {
+        bail!(
+            "The constant {} is {}, but only boolean constants can be true or false",
+            name,
+            value
+        );
+    }
+    if !is_boolean_literal && ty == ast::Type::Boolean {
+        bail!("The boolean constant {} must be true or false, not {}", name, value);
+    }
+
+    Ok(ast::Constant {
         comment,
         name,
         ty,
         value,
-    }
+    })
 }
 
 fn parse_type(pair: Pair<Rule>) -> ast::Type {
@@ -166,6 +246,8 @@ fn parse_multiplicity(pair: Pair<Rule>) -> ast::Multiplicity {
     }
 }
 
+/// The literals are kept as they were written, which is also valid
+/// Rust. This includes `true` and `false` for boolean constants.
 fn parse_value(pair: &Pair<Rule>) -> String {
     pair.as_str().into()
 }
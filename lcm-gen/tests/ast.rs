@@ -12,6 +12,8 @@ fn parse_temperature() {
     assert_eq!(
         file,
         ast::File {
+            comment: None,
+            version: None,
             namespaces: vec![],
             structs: vec![
                 ast::Struct {
@@ -23,6 +25,7 @@ fn parse_temperature() {
                             name: "utime".into(),
                             ty: ast::Type::Int64,
                             multiplicity: vec![],
+                            json_name: None,
                         },
                         ast::Field {
                             comment: Some(ast::Comment(
@@ -35,6 +38,7 @@ fn parse_temperature() {
                             name: "degCelsius".into(),
                             ty: ast::Type::Double,
                             multiplicity: vec![],
+                            json_name: None,
                         },
                     ],
                     constants: vec![],
@@ -52,6 +56,8 @@ fn parse_multiple_structs() {
     assert_eq!(
         file,
         ast::File {
+            comment: None,
+            version: None,
             namespaces: vec![],
             structs: vec![
                 ast::Struct {
@@ -63,12 +69,14 @@ fn parse_multiple_structs() {
                             name: "b".into(),
                             ty: ast::Type::Struct(vec![], "B".into()),
                             multiplicity: vec![],
+                            json_name: None,
                         },
                         ast::Field {
                             comment: None,
                             name: "c".into(),
                             ty: ast::Type::Struct(vec![], "C".into()),
                             multiplicity: vec![],
+                            json_name: None,
                         },
                     ],
                     constants: vec![],
@@ -82,6 +90,7 @@ fn parse_multiple_structs() {
                             name: "a".into(),
                             ty: ast::Type::Struct(vec![], "A".into()),
                             multiplicity: vec![],
+                            json_name: None,
                         },
                     ],
                     constants: vec![],
@@ -95,6 +104,7 @@ fn parse_multiple_structs() {
                             name: "b".into(),
                             ty: ast::Type::Struct(vec![], "B".into()),
                             multiplicity: vec![],
+                            json_name: None,
                         },
                     ],
                     constants: vec![],
@@ -112,6 +122,8 @@ fn parse_point2d_list() {
     assert_eq!(
         file,
         ast::File {
+            comment: None,
+            version: None,
             namespaces: vec![],
             structs: vec![
                 ast::Struct {
@@ -123,6 +135,7 @@ fn parse_point2d_list() {
                             name: "npoints".into(),
                             ty: ast::Type::Int32,
                             multiplicity: vec![],
+                            json_name: None,
                         },
                         ast::Field {
                             comment: None,
@@ -132,6 +145,7 @@ fn parse_point2d_list() {
                                 ast::Multiplicity::Variable("npoints".into()),
                                 ast::Multiplicity::Constant(2),
                             ],
+                            json_name: None,
                         },
                     ],
                     constants: vec![],
@@ -149,6 +163,8 @@ fn parse_camera_image() {
     assert_eq!(
         file,
         ast::File {
+            comment: None,
+            version: None,
             namespaces: vec![ast::Namespace("mycorp".into())],
             structs: vec![
                 ast::Struct {
@@ -160,12 +176,14 @@ fn parse_camera_image() {
                             name: "utime".into(),
                             ty: ast::Type::Int64,
                             multiplicity: vec![],
+                            json_name: None,
                         },
                         ast::Field {
                             comment: None,
                             name: "camera_name".into(),
                             ty: ast::Type::String,
                             multiplicity: vec![],
+                            json_name: None,
                         },
                         ast::Field {
                             comment: None,
@@ -175,6 +193,7 @@ fn parse_camera_image() {
                                 "image_t".into(),
                             ),
                             multiplicity: vec![],
+                            json_name: None,
                         },
                         ast::Field {
                             comment: None,
@@ -184,6 +203,7 @@ fn parse_camera_image() {
                                 "pose_t".into(),
                             ),
                             multiplicity: vec![],
+                            json_name: None,
                         },
                     ],
                     constants: vec![],
@@ -201,6 +221,8 @@ fn parse_my_constants() {
     assert_eq!(
         file,
         ast::File {
+            comment: None,
+            version: None,
             namespaces: vec![],
             structs: vec![
                 ast::Struct {
@@ -247,6 +269,8 @@ fn parse_struct_with_comments() {
     assert_eq!(
         file,
         ast::File {
+            comment: None,
+            version: None,
             namespaces: vec![],
             structs: vec![
                 ast::Struct {
@@ -261,12 +285,14 @@ fn parse_struct_with_comments() {
                             name: "x".into(),
                             ty: ast::Type::Int32,
                             multiplicity: vec![],
+                            json_name: None,
                         },
                         ast::Field {
                             comment: Some(ast::Comment(" Vertical position in meters.".into())),
                             name: "y".into(),
                             ty: ast::Type::Int32,
                             multiplicity: vec![],
+                            json_name: None,
                         },
                     ],
                     constants: vec![],
@@ -310,6 +336,8 @@ struct foo_t {
     assert_eq!(
         file,
         ast::File {
+            comment: Some(ast::Comment(" A package".into())),
+            version: None,
             namespaces: vec![ast::Namespace("exlcm".into())],
             structs: vec![
                 ast::Struct {
@@ -322,3 +350,52 @@ struct foo_t {
         }
     );
 }
+
+#[test]
+fn file_comments() {
+    let data = include_str!("data/file_comments.lcm");
+    let file = parser::parse_file(data).expect("Failed to parse file.");
+
+    assert_eq!(
+        file,
+        ast::File {
+            comment: Some(ast::Comment(
+                " Messages for the exlcm example.\n It has a comment before the package.".into(),
+            )),
+            version: None,
+            namespaces: vec![ast::Namespace("exlcm".into())],
+            structs: vec![
+                ast::Struct {
+                    comment: Some(ast::Comment(" The first struct.".into())),
+                    name: "first_t".into(),
+                    fields: vec![],
+                    constants: vec![],
+                },
+                ast::Struct {
+                    comment: None,
+                    name: "second_t".into(),
+                    fields: vec![],
+                    constants: vec![],
+                },
+            ],
+        }
+    );
+}
+
+#[test]
+fn json_name_on_grouped_fields() {
+    let data = "struct point_t {\n    // @json(\"position\")\n    double x, y;\n}\n";
+    let error = parser::parse_file(data).unwrap_err().to_string();
+    assert!(error.contains("x, y"), "{}", error);
+}
+
+#[test]
+fn mismatched_boolean_constants() {
+    for data in &[
+        "struct flags_t { const int32_t ENABLED = true; }",
+        "struct flags_t { const boolean ENABLED = 1; }",
+    ] {
+        let error = parser::parse_file(data).unwrap_err().to_string();
+        assert!(error.contains("ENABLED"), "{}", error);
+    }
+}
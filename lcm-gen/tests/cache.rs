@@ -0,0 +1,77 @@
+extern crate lcm_gen;
+extern crate tempdir;
+
+use lcm_gen::Config;
+use std::fs;
+use tempdir::TempDir;
+
+#[test]
+fn unchanged_inputs_are_not_regenerated() {
+    let dir = TempDir::new("lcm-gen").unwrap();
+    let output = dir.path().join("mod.rs");
+    let cache = dir.path().join(".mod.rs.lcm-gen-cache");
+    let input = dir.path().join("temperature_t.lcm");
+    fs::copy("tests/data/temperature_t.lcm", &input).unwrap();
+
+    let mut config = Config {
+        output_file: Some(output.clone()),
+        ..Config::default()
+    };
+    config.generate(&[&input]).unwrap();
+    let hash = fs::read_to_string(&cache).unwrap();
+
+    // A rewrite would replace this marker.
+    fs::write(&output, "// Not regenerated").unwrap();
+    config.generate(&[&input]).unwrap();
+    assert_eq!(fs::read_to_string(&output).unwrap(), "// Not regenerated");
+    assert_eq!(fs::read_to_string(&cache).unwrap(), hash);
+
+    // Changing the configuration regenerates the output...
+    config.additional_traits = vec!["PartialEq".into()];
+    config.generate(&[&input]).unwrap();
+    assert!(fs::read_to_string(&output).unwrap().contains("PartialEq"));
+    let new_hash = fs::read_to_string(&cache).unwrap();
+    assert_ne!(new_hash, hash);
+    let hash = new_hash;
+
+    // ...and so does changing one of the inputs.
+    let schema = fs::read_to_string(&input).unwrap();
+    fs::write(&input, schema.replace("temperature_t", "celsius_t")).unwrap();
+    config.generate(&[&input]).unwrap();
+    assert!(fs::read_to_string(&output).unwrap().contains("struct Celsius"));
+    assert_ne!(fs::read_to_string(&cache).unwrap(), hash);
+}
+
+#[test]
+fn missing_output_is_regenerated() {
+    let dir = TempDir::new("lcm-gen").unwrap();
+    let output = dir.path().join("mod.rs");
+
+    let mut config = Config {
+        output_file: Some(output.clone()),
+        ..Config::default()
+    };
+    config.generate(&["tests/data/temperature_t.lcm"]).unwrap();
+    fs::remove_file(&output).unwrap();
+    config.generate(&["tests/data/temperature_t.lcm"]).unwrap();
+    assert!(fs::read_to_string(&output).unwrap().contains("struct Temperature"));
+}
+
+#[test]
+fn failed_generation_keeps_the_output() {
+    let dir = TempDir::new("lcm-gen").unwrap();
+    let output = dir.path().join("mod.rs");
+    let input = dir.path().join("temperature_t.lcm");
+    fs::copy("tests/data/temperature_t.lcm", &input).unwrap();
+
+    let mut config = Config {
+        output_file: Some(output.clone()),
+        ..Config::default()
+    };
+    config.generate(&[&input]).unwrap();
+    let generated = fs::read_to_string(&output).unwrap();
+
+    fs::write(&input, "struct broken_t {").unwrap();
+    assert!(config.generate(&[&input]).is_err());
+    assert_eq!(fs::read_to_string(&output).unwrap(), generated);
+}
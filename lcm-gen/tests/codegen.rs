@@ -4,10 +4,13 @@ extern crate pretty_assertions;
 
 use lcm_gen::{ast, codegen, Config};
 use std::collections::HashMap;
+use std::io::{self, Cursor, Write};
 
 #[test]
 fn simple_struct() {
     let module = ast::Module {
+        comments: vec![],
+        version: None,
         submodules: HashMap::new(),
         structs: vec![
             ast::Struct {
@@ -19,6 +22,7 @@ fn simple_struct() {
                         name: "field".into(),
                         ty: ast::Type::Double,
                         multiplicity: vec![],
+                        json_name: None,
                     },
                 ],
                 constants: vec![],
@@ -78,6 +82,22 @@ pub struct MyStruct {
 "##
 );
 
+check_generated!(
+    file_comments,
+    r##"pub mod exlcm {
+    //! Messages for the exlcm example.
+    //! It has a comment before the package.
+    #[doc = r#" The first struct."#]
+    #[derive(Clone, Debug, Message)]
+    pub struct First {
+    }
+    #[derive(Clone, Debug, Message)]
+    pub struct Second {
+    }
+}
+"##
+);
+
 check_generated!(
     multiple_structs,
     r#"#[derive(Clone, Debug, Message)]
@@ -110,6 +130,18 @@ impl MyConstants {
 "##
 );
 
+check_generated!(
+    boolean_constants_t,
+    r##"#[derive(Clone, Debug, Message)]
+pub struct BooleanConstants {
+}
+impl BooleanConstants {
+    pub const ENABLED: bool = true;
+    pub const DISABLED: bool = false;
+}
+"##
+);
+
 check_generated!(
     point2d_list_t,
     r#"#[derive(Clone, Debug, Message)]
@@ -158,6 +190,8 @@ pub struct MemberGroup {
 #[test]
 fn optional_traits() {
     let module = ast::Module {
+        comments: vec![],
+        version: None,
         submodules: HashMap::new(),
         structs: vec![
             ast::Struct {
@@ -182,3 +216,563 @@ pub struct MyType {
 
     assert_eq!(generated, expected);
 }
+
+#[test]
+fn generate_to_writer() {
+    let files = ["tests/data/temperature_t.lcm", "tests/data/camera_image_t.lcm"];
+    let mut cursor = Cursor::new(Vec::new());
+    Config::default().generate_to(&files, &mut cursor).unwrap();
+
+    let generated = String::from_utf8(cursor.into_inner()).unwrap();
+    assert!(generated.starts_with("#[derive(Clone, Debug, Message)]\npub struct Temperature {"));
+    assert_eq!(generated, Config::default().generate_string(&files).unwrap());
+}
+
+#[test]
+fn generate_to_failing_writer() {
+    /// A writer that accepts a few bytes and then fails.
+    struct Failing(usize);
+    impl Write for Failing {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "disk full"));
+            }
+            let len = buf.len().min(self.0);
+            self.0 -= len;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let error = Config::default()
+        .generate_to(&["tests/data/temperature_t.lcm"], Failing(10))
+        .unwrap_err();
+    assert!(error.iter_chain().any(|cause| cause.to_string() == "disk full"));
+}
+
+#[test]
+fn strict_unknown_types() {
+    let mut config = Config {
+        strict: true,
+        ..Config::default()
+    };
+    let error = config
+        .generate_string(&["tests/data/camera_image_t.lcm"])
+        .unwrap_err()
+        .to_string();
+
+    assert_eq!(
+        error,
+        r#"Unknown types in the schemas:
+Unknown type "jpeg.image_t" for field "jpeg_image" of "mycorp.camera_image_t"
+Unknown type "mit.pose_t" for field "pose" of "mycorp.camera_image_t""#
+    );
+}
+
+#[test]
+fn strict_known_types() {
+    let mut config = Config {
+        strict: true,
+        ..Config::default()
+    };
+    assert!(config.generate_string(&["tests/data/multiple_structs.lcm"]).is_ok());
+
+    // Without strict mode, unknown types are assumed to exist elsewhere.
+    config.strict = false;
+    assert!(config.generate_string(&["tests/data/camera_image_t.lcm"]).is_ok());
+}
+
+#[test]
+fn field_info() {
+    let mut config = Config {
+        field_info: true,
+        ..Config::default()
+    };
+    let generated = config
+        .generate_string(&["tests/data/temperature_t.lcm"])
+        .unwrap();
+
+    let expected = r##"#[derive(Clone, Debug, Message)]
+pub struct Temperature {
+    pub utime: i64,
+    #[doc = r#" Temperature in degrees Celsius. A "float" would probably
+     * be good enough, unless we're measuring temperatures during
+     * the big bang. Note that the asterisk on the beginning of this
+     * line is not syntactically necessary, it's just pretty.
+     "#]
+    pub degCelsius: f64,
+}
+impl Temperature {
+    #[doc = r#" The name and LCM type of each field, in the order they are encoded."#]
+    pub fn fields() -> &'static [(&'static str, &'static str)] {
+        &[
+            ("utime", "int64_t"),
+            ("degCelsius", "double"),
+        ]
+    }
+}
+"##;
+
+    assert_eq!(generated, expected);
+}
+
+#[test]
+fn reset() {
+    let mut config = Config {
+        reset: true,
+        ..Config::default()
+    };
+    let generated = config.generate_string(&["tests/data/reset_t.lcm"]).unwrap();
+
+    let expected = r##"#[derive(Clone, Debug, Message)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+}
+impl Position {
+    #[doc = r#" Sets every field to zero or empty, keeping the memory allocated by strings and vectors."#]
+    pub fn reset(&mut self) {
+        self.x = 0.0;
+        self.y = 0.0;
+    }
+}
+#[derive(Clone, Debug, Message)]
+pub struct Reset {
+    pub utime: i64,
+    pub name: String,
+    pub valid: bool,
+    #[lcm()]
+    pub flags: [i8; 4],
+    pub npath: i32,
+    #[lcm(length = "npath")]
+    pub path: Vec<Position>,
+    #[lcm()]
+    pub corners: [[Position; 2]; 2],
+    pub origin: Position,
+    pub nrows: i32,
+    #[lcm(length = "nrows")]
+    pub labels: [Vec<String>; 3],
+}
+impl Reset {
+    #[doc = r#" Sets every field to zero or empty, keeping the memory allocated by strings and vectors."#]
+    pub fn reset(&mut self) {
+        self.utime = 0;
+        self.name.clear();
+        self.valid = false;
+        for item in self.flags.iter_mut() {
+            *item = 0;
+        }
+        self.npath = 0;
+        self.path.clear();
+        for item in self.corners.iter_mut() {
+            for item in item.iter_mut() {
+                item.reset();
+            }
+        }
+        self.origin.reset();
+        self.nrows = 0;
+        for item in self.labels.iter_mut() {
+            item.clear();
+        }
+    }
+}
+"##;
+
+    assert_eq!(generated, expected);
+}
+
+#[test]
+fn json_names() {
+    let mut config = Config {
+        additional_traits: vec!["Serialize".into(), "Deserialize".into()],
+        ..Config::default()
+    };
+    let generated = config
+        .generate_string(&["tests/data/json_names_t.lcm"])
+        .unwrap();
+
+    let expected = r##"#[derive(Clone, Debug, Deserialize, Message, Serialize)]
+pub struct JsonNames {
+    #[doc = r#" Microseconds since the epoch."#]
+    #[serde(rename = "timestamp")]
+    pub utime: i64,
+    #[serde(rename = "deviceId")]
+    pub device_id: String,
+    pub value: f64,
+}
+"##;
+
+    assert_eq!(generated, expected);
+}
+
+#[test]
+fn json_name_with_quotes() {
+    let module = ast::Module {
+        structs: vec![
+            ast::Struct {
+                comment: None,
+                name: "quoted_t".into(),
+                fields: vec![
+                    ast::Field {
+                        comment: None,
+                        name: "value".into(),
+                        ty: ast::Type::Double,
+                        multiplicity: vec![],
+                        json_name: Some("say \"hi\"".into()),
+                    },
+                ],
+                constants: vec![],
+            },
+        ],
+        ..ast::Module::default()
+    };
+    let config = Config {
+        additional_traits: vec!["Serialize".into()],
+        ..Config::default()
+    };
+    let generated = codegen::generate_with_config(&module, &config);
+
+    assert!(generated.contains(r#"#[serde(rename = "say \"hi\"")]"#));
+}
+
+#[test]
+fn json_names_without_serde() {
+    let generated = Config::default()
+        .generate_string(&["tests/data/json_names_t.lcm"])
+        .unwrap();
+
+    assert!(!generated.contains("serde"));
+    assert!(!generated.contains("@json"));
+}
+
+check_generated!(
+    versioned,
+    r##"pub mod versioned {
+    //! Messages for the versioned example.
+    #[doc = r#" The version of the schemas in this module."#]
+    pub const SCHEMA_VERSION: &str = "1.2.0";
+    #[derive(Clone, Debug, Message)]
+    pub struct Status {
+        pub code: i32,
+    }
+}
+"##
+);
+
+#[test]
+fn content_hash() {
+    let mut config = Config {
+        content_hash: true,
+        ..Config::default()
+    };
+    let generated = config
+        .generate_string(&["tests/data/my_constants_t.lcm"])
+        .unwrap();
+
+    let expected = r##"#[derive(Clone, Debug, Message)]
+pub struct MyConstants {
+}
+impl MyConstants {
+    pub const YELLOW: i32 = 1;
+    pub const GOLDENROD: i32 = 2;
+    pub const CANARY: i32 = 3;
+    pub const E: f64 = 2.8718;
+    #[doc = r#" A hash of the encoded contents of the message, such as for detecting changes."#]
+    pub fn content_hash(&self) -> Result<u64, ::lcm::error::EncodeError> {
+        ::lcm::content_hash(self)
+    }
+}
+"##;
+
+    assert_eq!(generated, expected);
+}
+
+#[test]
+fn hex_constants() {
+    let mut config = Config {
+        hex_constants: true,
+        ..Config::default()
+    };
+    let generated = config.generate_string(&["tests/data/flags_t.lcm"]).unwrap();
+
+    let expected = r##"#[derive(Clone, Debug, Message)]
+pub struct Flags {
+    pub status: i32,
+}
+impl Flags {
+    pub const READY_FLAG: i32 = 0x1;
+    pub const ERROR_FLAG: i32 = 0x10;
+    pub const STATUS_MASK: i32 = 0xff;
+    pub const INVERTED_MASK: i8 = -1;
+    pub const MAX_RETRIES: i64 = 42;
+}
+"##;
+
+    assert_eq!(generated, expected);
+
+    let generated = Config::default()
+        .generate_string(&["tests/data/flags_t.lcm"])
+        .unwrap();
+    assert!(generated.contains("pub const STATUS_MASK: i32 = 255;"));
+}
+
+#[test]
+fn byte_conversions() {
+    let mut config = Config {
+        byte_conversions: true,
+        ..Config::default()
+    };
+    let generated = config
+        .generate_string(&["tests/data/point2d_list_t.lcm"])
+        .unwrap();
+
+    let expected = r##"#[derive(Clone, Debug, Message)]
+pub struct Point2dList {
+    pub npoints: i32,
+    #[lcm(length = "npoints")]
+    pub points: Vec<[f64; 2]>,
+}
+impl<'a> ::std::convert::TryFrom<&'a Point2dList> for Vec<u8> {
+    type Error = ::lcm::error::EncodeError;
+    fn try_from(message: &'a Point2dList) -> Result<Self, Self::Error> {
+        ::lcm::Message::encode_with_hash(message)
+    }
+}
+impl<'a> ::std::convert::TryFrom<&'a [u8]> for Point2dList {
+    type Error = ::lcm::error::DecodeError;
+    fn try_from(mut buffer: &'a [u8]) -> Result<Self, Self::Error> {
+        let message = ::lcm::Message::decode_with_hash(&mut buffer)?;
+        if !buffer.is_empty() {
+            return Err(::lcm::error::DecodeError::TrailingBytes(buffer.len()));
+        }
+        Ok(message)
+    }
+}
+"##;
+
+    assert_eq!(generated, expected);
+}
+
+#[test]
+fn non_exhaustive() {
+    let mut config = Config {
+        non_exhaustive: true,
+        ..Config::default()
+    };
+    let generated = config.generate_string(&["tests/data/flags_t.lcm"]).unwrap();
+    assert!(generated.starts_with(
+        "#[derive(Clone, Debug, Message)]\n#[non_exhaustive]\npub struct Flags {\n"
+    ));
+    // Other crates can only build the struct with its builder.
+    assert!(generated.contains("pub fn builder() -> FlagsBuilder {"));
+
+    let generated = Config::default()
+        .generate_string(&["tests/data/flags_t.lcm"])
+        .unwrap();
+    assert!(!generated.contains("#[non_exhaustive]"));
+}
+
+#[test]
+fn indent() {
+    let mut config = Config {
+        content_hash: true,
+        indent: "  ".into(),
+        ..Config::default()
+    };
+    let generated = config
+        .generate_string(&["tests/data/my_constants_t.lcm"])
+        .unwrap();
+
+    let expected = r##"#[derive(Clone, Debug, Message)]
+pub struct MyConstants {
+}
+impl MyConstants {
+  pub const YELLOW: i32 = 1;
+  pub const GOLDENROD: i32 = 2;
+  pub const CANARY: i32 = 3;
+  pub const E: f64 = 2.8718;
+  #[doc = r#" A hash of the encoded contents of the message, such as for detecting changes."#]
+  pub fn content_hash(&self) -> Result<u64, ::lcm::error::EncodeError> {
+    ::lcm::content_hash(self)
+  }
+}
+"##;
+
+    assert_eq!(generated, expected);
+}
+
+#[test]
+fn builder() {
+    let mut config = Config {
+        builder: true,
+        ..Config::default()
+    };
+    let generated = config
+        .generate_string(&["tests/data/point2d_list_t.lcm"])
+        .unwrap();
+
+    let expected = r##"#[derive(Clone, Debug, Message)]
+pub struct Point2dList {
+    pub npoints: i32,
+    #[lcm(length = "npoints")]
+    pub points: Vec<[f64; 2]>,
+}
+impl Point2dList {
+    #[doc = r#" Starts building a message, with every field unset."#]
+    pub fn builder() -> Point2dListBuilder {
+        Point2dListBuilder::default()
+    }
+}
+#[doc = r#" Builds a `Point2dList`, filling in any fields that aren't set."#]
+#[derive(Clone, Debug, Default)]
+pub struct Point2dListBuilder {
+    points: Option<Vec<[f64; 2]>>,
+}
+impl Point2dListBuilder {
+    pub fn points(mut self, points: Vec<[f64; 2]>) -> Self {
+        self.points = Some(points);
+        self
+    }
+    #[doc = r#" Unset fields are zero or empty, and array lengths are taken from the arrays."#]
+    pub fn build(self) -> Point2dList {
+        let points = self.points.unwrap_or_default();
+        let npoints = ::std::cmp::min(points.len(), i32::MAX as usize) as i32;
+        Point2dList {
+            npoints,
+            points,
+        }
+    }
+}
+"##;
+
+    assert_eq!(generated, expected);
+}
+
+#[test]
+fn builder_defaults() {
+    let mut config = Config {
+        builder: true,
+        ..Config::default()
+    };
+    let generated = config.generate_string(&["tests/data/reset_t.lcm"]).unwrap();
+
+    // Structs don't implement `Default`, so they are made by their builders.
+    assert!(generated.contains(
+        "let corners = self.corners.unwrap_or_else(|| ::std::array::from_fn(|_| \
+         ::std::array::from_fn(|_| PositionBuilder::default().build())));"
+    ));
+    assert!(generated
+        .contains("let origin = self.origin.unwrap_or_else(|| PositionBuilder::default().build());"));
+    assert!(generated.contains("let npath = ::std::cmp::min(path.len(), i32::MAX as usize) as i32;"));
+    assert!(generated.contains(
+        "let nrows = ::std::cmp::min(labels.first().map_or(0, |x| x.len()), i32::MAX as usize) \
+         as i32;"
+    ));
+}
+
+#[test]
+fn builder_method_clash() {
+    let schema = "tests/data/job_t.lcm";
+    let mut config = Config {
+        builder: true,
+        ..Config::default()
+    };
+    let error = config.generate_string(&[&schema]).unwrap_err().to_string();
+    assert!(error.contains("\"build\""), "{}", error);
+
+    // Without a builder, the name is fine.
+    Config::default().generate_string(&[&schema]).unwrap();
+}
+
+#[test]
+fn field_info_arrays() {
+    let mut config = Config {
+        field_info: true,
+        ..Config::default()
+    };
+    let generated = config
+        .generate_string(&["tests/data/point2d_list_t.lcm"])
+        .unwrap();
+
+    assert!(generated.contains(r#"("points", "double[npoints][2]"),"#));
+}
+
+#[test]
+fn channel_enum() {
+    let config = Config {
+        channels: vec![
+            ("CAMERA_FRONT".into(), "mycorp.camera_image_t".into()),
+            ("camera.rear".into(), "mycorp.camera_image_t".into()),
+        ],
+        ..Config::default()
+    };
+    let module = ast::Module::default();
+    let generated = codegen::generate_with_config(&module, &config);
+
+    let expected = r##"#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Channel {
+    #[doc = r#" Carries `mycorp::CameraImage` messages."#]
+    CameraFront,
+    #[doc = r#" Carries `mycorp::CameraImage` messages."#]
+    CameraRear,
+}
+impl Channel {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Channel::CameraFront => "CAMERA_FRONT",
+            Channel::CameraRear => "camera.rear",
+        }
+    }
+}
+impl ::std::str::FromStr for Channel {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "CAMERA_FRONT" => Ok(Channel::CameraFront),
+            "camera.rear" => Ok(Channel::CameraRear),
+            _ => Err(()),
+        }
+    }
+}
+"##;
+
+    assert_eq!(generated, expected);
+}
+
+#[test]
+fn channel_enum_unknown_type() {
+    let mut config = Config {
+        channels: vec![("CAMERA".into(), "mycorp.missing_t".into())],
+        ..Config::default()
+    };
+    assert!(
+        config
+            .generate_string(&["tests/data/camera_image_t.lcm"])
+            .is_err()
+    );
+}
+
+#[test]
+fn channel_enum_bad_variants() {
+    for channels in &[
+        vec!["CAMERA_FRONT", "camera.front"],
+        vec!["..."],
+        vec!["3D_POINTS"],
+    ] {
+        let mut config = Config {
+            channels: channels
+                .iter()
+                .map(|&channel| (channel.into(), "mycorp.camera_image_t".into()))
+                .collect(),
+            ..Config::default()
+        };
+        let error = config
+            .generate_string(&["tests/data/camera_image_t.lcm"])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains(channels[0]), "{}", error);
+    }
+}
@@ -0,0 +1,96 @@
+extern crate lcm_gen;
+extern crate tempdir;
+
+use lcm_gen::Config;
+use std::fs;
+use std::path::Path;
+use tempdir::TempDir;
+
+fn generate(schema: &Path, fingerprints: &Path) -> Result<String, String> {
+    let mut config = Config {
+        fingerprints: Some(fingerprints.to_owned()),
+        ..Config::default()
+    };
+    config
+        .generate_string(&[schema])
+        .map_err(|e| e.to_string())
+}
+
+#[test]
+fn records_new_types() {
+    let dir = TempDir::new("lcm-gen").unwrap();
+    let fingerprints = dir.path().join("fingerprints.json");
+
+    generate(Path::new("tests/data/point2d_list_t.lcm"), &fingerprints).unwrap();
+    assert_eq!(
+        fs::read_to_string(&fingerprints).unwrap(),
+        "{\n  \"point2d_list_t\": \"0x4f85d1e7da2fc594\"\n}\n"
+    );
+
+    // Types that are already recorded are left alone.
+    let mut config = Config {
+        fingerprints: Some(fingerprints.clone()),
+        ..Config::default()
+    };
+    config
+        .generate_string(&[
+            "tests/data/point2d_list_t.lcm",
+            "tests/data/temperature_t.lcm",
+        ])
+        .unwrap();
+    let recorded = fs::read_to_string(&fingerprints).unwrap();
+    assert!(recorded.contains("\"point2d_list_t\": \"0x4f85d1e7da2fc594\""));
+    assert!(recorded.contains("\"temperature_t\": \"0xa07fa3d64cbea6ea\""));
+}
+
+#[test]
+fn reordered_fields() {
+    let dir = TempDir::new("lcm-gen").unwrap();
+    let fingerprints = dir.path().join("fingerprints.json");
+    let schema = dir.path().join("point_t.lcm");
+
+    fs::write(&schema, "package geo; struct point_t { double x; double y; }").unwrap();
+    generate(&schema, &fingerprints).unwrap();
+    let recorded = fs::read_to_string(&fingerprints).unwrap();
+
+    // Reordering the fields changes the fingerprint, so the old and new
+    // types can't talk to each other.
+    fs::write(&schema, "package geo; struct point_t { double y; double x; }").unwrap();
+    let error = generate(&schema, &fingerprints).unwrap_err();
+    assert!(error.starts_with("Fingerprints differ from those recorded in"));
+    assert!(error.contains("\ngeo.point_t changed from 0x"));
+    assert_eq!(fs::read_to_string(&fingerprints).unwrap(), recorded);
+
+    // The change is accepted once the old fingerprint is removed.
+    fs::remove_file(&fingerprints).unwrap();
+    generate(&schema, &fingerprints).unwrap();
+    assert_ne!(fs::read_to_string(&fingerprints).unwrap(), recorded);
+}
+
+#[test]
+fn invalid_fingerprints() {
+    let dir = TempDir::new("lcm-gen").unwrap();
+    let fingerprints = dir.path().join("fingerprints.json");
+    let schema = Path::new("tests/data/temperature_t.lcm");
+
+    fs::write(&fingerprints, "{\"temperature_t\": \"hot\"}").unwrap();
+    let error = generate(schema, &fingerprints).unwrap_err();
+    assert!(error.starts_with("Invalid fingerprint \"hot\""));
+
+    fs::write(&fingerprints, "[]").unwrap();
+    assert!(generate(schema, &fingerprints).is_err());
+}
+
+#[test]
+fn missing_types() {
+    let dir = TempDir::new("lcm-gen").unwrap();
+    let fingerprints = dir.path().join("fingerprints.json");
+
+    generate(Path::new("tests/data/point2d_list_t.lcm"), &fingerprints).unwrap();
+    let recorded = fs::read_to_string(&fingerprints).unwrap();
+
+    let error = generate(Path::new("tests/data/temperature_t.lcm"), &fingerprints).unwrap_err();
+    assert!(error.starts_with("Types recorded in"));
+    assert!(error.ends_with("are missing from the schemas:\npoint2d_list_t"));
+    assert_eq!(fs::read_to_string(&fingerprints).unwrap(), recorded);
+}
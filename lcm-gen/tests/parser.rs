@@ -192,6 +192,28 @@ fn simple_constant() {
     }
 }
 
+#[test]
+fn boolean_constant() {
+    parses_to!{
+        parser: LcmParser,
+        input: "const boolean ENABLED = true;",
+        rule: Rule::constant_group,
+        tokens: [
+            constant_group(0, 29, [
+                lcm_type(6, 13, [
+                    boolean(6, 13)
+                ]),
+                constant(14, 28, [
+                    constant_name(14, 21),
+                    constant_value(24, 28, [
+                        boolean_literal(24, 28)
+                    ])
+                ]),
+            ])
+        ]
+    }
+}
+
 #[test]
 fn multiple_constants() {
     parses_to!{
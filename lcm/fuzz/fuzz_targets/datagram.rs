@@ -0,0 +1,8 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate lcm;
+
+fuzz_target!(|data: &[u8]| {
+    lcm::fuzz_parse_datagram(data);
+});
@@ -0,0 +1,15 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate lcm;
+
+fuzz_target!(|data: &[u8]| {
+    for event in lcm::LogReader::new(data) {
+        match event {
+            Ok(event) => {
+                let _ = lcm::decode::<lcm::Raw>(&event.data);
+            }
+            Err(_) => break,
+        }
+    }
+});
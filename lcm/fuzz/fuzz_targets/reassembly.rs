@@ -0,0 +1,23 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate lcm;
+
+// The input is a flag byte followed by datagrams, each prefixed by its length
+// as a single byte. The lengths are short, but that is enough to exercise
+// the fragment headers.
+fuzz_target!(|data: &[u8]| {
+    let (dedup, mut data) = match data.split_first() {
+        Some((&flags, rest)) => (flags & 1 != 0, rest),
+        None => return,
+    };
+
+    let mut datagrams = Vec::new();
+    while let Some((&len, rest)) = data.split_first() {
+        let len = (len as usize).min(rest.len());
+        datagrams.push(&rest[..len]);
+        data = &rest[len..];
+    }
+
+    lcm::fuzz_process_datagrams(&datagrams, dedup);
+});
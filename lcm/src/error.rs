@@ -5,7 +5,7 @@
 //! operator or `From`. The other error types exist in case one wants to
 //! attempt to recover from an error.
 
-use std::{io, num, string};
+use std::{io, net, num, string};
 use regex;
 use url;
 
@@ -55,6 +55,18 @@ pub enum Error {
     /// An error happened while trying to handle incoming messages.
     #[fail(display = "Unable to handle incoming messages.")]
     Handle(#[cause] HandleError),
+
+    /// An error happened while trying to read a log file.
+    #[fail(display = "Unable to read the log file.")]
+    Log(#[cause] DecodeError),
+
+    /// An error happened while trying to change a setting.
+    #[fail(display = "Failed to change the setting.")]
+    Setting(#[cause] SettingError),
+
+    /// A log can only be replayed at a positive speed.
+    #[fail(display = "The replay speed {} is not positive.", _0)]
+    InvalidReplaySpeed(f64),
 }
 impl From<InitError> for Error {
     fn from(err: InitError) -> Self {
@@ -76,6 +88,11 @@ impl From<HandleError> for Error {
         Error::Handle(err)
     }
 }
+impl From<SettingError> for Error {
+    fn from(err: SettingError) -> Self {
+        Error::Setting(err)
+    }
+}
 
 
 /// The LCM instance was unable to start.
@@ -85,19 +102,84 @@ pub enum InitError {
     #[fail(display = "The LCM provider failed to start due to an IO error.")]
     IoError(#[cause] io::Error),
 
-    /// The supplied LCM URL requested a provider that isn't known.
+    /// The socket could not join one of the multicast groups.
     ///
-    /// If you get this error, check the feature flags on the crate. It is
-    /// possible that the provider you are requesting is disabled.
+    /// This commonly happens when no network interface is up or there is no
+    /// route for multicast traffic. On Linux, loopback multicast can be
+    /// enabled with `ip route add 224.0.0.0/4 dev lo`.
+    #[fail(
+        display = "Failed to join multicast group {}. Check that a network interface is up and has a route for multicast traffic.",
+        group
+    )]
+    MulticastJoinFailed {
+        /// The multicast group that could not be joined.
+        group: net::IpAddr,
+        /// The error returned when joining the group.
+        #[cause]
+        source: io::Error,
+    },
+
+    /// The socket's receive buffer could not be set to the size given by the
+    /// "recv_buf_size" option.
+    #[fail(display = "Failed to set the receive buffer size to {} bytes.", size)]
+    RecvBufSizeFailed {
+        /// The requested size.
+        size: usize,
+        /// The error returned when setting the size.
+        #[cause]
+        source: io::Error,
+    },
+
+    /// The supplied LCM URL requested a provider that isn't known.
     #[fail(display = "Unknown provider \"{}\".", _0)]
     UnknownProvider(String),
 
+    /// The supplied LCM URL requested a provider that exists but was not
+    /// compiled in.
+    ///
+    /// Enable the feature flag of the same name on this crate to use it.
+    #[fail(display = "The \"{}\" provider is disabled by feature flags.", _0)]
+    ProviderDisabled(String),
+
+    /// The supplied LCM URL requested an option that was not compiled in.
+    ///
+    /// Enable the named feature flag on this crate to use it.
+    #[fail(display = "The \"{}\" feature is needed for this LCM URL.", _0)]
+    FeatureDisabled(String),
+
+    /// An existing socket was supplied for a provider that doesn't use one.
+    #[fail(display = "The \"{}\" provider can not use a supplied socket.", _0)]
+    SocketNotSupported(String),
+
     /// The provided LCM URL was not valid.
+    ///
+    /// This is also returned if the URL is missing a part that the provider
+    /// requires, such as the port for UDPM.
     #[fail(display = "Invalid LCM URL.")]
     InvalidLcmUrl(#[cause] url::ParseError),
 
     #[fail(display = "Failed to parse time to live argument.")]
     InvalidTtl(#[cause] num::ParseIntError),
+
+    /// A flag in the LCM URL was neither `true` nor `false`.
+    #[fail(display = "Invalid value for the \"{}\" flag.", _0)]
+    InvalidFlag(String),
+
+    /// A size in the LCM URL was not a valid number of bytes.
+    #[fail(display = "Invalid value for the \"{}\" size.", _0)]
+    InvalidSize(String),
+
+    /// Some other option in the LCM URL had an invalid value.
+    #[fail(display = "Invalid value for the \"{}\" option.", _0)]
+    InvalidOption(String),
+
+    /// One of the additional multicast groups was not a valid IP address.
+    #[fail(display = "Failed to parse multicast group.")]
+    InvalidMulticastGroup(#[cause] net::AddrParseError),
+
+    /// The address to bind the socket to was not a valid IP address.
+    #[fail(display = "Failed to parse bind address.")]
+    InvalidBindAddress(#[cause] net::AddrParseError),
 }
 
 /// The attempt to subscribe to a channel was unsuccessful.
@@ -148,6 +230,26 @@ pub enum HandleError {
     /// information in this error type.
     #[fail(display = "The provider was unable to handle the incoming messages.")]
     ProviderIssue,
+
+    /// The provider is reading from a log file and there are no more events.
+    #[fail(display = "Reached the end of the log file.")]
+    EndOfLog,
+}
+
+/// An error occurred while changing a setting of a running instance.
+#[derive(Debug, Fail)]
+pub enum SettingError {
+    /// There was an IO issue that prevented the setting from changing.
+    #[fail(display = "IO error while changing the setting.")]
+    IoError(#[cause] io::Error),
+
+    /// The multicast TTL was larger than 255.
+    #[fail(display = "The TTL {} is larger than 255.", _0)]
+    InvalidTtl(u32),
+
+    /// The backing provider does not have the setting.
+    #[fail(display = "The provider does not support this setting.")]
+    NotSupported,
 }
 
 /// An error occurred while trying to decode a message.
@@ -174,6 +276,22 @@ pub enum DecodeError {
     #[fail(display = "Invalid Unicode found.")]
     Utf8Error(#[cause] string::FromUtf8Error),
 
+    /// An event in a log file did not start with the expected sync word.
+    #[fail(display = "Invalid sync word 0x{:08X} in the log file.", _0)]
+    InvalidSyncWord(u32),
+
+    /// The buffer ended before all of the elements of a variable sized
+    /// array had been decoded.
+    #[fail(display = "The buffer ended before the {} elements of {} given by {}.", expected, field, size_var)]
+    Truncated {
+        /// The array field being decoded.
+        field: &'static str,
+        /// The field specifying the size of the array.
+        size_var: &'static str,
+        /// The number of elements the array was expected to have.
+        expected: i64,
+    },
+
     /// A string was missing the null terminator.
     ///
     /// This doesn't stop us from parsing the string, but it does mean that the
@@ -181,6 +299,10 @@ pub enum DecodeError {
     #[fail(display = "String is missing the null terminator.")]
     MissingNullTerminator,
 
+    /// The buffer had bytes left over after the message was decoded.
+    #[fail(display = "{} bytes were left over after decoding the message.", _0)]
+    TrailingBytes(usize),
+
     /// An error occurred while trying to read from buffer.
     ///
     /// This error should never happen and should be removed in a future
@@ -208,6 +330,10 @@ pub enum EncodeError {
         found: usize,
     },
 
+    /// A byte blob had more bytes than can be counted by its `i32` length.
+    #[fail(display = "A blob of {} bytes is too long to be encoded.", _0)]
+    BlobTooLong(usize),
+
     /// An error occurred while trying to write to the buffer.
     ///
     /// This error should never happen and should be removed in a future
@@ -258,6 +384,12 @@ pub mod from {
         }
     }
     #[doc(hidden)]
+    impl From<io::Error> for SettingError {
+        fn from(err: io::Error) -> Self {
+            SettingError::IoError(err)
+        }
+    }
+    #[doc(hidden)]
     impl From<io::Error> for DecodeError {
         fn from(err: io::Error) -> Self {
             DecodeError::IoError(err)
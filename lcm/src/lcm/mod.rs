@@ -1,8 +1,20 @@
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
 use std::env;
-use std::io::{Read, Write};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Write};
+use std::process;
+use std::net::{IpAddr, SocketAddr};
+#[cfg(feature = "udpm")]
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::sync::mpsc;
-use std::time::Duration;
-use regex::Regex;
+use std::thread;
+use std::time::{Duration, Instant};
+use regex::{self, Regex};
 use url::Url;
 
 mod providers;
@@ -10,17 +22,32 @@ mod providers;
 use self::providers::file::FileProvider;
 #[cfg(feature = "udpm")]
 use self::providers::udpm::UdpmProvider;
+#[cfg(all(fuzzing, feature = "udpm"))]
+pub use self::providers::udpm::{fuzz_parse_datagram, fuzz_process_datagrams};
 
-use {Marshall, Message};
+use {LogReader, Marshall, Message, TypedRouter};
 use error::*;
 use utils::spsc;
 
 /// Message used to subscribe to a new channel.
+///
+/// The function is given the channel, the encoded message, and how the
+/// message was received. It returns whether the message was queued for the
+/// subscription's callback. The subscription is sent again on the
+/// unsubscribe channel once it is removed.
 type SubscribeMsg = (
+    Subscription,
     Regex,
-    Box<Fn(&str, &[u8]) -> Result<(), TrampolineError> + Send + 'static>,
+    Box<Fn(&str, &[u8], &MessageMeta) -> Result<bool, TrampolineError> + Send + 'static>,
 );
 
+/// Runs a subscription's callback on its queued messages.
+///
+/// The function is given the maximum number of messages it may deliver and
+/// returns the number it actually delivered, along with whether any messages
+/// are still queued.
+type DispatchFn<'a> = Box<FnMut(usize) -> (usize, bool) + 'a>;
+
 /// This is the maximum allowed message size.
 ///
 /// The C version of LCM discards any message greater than this size.
@@ -29,9 +56,26 @@ pub const MAX_MESSAGE_SIZE: usize = 1 << 28;
 /// The maximum allow number of bytes in a channel name.
 pub const MAX_CHANNEL_NAME_LENGTH: usize = 63;
 
+// Without a provider the `Provider` enum is empty and the dispatch below
+// produces a long list of confusing type errors, so fail early instead. The
+// `Lcm` methods are left out in that case so that this is the only error.
+#[cfg(not(any(feature = "udpm", feature = "file")))]
+compile_error!("At least one provider feature (\"udpm\" or \"file\") must be enabled.");
+
 /// Convenience macro for dispatching functions among providers.
+///
+/// Prefixing the call with `&` only borrows the provider immutably.
 macro_rules! provider
 {
+    (&$self:ident.$func:ident($($args:expr),*)) => {
+        match $self.provider {
+            #[cfg(feature = "udpm")]
+            Provider::Udpm(ref p) => p.$func($($args),*),
+
+            #[cfg(feature = "file")]
+            Provider::File(ref p) => p.$func($($args),*),
+        }
+    };
     ($self:ident.$func:ident($($args:expr),*)) => {
         match $self.provider {
             #[cfg(feature = "udpm")]
@@ -40,13 +84,140 @@ macro_rules! provider
             #[cfg(feature = "file")]
             Provider::File(ref mut p) => p.$func($($args),*),
         }
-    }
+    };
 }
 
+/// The most channels that `Stats` keeps per-channel statistics for, so that
+/// a sender using many channel names can't use up the memory.
+const MAX_TRACKED_CHANNELS: usize = 1024;
+
+/// The number of messages queued by a forwarding subscription.
+const FORWARD_BUFFER_SIZE: usize = 100;
+
 /// Default LCM URL to be used when the `LCM_DEFAULT_URL` environment variable
 /// is not available.
 const LCM_DEFAULT_URL: &str = "udpm://239.255.76.67:7667?ttl=0";
 
+/// Returns the LCM URL used when no other URL has been specified.
+pub fn default_url() -> String {
+    LCM_DEFAULT_URL.into()
+}
+
+/// Returns the LCM URL that `Lcm::new` will use.
+///
+/// The first of these that is available is used:
+///
+/// 1. The `LCM_DEFAULT_URL` environment variable, if it is not empty.
+/// 2. The file `$XDG_CONFIG_HOME/lcm/url`, or `$HOME/.config/lcm/url` if
+///    `XDG_CONFIG_HOME` is not set.
+/// 3. The file `$HOME/.lcmrc`.
+/// 4. The result of `default_url`.
+///
+/// A config file provides the first line that is neither blank nor a comment
+/// starting with `#`. Files that are missing, unreadable or have no such line
+/// are skipped.
+pub fn resolve_url() -> String {
+    resolve_url_with(|name| env::var_os(name))
+}
+
+/// Resolves the LCM URL as described in `resolve_url`, looking up
+/// environment variables with `var`.
+fn resolve_url_with<F: Fn(&str) -> Option<OsString>>(var: F) -> String {
+    match var("LCM_DEFAULT_URL").map(OsString::into_string) {
+        Some(Ok(ref s)) if s.is_empty() => {
+            debug!("LCM_DEFAULT_URL available but empty. Checking config files.");
+        }
+        Some(Ok(s)) => {
+            debug!("LCM_DEFAULT_URL=\"{}\"", s);
+            return s;
+        }
+        _ => {
+            debug!("LCM_DEFAULT_URL not present or unavailable. Checking config files.");
+        }
+    }
+
+    for path in config_file_paths(&var) {
+        if let Some(url) = read_url_file(&path) {
+            debug!("Using \"{}\" from {}", url, path.display());
+            return url;
+        }
+    }
+
+    debug!("No LCM URL configured. Using default settings.");
+    default_url()
+}
+
+/// Returns the config files that may contain an LCM URL, in order of
+/// precedence.
+fn config_file_paths<F: Fn(&str) -> Option<OsString>>(var: &F) -> Vec<PathBuf> {
+    let home = var("HOME").filter(|h| !h.is_empty()).map(PathBuf::from);
+
+    let mut paths = Vec::new();
+    match var("XDG_CONFIG_HOME").filter(|x| !x.is_empty()) {
+        Some(config) => paths.push(PathBuf::from(config).join("lcm").join("url")),
+        None => if let Some(ref home) = home {
+            paths.push(home.join(".config").join("lcm").join("url"));
+        },
+    }
+    if let Some(home) = home {
+        paths.push(home.join(".lcmrc"));
+    }
+    paths
+}
+
+/// Reads the LCM URL from a config file, if it exists and contains one.
+fn read_url_file(path: &Path) -> Option<String> {
+    let contents = fs::read_to_string(path).ok()?;
+    contents
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(Into::into)
+}
+
+/// The URL schemes of the providers that have been compiled in.
+const PROVIDERS: &[&str] = &[
+    #[cfg(feature = "udpm")]
+    "udpm",
+    #[cfg(feature = "file")]
+    "file",
+];
+
+/// Returns the URL schemes of the providers that this build supports.
+///
+/// Which providers are available depends on the crate's feature flags.
+pub fn available_providers() -> &'static [&'static str] {
+    PROVIDERS
+}
+
+/// Checks that the LCM URL can be used to construct a provider.
+///
+/// The scheme must name a provider that has been compiled in, and the rest of
+/// the URL must contain what that provider needs. Unrecognized options only
+/// produce a warning in the log.
+pub fn validate_url(lcm_url: &str) -> Result<(), InitError> {
+    check_url(&Url::parse(lcm_url)?)
+}
+
+/// Checks an already parsed LCM URL. See `validate_url`.
+fn check_url(url: &Url) -> Result<(), InitError> {
+    match url.scheme() {
+        #[cfg(feature = "udpm")]
+        "udpm" => UdpmProvider::validate_url(url),
+
+        #[cfg(feature = "file")]
+        "file" => FileProvider::validate_url(url),
+
+        #[cfg(not(feature = "udpm"))]
+        "udpm" => Err(InitError::ProviderDisabled("udpm".into())),
+
+        #[cfg(not(feature = "file"))]
+        "file" => Err(InitError::ProviderDisabled("file".into())),
+
+        scheme => Err(InitError::UnknownProvider(scheme.into())),
+    }
+}
+
 /// An LCM instance that handles publishing and subscribing as well as encoding
 /// and decoding messages.
 pub struct Lcm<'a> {
@@ -59,60 +230,204 @@ pub struct Lcm<'a> {
     /// The next available subscription ID
     next_subscription_id: u32,
     /// The subscriptions.
-    subscriptions: Vec<(Subscription, Box<FnMut() + 'a>)>,
+    subscriptions: Vec<(Subscription, DispatchFn<'a>)>,
+    /// The subscription that `dispatch` starts with, so that a bounded
+    /// dispatch doesn't always favour the earliest subscriptions.
+    dispatch_start: usize,
+    /// Set when a bounded dispatch ran out before the queues were empty.
+    ///
+    /// The provider doesn't notify us again for those messages, so the next
+    /// call to `handle` shouldn't wait for it.
+    dispatch_pending: bool,
+    /// The channel pattern of each subscription, as it was compiled and as
+    /// it was given.
+    patterns: Vec<(Subscription, Regex, String)>,
+    /// Flags set by limited subscriptions once they have run out.
+    limited_subscriptions: Vec<(Subscription, Rc<Cell<bool>>)>,
+    /// Messages received by forwarding subscriptions, along with the channel
+    /// they should be published on.
+    forwarded: Rc<RefCell<Vec<(String, Vec<u8>)>>>,
     /// The channel used to notify the backend of new subscriptions.
     subscribe_tx: mpsc::Sender<SubscribeMsg>,
+    /// The channel used to notify the backend of removed subscriptions.
+    unsubscribe_tx: mpsc::Sender<Subscription>,
+    /// Called whenever a subscription is made.
+    on_subscribe: Option<Box<FnMut(&Subscription, &str) + 'a>>,
+    /// Called whenever a subscription is removed.
+    on_unsubscribe: Option<Box<FnMut(&Subscription) + 'a>>,
+    /// Prepended to the channel of every publish and subscription.
+    channel_prefix: String,
 }
+#[cfg(any(feature = "udpm", feature = "file"))]
 impl<'a> Lcm<'a> {
     /// Creates a new `Lcm` instance.
     ///
     /// This uses the `LCM_DEFAULT_URL` environment variable to construct a
-    /// provider. If the variable does not exist or is empty, it will use the
-    /// LCM default of "udpm://239.255.76.67:7667?ttl=0".
+    /// provider. If the variable does not exist or is empty, it falls back to
+    /// the user's config files and then to the LCM default of
+    /// "udpm://239.255.76.67:7667?ttl=0". See `resolve_url` for details.
     pub fn new() -> Result<Self, InitError> {
-        let lcm_default_url = env::var("LCM_DEFAULT_URL");
-        let lcm_url = match lcm_default_url {
-            Ok(ref s) if s.is_empty() => {
-                debug!("LCM_DEFAULT_URL available but empty. Using default settings.");
-                LCM_DEFAULT_URL
-            }
-            Ok(ref s) => {
-                debug!("LCM_DEFAULT_URL=\"{}\"", s);
-                s
-            }
-            Err(_) => {
-                debug!("LCM_DEFAULT_URL not present or unavailable. Using default settings.");
-                LCM_DEFAULT_URL
-            }
-        };
-
-        Lcm::with_lcm_url(lcm_url)
+        Lcm::with_lcm_url(&resolve_url())
     }
 
     /// Create a new `Lcm` instance with the provider constructed from the
     /// supplied LCM URL.
+    ///
+    /// # TTL
+    ///
+    /// The UDPM option `ttl` sets the time-to-live of outgoing multicast
+    /// packets, and is 0 unless given. **With a TTL of 0, which includes the
+    /// default URL, IPv4 messages are sent and received on the loopback
+    /// interface.** They never leave this host, but programs that only listen
+    /// on another interface won't see them either, which is the case for
+    /// other LCM implementations that join the group on the default
+    /// interface. Use `ttl=1` or higher to talk to those programs, even on
+    /// the same host.
+    ///
+    /// # Compression
+    ///
+    /// With the "compression" feature enabled, the UDPM option
+    /// `compress=true` compresses every message with zlib before it is sent
+    /// and decompresses every message that is received. **This is not part of
+    /// the LCM protocol.** Other LCM implementations, and instances without
+    /// the option, can't read these messages, and their messages can't be
+    /// read by this instance. Only use it when every process on the network
+    /// has the option set. `Lcm::fragment_count` does not account for the
+    /// compression.
     pub fn with_lcm_url(lcm_url: &str) -> Result<Self, InitError> {
         debug!("Creating LCM instance using \"{}\"", lcm_url);
         let url = Url::parse(lcm_url)?;
+        check_url(&url)?;
 
         let (subscribe_tx, subscribe_rx) = mpsc::channel();
+        let (unsubscribe_tx, unsubscribe_rx) = mpsc::channel();
 
         let provider = match url.scheme() {
             #[cfg(feature = "udpm")]
-            "udpm" => Provider::Udpm(UdpmProvider::new(&url, subscribe_rx)?),
+            "udpm" => Provider::Udpm(UdpmProvider::new(&url, subscribe_rx, unsubscribe_rx)?),
 
             #[cfg(feature = "file")]
-            "file" => Provider::File(FileProvider::new(&url)?),
+            "file" => Provider::File(FileProvider::new(&url, subscribe_rx, unsubscribe_rx)?),
 
             scheme => return Err(InitError::UnknownProvider(scheme.into())),
         };
 
-        Ok(Lcm {
+        Ok(Lcm::with_provider(provider, subscribe_tx, unsubscribe_tx))
+    }
+
+    /// Create a new `Lcm` instance that communicates over an existing UDP
+    /// socket.
+    ///
+    /// This is meant for environments where sockets are managed externally,
+    /// such as systemd socket activation. The LCM URL must use the "udpm"
+    /// provider and still supplies the address that messages are published
+    /// to, but the socket is not bound, joined to any multicast groups, or
+    /// otherwise configured.
+    #[cfg(feature = "udpm")]
+    pub fn with_socket(lcm_url: &str, socket: UdpSocket) -> Result<Self, InitError> {
+        debug!("Creating LCM instance on a supplied socket using \"{}\"", lcm_url);
+        let url = Url::parse(lcm_url)?;
+        check_url(&url)?;
+        if url.scheme() != "udpm" {
+            return Err(InitError::SocketNotSupported(url.scheme().into()));
+        }
+
+        let (subscribe_tx, subscribe_rx) = mpsc::channel();
+        let (unsubscribe_tx, unsubscribe_rx) = mpsc::channel();
+        let provider = Provider::Udpm(UdpmProvider::with_socket(
+            &url,
+            socket,
+            subscribe_rx,
+            unsubscribe_rx,
+        )?);
+
+        Ok(Lcm::with_provider(provider, subscribe_tx, unsubscribe_tx))
+    }
+
+    /// Wraps a newly created provider.
+    fn with_provider(
+        provider: Provider,
+        subscribe_tx: mpsc::Sender<SubscribeMsg>,
+        unsubscribe_tx: mpsc::Sender<Subscription>,
+    ) -> Self {
+        Lcm {
             provider,
             next_subscription_id: 0,
             subscriptions: Vec::new(),
+            dispatch_start: 0,
+            dispatch_pending: false,
+            patterns: Vec::new(),
+            limited_subscriptions: Vec::new(),
+            forwarded: Rc::new(RefCell::new(Vec::new())),
             subscribe_tx,
-        })
+            unsubscribe_tx,
+            on_subscribe: None,
+            on_unsubscribe: None,
+            channel_prefix: String::new(),
+        }
+    }
+
+    /// Prepends a namespace to every channel used by this instance.
+    ///
+    /// Messages are published on the prefixed channel, and subscriptions
+    /// only match prefixed channels. Each subscription's regular expression
+    /// is matched against the rest of the channel, right after the prefix,
+    /// as if it started with `^`. This changes the meaning of unanchored
+    /// patterns: `STATUS` matches `ROBOT_STATUS` without a prefix, but with
+    /// one it only matches channels that start with `STATUS`. A `^` at the
+    /// start of each alternative, as in `^POSE|^STATUS`, is allowed and has
+    /// no effect. The prefix is inserted literally, so
+    /// special characters in it need no escaping. Callbacks are given the
+    /// channel with the prefix removed. This keeps instances with different prefixes
+    /// apart, e.g., test runs sharing a multicast group. It only applies to
+    /// subscriptions made after it is set.
+    pub fn channel_prefix(mut self, prefix: &str) -> Self {
+        self.channel_prefix = prefix.into();
+        self
+    }
+
+    /// Returns the channel with the prefix prepended.
+    fn prefixed<'c>(&self, channel: &'c str) -> Cow<'c, str> {
+        if self.channel_prefix.is_empty() {
+            Cow::Borrowed(channel)
+        } else {
+            Cow::Owned(format!("{}{}", self.channel_prefix, channel))
+        }
+    }
+
+    /// Sets a function to be called whenever a subscription is made.
+    ///
+    /// It is given the new subscription and the channel's regular expression.
+    /// This is meant for debugging and replaces any previous function.
+    pub fn on_subscribe<F>(&mut self, hook: F)
+    where
+        F: FnMut(&Subscription, &str) + 'a,
+    {
+        self.on_subscribe = Some(Box::new(hook));
+    }
+
+    /// Sets a function to be called whenever a subscription is removed.
+    ///
+    /// This includes limited subscriptions that have run out. This is meant
+    /// for debugging and replaces any previous function.
+    pub fn on_unsubscribe<F>(&mut self, hook: F)
+    where
+        F: FnMut(&Subscription) + 'a,
+    {
+        self.on_unsubscribe = Some(Box::new(hook));
+    }
+
+    /// Sets a function to be called when the provider's background thread
+    /// exits, whether because of an error or because the `Lcm` instance was
+    /// dropped.
+    ///
+    /// The function is called from the background thread. If the thread has
+    /// already exited, it is called immediately.
+    pub fn on_backend_exit<F>(&mut self, hook: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        provider!(self.on_backend_exit(Box::new(hook)))
     }
 
     /// Subscribes a callback to a particular channel.
@@ -130,16 +445,121 @@ impl<'a> Lcm<'a> {
         M: Message + Send + 'static,
         F: FnMut(&str, M) + 'a,
     {
-        let channel = Regex::new(channel)?;
+        self.subscribe_timestamped(channel, buffer_size, move |chan: &str, m: M, _| {
+            callback(chan, m)
+        })
+    }
+
+    /// Subscribes a callback to a particular channel, also passing it the
+    /// time at which each message was received.
+    ///
+    /// The timestamp is taken as soon as the datagram is read from the socket,
+    /// so it does not include the time the message spent waiting in the
+    /// queue. For messages that were split into fragments, it is the time at
+    /// which the last fragment arrived.
+    pub fn subscribe_timestamped<M, F>(
+        &mut self,
+        channel: &str,
+        buffer_size: usize,
+        mut callback: F,
+    ) -> Result<Subscription, SubscribeError>
+    where
+        M: Message + Send + 'static,
+        F: FnMut(&str, M, Instant) + 'a,
+    {
+        self.subscribe_with_meta(channel, buffer_size, move |chan: &str, m: M, meta| {
+            callback(chan, m, meta.received)
+        })
+    }
+
+    /// Subscribes a callback to a particular channel, also passing it details
+    /// about how each message was received.
+    ///
+    /// This is mostly useful for diagnosing network issues, such as finding
+    /// the messages that are routinely split into fragments.
+    pub fn subscribe_with_meta<M, F>(
+        &mut self,
+        channel: &str,
+        buffer_size: usize,
+        callback: F,
+    ) -> Result<Subscription, SubscribeError>
+    where
+        M: Message + Send + 'static,
+        F: FnMut(&str, M, MessageMeta) + 'a,
+    {
+        self.subscribe_with_decoder(
+            channel,
+            buffer_size,
+            |mut bytes: &[u8]| M::decode_with_hash(&mut bytes).map(Some),
+            callback,
+        )
+    }
+
+    /// Subscribes a callback to a particular channel, but only for messages
+    /// that satisfy the predicate.
+    ///
+    /// The predicate is run on the backend thread as soon as each message is
+    /// decoded. Messages that fail it are dropped there, so they don't take
+    /// up space in the queue or wake up `handle`.
+    pub fn subscribe_filtered<M, P, F>(
+        &mut self,
+        channel: &str,
+        buffer_size: usize,
+        predicate: P,
+        mut callback: F,
+    ) -> Result<Subscription, SubscribeError>
+    where
+        M: Message + Send + 'static,
+        P: Fn(&M) -> bool + Send + 'static,
+        F: FnMut(&str, M) + 'a,
+    {
+        let decode = move |mut bytes: &[u8]| {
+            let message = M::decode_with_hash(&mut bytes)?;
+            Ok(if predicate(&message) { Some(message) } else { None })
+        };
+        self.subscribe_with_decoder(channel, buffer_size, decode, move |chan: &str, m: M, _| {
+            callback(chan, m)
+        })
+    }
+
+    /// The implementation shared by all subscriptions.
+    ///
+    /// The decoder runs on the backend thread, and returns `None` for
+    /// messages that should be dropped there.
+    fn subscribe_with_decoder<M, D, F>(
+        &mut self,
+        channel: &str,
+        buffer_size: usize,
+        decode: D,
+        mut callback: F,
+    ) -> Result<Subscription, SubscribeError>
+    where
+        M: Send + 'static,
+        D: Fn(&[u8]) -> Result<Option<M>, DecodeError> + Send + 'static,
+        F: FnMut(&str, M, MessageMeta) + 'a,
+    {
+        let pattern = channel.to_owned();
+        let channel = if self.channel_prefix.is_empty() {
+            Regex::new(channel)?
+        } else {
+            Regex::new(&prefix_pattern(&self.channel_prefix, channel))?
+        };
+        let prefix = self.channel_prefix.clone();
 
         // Create the channel used to send the message back from the backend
-        let (tx, rx) = spsc::channel::<(String, M)>(buffer_size);
+        let (tx, rx) = spsc::channel::<(String, M, MessageMeta)>(buffer_size);
 
         // Then create the function that will convert the bytes into a message
         // and send it and the function that will pass things on to the callback.
-        let conversion_func = move |chan: &str, mut bytes: &[u8]| -> Result<(), TrampolineError> {
+        let conversion_func = move |chan: &str,
+                                    bytes: &[u8],
+                                    meta: &MessageMeta|
+              -> Result<bool, TrampolineError> {
             // First try to decode the message
-            let message = M::decode_with_hash(&mut bytes)?;
+            let message = match decode(bytes)? {
+                Some(message) => message,
+                None => return Ok(false),
+            };
 
             // Then double check that the channel isn't closed
             if tx.is_closed() {
@@ -147,22 +567,26 @@ impl<'a> Lcm<'a> {
             }
 
             // Otherwise, put it in the queue and call it a day.
-            tx.send((chan.into(), message));
-            Ok(())
+            let chan = chan.strip_prefix(&prefix[..]).unwrap_or(chan);
+            tx.send((chan.into(), message, *meta));
+            Ok(true)
         };
 
-        let callback_fn = move || {
+        let callback_fn = move |max_messages: usize| {
             // We can't loop forever because they might be filling up faster
             // than we can process them. So we're only going to read a number
             // equal to the size of the queue. This seems like it would be the
             // least surprising behavior for the user.
-            for _ in 0..rx.capacity() {
-                if let Some((chan, m)) = rx.recv() {
-                    callback(&chan, m);
+            let mut delivered = 0;
+            while delivered < rx.capacity().min(max_messages) {
+                if let Some((chan, m, meta)) = rx.recv() {
+                    callback(&chan, m, meta);
+                    delivered += 1;
                 } else {
                     break;
                 }
             }
+            (delivered, !rx.is_empty())
         };
 
         // Finally, create the new subscription ID
@@ -170,7 +594,10 @@ impl<'a> Lcm<'a> {
         self.next_subscription_id += 1;
 
         // Send it across the way and then store our callback.
-        match self.subscribe_tx.send((channel, Box::new(conversion_func))) {
+        let regex = channel.clone();
+        match self.subscribe_tx
+            .send((Subscription(sub_id), channel, Box::new(conversion_func)))
+        {
             Ok(_) => {}
             Err(_) => {
                 warn!("UDPM provider has died. Unable to send subscribe message.");
@@ -180,12 +607,112 @@ impl<'a> Lcm<'a> {
         self.subscriptions
             .push((Subscription(sub_id), Box::new(callback_fn)));
 
+        if let Some(ref mut hook) = self.on_subscribe {
+            hook(&Subscription(sub_id), regex.as_str());
+        }
+        self.patterns.push((Subscription(sub_id), regex, pattern));
+
         Ok(Subscription(sub_id))
     }
 
+    /// Subscribes a single callback to several channels.
+    ///
+    /// Each channel is interpreted as a regular expression, as with
+    /// `Lcm::subscribe`, and gets its own queue of `buffer_size` messages. If
+    /// any of the subscriptions fail, the ones already made are removed.
+    pub fn subscribe_all<M, F>(
+        &mut self,
+        channels: &[&str],
+        buffer_size: usize,
+        callback: F,
+    ) -> Result<Vec<Subscription>, SubscribeError>
+    where
+        M: Message + Send + 'static,
+        F: FnMut(&str, M) + 'a,
+    {
+        let callback = Rc::new(RefCell::new(callback));
+        let mut subscriptions = Vec::with_capacity(channels.len());
+        for channel in channels {
+            let callback = callback.clone();
+            let res = self.subscribe(channel, buffer_size, move |chan: &str, m: M| {
+                (*callback.borrow_mut())(chan, m)
+            });
+
+            match res {
+                Ok(subscription) => subscriptions.push(subscription),
+                Err(e) => {
+                    for subscription in subscriptions {
+                        self.unsubscribe(subscription);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(subscriptions)
+    }
+
+    /// Subscribes a callback to a particular channel, but only for the next
+    /// `count` messages.
+    ///
+    /// Once the callback has been called `count` times, the subscription is
+    /// automatically removed at the end of the current call to `handle`. Any
+    /// other messages that were already queued are discarded.
+    pub fn subscribe_limited<M, F>(
+        &mut self,
+        channel: &str,
+        buffer_size: usize,
+        count: usize,
+        mut callback: F,
+    ) -> Result<Subscription, SubscribeError>
+    where
+        M: Message + Send + 'static,
+        F: FnMut(&str, M) + 'a,
+    {
+        let exhausted = Rc::new(Cell::new(count == 0));
+        let mut remaining = count;
+
+        let flag = exhausted.clone();
+        let subscription = self.subscribe(channel, buffer_size, move |chan: &str, m: M| {
+            if remaining > 0 {
+                remaining -= 1;
+                callback(chan, m);
+                flag.set(remaining == 0);
+            }
+        })?;
+
+        self.limited_subscriptions
+            .push((Subscription(subscription.0), exhausted));
+        Ok(subscription)
+    }
+
+    /// Subscribes to a particular channel, keeping only the most recent
+    /// message.
+    ///
+    /// The returned `Latest` can be used to read the message at any time. It
+    /// is only updated during calls to `handle`.
+    pub fn subscribe_latest<M>(
+        &mut self,
+        channel: &str,
+    ) -> Result<(Subscription, Latest<M>), SubscribeError>
+    where
+        M: Message + Send + 'static,
+    {
+        let latest = Latest(Rc::new(RefCell::new(None)));
+        let cache = latest.clone();
+        let subscription = self.subscribe(channel, 1, move |_: &str, m: M| {
+            *cache.0.borrow_mut() = Some(m);
+        })?;
+
+        Ok((subscription, latest))
+    }
+
     /// Subscribes a raw callback to a particular channel.
     ///
     /// The normal `Lcm::subscribe` function should be preferred over this one.
+    /// The callback is given the bytes exactly as they were published,
+    /// including the message hash. Unlike subscribing with `Raw`, this
+    /// accepts messages that are too short to have a hash.
     pub fn subscribe_raw<F>(
         &mut self,
         channel: &str,
@@ -195,21 +722,123 @@ impl<'a> Lcm<'a> {
     where
         F: FnMut(&str, &[u8]) + 'a,
     {
-        self.subscribe(channel, buffer_size, move |chan: &str, m: RawBytes| {
-            callback(chan, &m.0);
+        self.subscribe_with_decoder(
+            channel,
+            buffer_size,
+            |bytes: &[u8]| Ok(Some(bytes.to_vec())),
+            move |chan: &str, m: Vec<u8>, _| callback(chan, &m),
+        )
+    }
+
+    /// Subscribes a `TypedRouter` to a particular channel.
+    ///
+    /// Each message is passed to the router's handler for its hash, which
+    /// allows one channel to carry several message types.
+    pub fn subscribe_router(
+        &mut self,
+        channel: &str,
+        buffer_size: usize,
+        mut router: TypedRouter<'a>,
+    ) -> Result<Subscription, SubscribeError> {
+        self.subscribe_raw(channel, buffer_size, move |chan: &str, m: &[u8]| {
+            router.dispatch(chan, m);
+        })
+    }
+
+    /// Subscribes a callback that receives both the decoded message and the
+    /// raw bytes it was decoded from.
+    ///
+    /// This is useful for logging messages while also processing them, since
+    /// the message does not need to be decoded twice or encoded again.
+    pub fn subscribe_raw_typed<M, F>(
+        &mut self,
+        channel: &str,
+        buffer_size: usize,
+        mut callback: F,
+    ) -> Result<Subscription, SubscribeError>
+    where
+        M: Message + Send + 'static,
+        F: FnMut(&str, M, &[u8]) + 'a,
+    {
+        self.subscribe(channel, buffer_size, move |chan: &str, m: TypedBytes<M>| {
+            callback(chan, m.0, &m.1);
+        })
+    }
+
+    /// Republishes the messages received on one channel onto another.
+    ///
+    /// The bytes are published verbatim, without being decoded. Messages are
+    /// forwarded during `handle`, after the other callbacks have run. The
+    /// input channel is a regular expression, as with `Lcm::subscribe`, and
+    /// should not match the output channel.
+    pub fn forward(
+        &mut self,
+        from_channel: &str,
+        to_channel: &str,
+    ) -> Result<Subscription, SubscribeError> {
+        let forwarded = self.forwarded.clone();
+        let to_channel = to_channel.to_owned();
+        self.subscribe_raw(from_channel, FORWARD_BUFFER_SIZE, move |_: &str, m: &[u8]| {
+            forwarded.borrow_mut().push((to_channel.clone(), m.to_vec()))
         })
     }
 
+    /// Returns the patterns of the subscriptions that match a channel.
+    ///
+    /// This is meant for debugging why a callback isn't being called. The
+    /// patterns are returned in the order the subscriptions were made, and
+    /// a pattern appears once for each subscription that uses it. If a
+    /// channel prefix is set, it is added to the channel before matching,
+    /// but the patterns are returned as they were given.
+    pub fn would_match(&self, channel: &str) -> Vec<String> {
+        let channel = self.prefixed(channel);
+        let channel = &channel[..];
+        self.patterns
+            .iter()
+            .filter(|&&(_, ref regex, _)| regex.is_match(channel))
+            .map(|&(_, _, ref pattern)| pattern.clone())
+            .collect()
+    }
+
     /// Unsubscribes a message handler.
     pub fn unsubscribe(&mut self, subscription: Subscription) {
+        let count = self.subscriptions.len();
         self.subscriptions
             .retain(|&(ref sub, _)| *sub != subscription);
+        self.patterns
+            .retain(|&(ref sub, _, _)| *sub != subscription);
+        self.limited_subscriptions
+            .retain(|&(ref sub, _)| *sub != subscription);
+
+        if self.subscriptions.len() != count {
+            if let Some(ref mut hook) = self.on_unsubscribe {
+                hook(&subscription);
+            }
+            self.release(subscription);
+        }
+    }
+
+    /// Unsubscribes every message handler, including forwarding ones.
+    pub fn unsubscribe_all(&mut self) {
+        if let Some(ref mut hook) = self.on_unsubscribe {
+            for &(ref subscription, _) in &self.subscriptions {
+                hook(subscription);
+            }
+        }
+        let subscriptions: Vec<_> = self.subscriptions.drain(..).collect();
+        for (subscription, _) in subscriptions {
+            self.release(subscription);
+        }
+        self.patterns.clear();
+        self.limited_subscriptions.clear();
+        self.forwarded.borrow_mut().clear();
+    }
 
-        // Explicitly drop the subscription, since it is no longer
-        // valid.  Without this, clippy suggests passing the
-        // subscription by reference, which does not capture the
-        // semantics of what this function does.
-        drop(subscription);
+    /// Tells the provider to release its side of a removed subscription.
+    fn release(&self, subscription: Subscription) {
+        if self.unsubscribe_tx.send(subscription).is_err() {
+            debug!("The provider has shut down. Unable to send unsubscribe message.");
+        }
     }
 
     /// Publishes a message on the specified channel.
@@ -218,67 +847,607 @@ impl<'a> Lcm<'a> {
         M: Message,
     {
         let message_buf = message.encode_with_hash()?;
-
-        if channel.len() > MAX_CHANNEL_NAME_LENGTH {
-            warn!("The channel name was too long. Unable to publish message.");
-            return Err(PublishError::ProviderIssue);
-        }
-
-        if message_buf.len() > MAX_MESSAGE_SIZE {
-            warn!("The message was too large to publish.");
-            return Err(PublishError::ProviderIssue);
-        }
-
-        provider!(self.publish(channel, &message_buf))
+        self.publish_raw(channel, &message_buf)
     }
 
     /// Publishes a raw message on the specified channel.
     ///
-    /// The normal `Lcm::publish` function should be preferred over this one.
+    /// The buffer is sent verbatim, so for other LCM implementations to
+    /// accept it, it should already start with the message hash. This is the
+    /// case for the bytes given to `Lcm::subscribe_raw` and the events in a
+    /// log file. The normal `Lcm::publish` function should be preferred over
+    /// this one.
     pub fn publish_raw(&mut self, channel: &str, buffer: &[u8]) -> Result<(), PublishError> {
-        // TODO:
-        // This is a fairly inefficient implementation. At some point, it
-        // should be replaced with something better.
-        self.publish(channel, &RawBytes(buffer.to_owned()))
+        let channel = self.prefixed(channel);
+        check_publish(&channel, buffer)?;
+        provider!(self.publish(&channel, buffer))
     }
 
     /// Waits for and dispatches messages.
     pub fn handle(&mut self) -> Result<(), HandleError> {
-        provider!(self.handle())?;
-        self.subscriptions
-            .iter_mut()
-            .for_each(|&mut (_, ref mut f)| (*f)());
+        self.wait(None)?;
+        self.dispatch(usize::MAX);
         Ok(())
     }
 
     /// Waits for and dispatches messages, with a timeout.
-    pub fn handle_timeout(&mut self, timeout: Duration) -> Result<(), HandleError> {
-        provider!(self.handle_timeout(timeout))?;
-        self.subscriptions
-            .iter_mut()
-            .for_each(|&mut (_, ref mut f)| (*f)());
-        Ok(())
+    ///
+    /// Returns `Ok(false)` if the timeout elapsed before the provider
+    /// received any messages.
+    pub fn handle_timeout(&mut self, timeout: Duration) -> Result<bool, HandleError> {
+        let received = self.wait(Some(timeout))?;
+        self.dispatch(usize::MAX);
+        Ok(received)
     }
-} // impl Lcm
 
-/// Errors that can happen during the trampoline closure.
-#[derive(Debug, Fail)]
-pub enum TrampolineError {
-    /// The channel was closed.
+    /// Dispatches any messages that are ready, without blocking.
     ///
-    /// This generally signifies that the user unsubscribed from the channel.
-    #[fail(display = "Unsubscribed from the channel")]
-    MessageChannelClosed,
+    /// Returns `Ok(false)` immediately if the provider hasn't received any
+    /// messages, so that this can be called from another event loop.
+    pub fn try_handle(&mut self) -> Result<bool, HandleError> {
+        // Always check the provider, so that a notification for messages
+        // left over from a bounded dispatch isn't seen later.
+        let ready = provider!(self.try_handle())? || self.dispatch_pending;
+        if ready {
+            self.dispatch(usize::MAX);
+        }
+        Ok(ready)
+    }
 
-    /// There was a decoding error.
-    #[fail(display = "Unable to decode message: {}", _0)]
-    Decode(#[cause] DecodeError),
-}
-impl From<DecodeError> for TrampolineError {
-    fn from(err: DecodeError) -> Self {
-        TrampolineError::Decode(err)
+    /// Waits for and dispatches messages, with a timeout, running at most
+    /// `max_messages` callbacks.
+    ///
+    /// Messages that are not dispatched stay queued for later calls, which
+    /// start with the subscription after the last one that was dispatched to.
+    /// This keeps the time spent in each call bounded even when the queues
+    /// are full. Returns `Ok(false)` if the timeout elapsed before the
+    /// provider received any messages, or immediately if `max_messages` is
+    /// zero.
+    pub fn handle_bounded(
+        &mut self,
+        max_messages: usize,
+        timeout: Duration,
+    ) -> Result<bool, HandleError> {
+        if max_messages == 0 {
+            return Ok(false);
+        }
+        let received = self.wait(Some(timeout))?;
+        self.dispatch(max_messages);
+        Ok(received)
     }
-}
+
+    /// Waits for the provider to receive messages, unless some are still
+    /// queued from a bounded dispatch.
+    fn wait(&mut self, timeout: Option<Duration>) -> Result<bool, HandleError> {
+        if self.dispatch_pending {
+            // Still clear any notification, so that it isn't seen later for
+            // messages that have already been dispatched.
+            provider!(self.try_handle())?;
+            return Ok(true);
+        }
+        match timeout {
+            Some(timeout) => provider!(self.handle_timeout(timeout)),
+            None => provider!(self.handle()).map(|_| true),
+        }
+    }
+
+    /// Checks that a message published by this instance is received by it
+    /// within the timeout.
+    ///
+    /// This publishes an empty message on a channel unique to this instance.
+    /// For the UDPM provider, it only succeeds if multicast loopback is
+    /// enabled. Messages for other subscriptions that arrive in the meantime
+    /// are dispatched as they would be by `Lcm::handle_timeout`.
+    pub fn self_test(&mut self, timeout: Duration) -> Result<bool, Error> {
+        let channel = format!(
+            "LCM_SELF_TEST_{}_{}",
+            process::id(),
+            self.next_subscription_id
+        );
+        let received = Rc::new(Cell::new(false));
+        let subscription = {
+            let received = received.clone();
+            let pattern = format!("^{}$", regex::escape(&channel));
+            self.subscribe_raw(&pattern, 1, move |_: &str, _: &[u8]| received.set(true))?
+        };
+
+        let res = self.publish_raw(&channel, &[])
+            .map_err(Error::from)
+            .and_then(|_| {
+                let start = Instant::now();
+                while !received.get() {
+                    let elapsed = start.elapsed();
+                    if elapsed >= timeout {
+                        break;
+                    }
+                    self.handle_timeout(timeout - elapsed)?;
+                }
+                Ok(received.get())
+            });
+
+        self.unsubscribe(subscription);
+        res
+    }
+
+    /// Publishes the events in an LCM log file on their recorded channels,
+    /// keeping their recorded timing.
+    ///
+    /// A `speed` of 2.0 replays the log twice as fast as it was recorded, and
+    /// `f64::INFINITY` publishes the events as quickly as possible. Returns
+    /// the number of events that were published.
+    pub fn replay_log<P: AsRef<Path>>(&mut self, path: P, speed: f64) -> Result<usize, Error> {
+        self.replay_log_with(path, &ReplayOptions {
+            speed,
+            ..ReplayOptions::default()
+        })
+    }
+
+    /// Publishes the events in an LCM log file on their recorded channels,
+    /// as controlled by the options.
+    ///
+    /// Events whose channel names are longer than `MAX_CHANNEL_NAME_LENGTH`
+    /// can't be published, so they are skipped with a warning. This does not
+    /// dispatch any messages, so subscriptions on this instance only see the
+    /// replayed messages on later calls to `handle`. Returns
+    /// `Error::InvalidReplaySpeed` if the speed is not positive.
+    pub fn replay_log_with<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        options: &ReplayOptions,
+    ) -> Result<usize, Error> {
+        if options.speed.is_nan() || options.speed <= 0.0 {
+            return Err(Error::InvalidReplaySpeed(options.speed));
+        }
+        let channel = match options.channel {
+            Some(ref channel) => Some(Regex::new(channel).map_err(SubscribeError::from)?),
+            None => None,
+        };
+
+        let mut published = 0;
+        loop {
+            let file = File::open(path.as_ref()).map_err(|e| Error::Log(e.into()))?;
+            let mut start = None;
+            let published_before = published;
+            for event in LogReader::new(BufReader::new(file)) {
+                let event = event.map_err(Error::Log)?;
+                if let Some(ref channel) = channel {
+                    if !channel.is_match(&event.channel) {
+                        continue;
+                    }
+                }
+                if event.channel.len() > MAX_CHANNEL_NAME_LENGTH {
+                    warn!(
+                        "Skipping event {} since its channel name is too long.",
+                        event.event_number
+                    );
+                    continue;
+                }
+
+                // Wait until the event is due, relative to the first one.
+                let (started, first_timestamp) =
+                    *start.get_or_insert_with(|| (Instant::now(), event.timestamp));
+                let due = replay_offset(first_timestamp, event.timestamp, options.speed);
+                let elapsed = started.elapsed();
+                if due > elapsed {
+                    thread::sleep(due - elapsed);
+                }
+
+                self.publish_raw(&event.channel, &event.data)?;
+                published += 1;
+            }
+
+            // Stop even when repeating if nothing could be published, since
+            // nothing will be the next time around either.
+            if !options.repeat || published == published_before {
+                return Ok(published);
+            }
+        }
+    }
+
+    /// Runs the callbacks for up to `max_messages` queued messages.
+    ///
+    /// Afterwards, any limited subscriptions that have run out are removed.
+    fn dispatch(&mut self, max_messages: usize) {
+        let count = self.subscriptions.len();
+        let mut remaining = max_messages;
+        self.dispatch_pending = false;
+        for i in 0..count {
+            let index = (self.dispatch_start + i) % count;
+            // Once the limit is reached, the rest of the subscriptions are
+            // only checked for queued messages.
+            let (delivered, queued) = (self.subscriptions[index].1)(remaining);
+            if queued {
+                self.dispatch_pending = true;
+            }
+            if remaining > 0 {
+                remaining -= delivered;
+                if remaining == 0 {
+                    self.dispatch_start = (index + 1) % count;
+                }
+            }
+        }
+
+        let forwarded: Vec<_> = self.forwarded.borrow_mut().drain(..).collect();
+        for (channel, message) in forwarded {
+            if let Err(e) = self.publish_raw(&channel, &message) {
+                warn!("Unable to forward message to \"{}\": {}", channel, e);
+            }
+        }
+
+        let exhausted: Vec<_> = self.limited_subscriptions
+            .iter()
+            .filter(|&&(_, ref flag)| flag.get())
+            .map(|&(ref sub, _)| Subscription(sub.0))
+            .collect();
+        for subscription in exhausted {
+            debug!("Limited subscription {:?} has run out.", subscription);
+            self.unsubscribe(subscription);
+        }
+    }
+
+    /// Changes the time-to-live of outgoing multicast packets.
+    ///
+    /// A TTL of 0 keeps messages on this host by sending them on the loopback
+    /// interface, while 1 allows them onto the local network. Instances
+    /// created with a TTL of 0 also receive messages sent on the loopback
+    /// interface. Messages that have already been published are not
+    /// affected. Fails with `SettingError::InvalidTtl` if `ttl` is larger
+    /// than 255, or `SettingError::NotSupported` if the provider doesn't use
+    /// multicast.
+    pub fn set_ttl(&mut self, ttl: u32) -> Result<(), SettingError> {
+        if ttl > 255 {
+            return Err(SettingError::InvalidTtl(ttl));
+        }
+        provider!(self.set_ttl(ttl))
+    }
+
+    /// Returns the sequence number that the next published message will use.
+    ///
+    /// This is an advanced API, meant for testing how messages are split into
+    /// fragments, reassembled, and deduplicated. Returns `None` if the
+    /// provider doesn't number its messages.
+    pub fn sequence_number(&self) -> Option<u32> {
+        provider!(&self.sequence_number())
+    }
+
+    /// Sets the sequence number that the next published message will use.
+    ///
+    /// This is an advanced API, meant for testing. Receivers that
+    /// deduplicate messages may drop the next ones if the number is moved
+    /// backwards. Fails with `SettingError::NotSupported` if the provider
+    /// doesn't number its messages.
+    pub fn set_sequence_number(&mut self, sequence_number: u32) -> Result<(), SettingError> {
+        provider!(self.set_sequence_number(sequence_number))
+    }
+
+    /// Stops delivering messages until `Lcm::resume` is called.
+    ///
+    /// This is meant for known busy periods, such as a long computation,
+    /// during which the subscriptions' queues would otherwise overflow.
+    /// Messages that arrive while paused are dropped rather than buffered,
+    /// although they still count towards `Lcm::stats`. Messages that were
+    /// queued before pausing are still delivered by the next `handle`.
+    /// Subscriptions are not affected, and can be added or removed while
+    /// paused.
+    pub fn pause(&mut self) {
+        provider!(self.pause())
+    }
+
+    /// Resumes delivering messages after a call to `Lcm::pause`.
+    pub fn resume(&mut self) {
+        provider!(self.resume())
+    }
+
+    /// Returns the features supported by the backing provider.
+    pub fn capabilities(&self) -> Capabilities {
+        provider!(&self.capabilities())
+    }
+
+    /// Returns the multicast settings that the provider is using, or `None`
+    /// if it does not use the network.
+    pub fn multicast_info(&self) -> Option<MulticastInfo> {
+        provider!(&self.multicast_info())
+    }
+
+    /// Returns the number of datagrams (or equivalent) that the provider
+    /// would use to publish a message of `message_size` bytes on the channel.
+    ///
+    /// The size is that of the encoded message including its hash, as given
+    /// by `Message::encoded_size`.
+    pub fn fragment_count(&self, channel: &str, message_size: usize) -> usize {
+        provider!(&self.fragment_count(channel, message_size))
+    }
+
+    /// Returns statistics about the messages received by the provider.
+    pub fn stats(&self) -> Stats {
+        provider!(&self.stats())
+    }
+
+    /// Returns every channel that a message has been received on, along with
+    /// the number of messages and when the most recent one arrived.
+    ///
+    /// This is meant for noticing channels that have gone quiet. Channels
+    /// are included whether or not anything is subscribed to them, and are
+    /// sorted by name.
+    pub fn channel_activity(&self) -> Vec<(String, u64, Instant)> {
+        let Stats {
+            messages_received,
+            last_received,
+            ..
+        } = self.stats();
+        messages_received
+            .into_iter()
+            .filter_map(|(channel, count)| {
+                let last = *last_received.get(&channel)?;
+                Some((channel, count, last))
+            })
+            .collect()
+    }
+
+    /// Formats the statistics in the Prometheus text exposition format.
+    #[cfg(feature = "metrics")]
+    pub fn metrics_text(&self) -> String {
+        self.stats().to_prometheus()
+    }
+} // impl Lcm
+#[cfg(any(feature = "udpm", feature = "file"))]
+impl<'a> Drop for Lcm<'a> {
+    fn drop(&mut self) {
+        if let Err(e) = provider!(self.flush()) {
+            warn!("Failed to flush the provider: {}", e);
+        }
+    }
+}
+
+/// The features supported by a provider.
+///
+/// Since the provider is chosen by the LCM URL, this allows generic code to
+/// adapt to whichever provider was configured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The provider can jump to a different point in time, e.g., in a log.
+    pub can_seek: bool,
+
+    /// Messages can be published through the provider.
+    pub can_publish: bool,
+
+    /// Messages can be received through the provider.
+    pub can_subscribe: bool,
+
+    /// The provider sends and receives messages using network addresses.
+    pub has_addressing: bool,
+}
+
+/// The multicast settings of a network provider.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MulticastInfo {
+    /// The multicast group that messages are sent to.
+    pub group: IpAddr,
+
+    /// The port that messages are sent to and received on.
+    pub port: u16,
+
+    /// The time-to-live of outgoing multicast packets.
+    pub ttl: u32,
+
+    /// Every multicast group that the socket joined, starting with `group`.
+    ///
+    /// This is empty if the provider was given an existing socket, since
+    /// the groups it is a member of can't be queried.
+    pub groups: Vec<IpAddr>,
+}
+
+/// Details about how a message was received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageMeta {
+    /// The time at which the message was read from the socket.
+    ///
+    /// For messages that were split into fragments, this is the time at which
+    /// the last fragment arrived.
+    pub received: Instant,
+
+    /// The address the message was sent from.
+    pub sender: SocketAddr,
+
+    /// The message was reassembled from several fragments.
+    ///
+    /// This is the case for any message too large to fit in a single
+    /// datagram.
+    pub fragmented: bool,
+
+    /// The number of datagrams the message was sent in.
+    pub fragment_count: u16,
+}
+
+/// Statistics about the messages received by a provider.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of datagrams received.
+    pub datagrams_received: u64,
+
+    /// The number of datagrams that were malformed or duplicates.
+    pub datagrams_dropped: u64,
+
+    /// The total size of the received datagrams.
+    pub bytes_received: u64,
+
+    /// The number of complete messages received on each channel, whether or
+    /// not anything was subscribed to it.
+    ///
+    /// At most 1024 channels are kept. Once there are that many, the channel that has gone the longest without a message is
+    /// dropped to make room for a new one, and its count starts over if it
+    /// comes back.
+    pub messages_received: BTreeMap<String, u64>,
+
+    /// When the most recent complete message was received on each channel.
+    ///
+    /// This tracks the same channels as `messages_received`.
+    pub last_received: BTreeMap<String, Instant>,
+}
+impl Stats {
+    /// Records a complete message received on the channel.
+    fn record_message(&mut self, channel: &str, received: Instant) {
+        if let Some(count) = self.messages_received.get_mut(channel) {
+            *count += 1;
+            self.last_received.insert(channel.into(), received);
+            return;
+        }
+
+        if self.messages_received.len() >= MAX_TRACKED_CHANNELS {
+            let oldest = self.last_received
+                .iter()
+                .min_by_key(|&(_, &last)| last)
+                .map(|(channel, _)| channel.clone());
+            if let Some(oldest) = oldest {
+                self.messages_received.remove(&oldest);
+                self.last_received.remove(&oldest);
+            }
+        }
+        self.messages_received.insert(channel.into(), 1);
+        self.last_received.insert(channel.into(), received);
+    }
+}
+#[cfg(feature = "metrics")]
+impl Stats {
+    /// Formats the statistics in the Prometheus text exposition format.
+    fn to_prometheus(&self) -> String {
+        use std::fmt::Write;
+
+        let mut text = String::new();
+        let counters = [
+            (
+                "lcm_datagrams_received_total",
+                "Datagrams received by the provider.",
+                self.datagrams_received,
+            ),
+            (
+                "lcm_datagrams_dropped_total",
+                "Datagrams dropped because they were malformed or duplicates.",
+                self.datagrams_dropped,
+            ),
+            (
+                "lcm_received_bytes_total",
+                "Bytes received by the provider.",
+                self.bytes_received,
+            ),
+        ];
+        for &(name, help, value) in &counters {
+            writeln!(text, "# HELP {} {}", name, help).unwrap();
+            writeln!(text, "# TYPE {} counter", name).unwrap();
+            writeln!(text, "{} {}", name, value).unwrap();
+        }
+
+        writeln!(
+            text,
+            "# HELP lcm_messages_received_total Complete messages received on each channel."
+        ).unwrap();
+        writeln!(text, "# TYPE lcm_messages_received_total counter").unwrap();
+        for (channel, count) in &self.messages_received {
+            let channel = channel
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            writeln!(
+                text,
+                "lcm_messages_received_total{{channel=\"{}\"}} {}",
+                channel, count
+            ).unwrap();
+        }
+
+        text
+    }
+}
+
+/// Errors that can happen during the trampoline closure.
+#[derive(Debug, Fail)]
+pub enum TrampolineError {
+    /// The channel was closed.
+    ///
+    /// This generally signifies that the user unsubscribed from the channel.
+    #[fail(display = "Unsubscribed from the channel")]
+    MessageChannelClosed,
+
+    /// There was a decoding error.
+    #[fail(display = "Unable to decode message: {}", _0)]
+    Decode(#[cause] DecodeError),
+}
+impl From<DecodeError> for TrampolineError {
+    fn from(err: DecodeError) -> Self {
+        TrampolineError::Decode(err)
+    }
+}
+
+/// Returns how long after the first event of a log another event should be
+/// replayed, given their timestamps in microseconds.
+///
+/// The recorded delay is divided by `speed`, which must be positive. An
+/// infinite speed replays every event immediately.
+fn replay_offset(first_timestamp: i64, timestamp: i64, speed: f64) -> Duration {
+    let offset = timestamp.saturating_sub(first_timestamp).max(0) as f64 / speed;
+    Duration::from_micros(offset as u64)
+}
+
+/// Turns a subscription's pattern into one that only matches channels with
+/// the prefix.
+///
+/// The pattern is matched right after the prefix, so a `^` at the start of
+/// each top level alternative is dropped, and the group keeps the prefix in
+/// front of all of them.
+fn prefix_pattern(prefix: &str, pattern: &str) -> String {
+    let mut alternatives = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut class_depth = 0;
+    let mut chars = pattern.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => {
+                class_depth += 1;
+                // A `]` right after the opening bracket is a literal.
+                if chars.peek().map(|&(_, c)| c) == Some('^') {
+                    chars.next();
+                }
+                if chars.peek().map(|&(_, c)| c) == Some(']') {
+                    chars.next();
+                }
+            }
+            ']' if class_depth > 0 => class_depth -= 1,
+            _ if class_depth > 0 => {}
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '|' if depth == 0 => {
+                alternatives.push(&pattern[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    alternatives.push(&pattern[start..]);
+
+    let alternatives: Vec<_> = alternatives
+        .into_iter()
+        .map(|alternative| alternative.strip_prefix('^').unwrap_or(alternative))
+        .collect();
+    format!("^{}(?:{})", regex::escape(prefix), alternatives.join("|"))
+}
+
+/// Checks that a message can be published by any provider.
+fn check_publish(channel: &str, buffer: &[u8]) -> Result<(), PublishError> {
+    if channel.len() > MAX_CHANNEL_NAME_LENGTH {
+        warn!("The channel name was too long. Unable to publish message.");
+        return Err(PublishError::ProviderIssue);
+    }
+
+    if buffer.len() > MAX_MESSAGE_SIZE {
+        warn!("The message was too large to publish.");
+        return Err(PublishError::ProviderIssue);
+    }
+
+    Ok(())
+}
 
 /// A subscription to an LCM topic.
 ///
@@ -286,6 +1455,52 @@ impl From<DecodeError> for TrampolineError {
 #[derive(Debug, PartialEq, Eq)]
 pub struct Subscription(u32);
 
+/// Options for replaying a log with `Lcm::replay_log_with`.
+#[derive(Clone, Debug)]
+pub struct ReplayOptions {
+    /// How fast to replay the log relative to the recorded timing.
+    ///
+    /// This is 1.0 by default, and must be positive. Use `f64::INFINITY` to
+    /// publish the events as quickly as possible.
+    pub speed: f64,
+    /// A regular expression that limits which channels are replayed.
+    pub channel: Option<String>,
+    /// If true, start again from the beginning of the log every time the end
+    /// is reached. The replay then only stops because of an error.
+    pub repeat: bool,
+}
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        ReplayOptions {
+            speed: 1.0,
+            channel: None,
+            repeat: false,
+        }
+    }
+}
+
+/// The most recent message received by `Lcm::subscribe_latest`.
+pub struct Latest<M>(Rc<RefCell<Option<M>>>);
+impl<M> Latest<M> {
+    /// Returns a copy of the most recent message, if one has been received.
+    pub fn get(&self) -> Option<M>
+    where
+        M: Clone,
+    {
+        self.0.borrow().clone()
+    }
+
+    /// Removes and returns the most recent message, if one has been received.
+    pub fn take(&self) -> Option<M> {
+        self.0.borrow_mut().take()
+    }
+}
+impl<M> Clone for Latest<M> {
+    fn clone(&self) -> Self {
+        Latest(self.0.clone())
+    }
+}
+
 /// The backing providers for the `Lcm` type.
 enum Provider {
     /// The UDP Multicast provider.
@@ -297,31 +1512,1186 @@ enum Provider {
     File(FileProvider),
 }
 
-/// A type used to allow users to subscribe to raw bytes.
-struct RawBytes(Vec<u8>);
-impl Marshall for RawBytes {
-    fn encode(&self, _: &mut Write) -> Result<(), EncodeError> {
-        unimplemented!();
+/// A type used to allow users to subscribe to a message along with the raw
+/// bytes it was decoded from.
+///
+/// The bytes include the message hash, and the `Marshall` methods treat them
+/// the same way as the `Message` methods.
+struct TypedBytes<M>(M, Vec<u8>);
+impl<M: Message> Marshall for TypedBytes<M> {
+    fn encode(&self, buffer: &mut Write) -> Result<(), EncodeError> {
+        buffer.write_all(&self.1)?;
+        Ok(())
     }
 
-    fn decode(_: &mut Read) -> Result<Self, DecodeError> {
-        unimplemented!();
+    fn decode(buffer: &mut Read) -> Result<Self, DecodeError> {
+        let mut bytes = Vec::new();
+        buffer.read_to_end(&mut bytes)?;
+        let message = M::decode_with_hash(&mut &bytes[..])?;
+        Ok(TypedBytes(message, bytes))
     }
 
     fn size(&self) -> usize {
-        unimplemented!();
+        self.1.len()
     }
 }
-impl Message for RawBytes {
-    const HASH: u64 = 0;
+impl<M: Message> Message for TypedBytes<M> {
+    const HASH: u64 = M::HASH;
+
+    fn encoded_size(&self) -> usize {
+        self.size()
+    }
 
     fn encode_with_hash(&self) -> Result<Vec<u8>, EncodeError> {
-        Ok(self.0.clone())
+        Ok(self.1.clone())
     }
 
     fn decode_with_hash(buffer: &mut Read) -> Result<Self, DecodeError> {
-        let mut bytes = Vec::new();
-        buffer.read_to_end(&mut bytes)?;
-        Ok(RawBytes(bytes))
+        Marshall::decode(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use Raw;
+
+    test_message!(
+        /// A minimal message type for testing.
+        #[derive(Clone)]
+        Count,
+        0x1234_5678
+    );
+
+    fn udpm(port: u16) -> Lcm<'static> {
+        Lcm::with_lcm_url(&format!("udpm://239.255.76.67:{}?ttl=0", port)).unwrap()
+    }
+
+    #[test]
+    fn handle_timeout_with_no_traffic() {
+        let mut lcm = udpm(7670);
+        lcm.subscribe_raw(".*", 10, |_: &str, _: &[u8]| panic!("Unexpected message"))
+            .unwrap();
+
+        let received = lcm.handle_timeout(Duration::from_millis(100)).unwrap();
+        assert!(!received);
+    }
+
+    #[test]
+    fn handle_timeout_with_traffic() {
+        let mut lcm = udpm(7671);
+        let count = Rc::new(Cell::new(0));
+        {
+            let count = count.clone();
+            lcm.subscribe_raw("TEST", 10, move |_: &str, _: &[u8]| count.set(count.get() + 1))
+                .unwrap();
+        }
+
+        lcm.publish_raw("TEST", &[1, 2, 3]).unwrap();
+        let received = lcm.handle_timeout(Duration::from_millis(500)).unwrap();
+        assert!(received);
+        assert_eq!(count.get(), 1);
+    }
+
+    #[test]
+    fn try_handle() {
+        let mut lcm = udpm(7716);
+        let count = Rc::new(Cell::new(0));
+        {
+            let count = count.clone();
+            lcm.subscribe_raw("TEST", 10, move |_: &str, _: &[u8]| count.set(count.get() + 1))
+                .unwrap();
+        }
+        assert!(!lcm.try_handle().unwrap());
+
+        lcm.publish_raw("TEST", &[1, 2, 3]).unwrap();
+        let start = Instant::now();
+        while !lcm.try_handle().unwrap() {
+            assert!(start.elapsed() < Duration::from_millis(500));
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(count.get(), 1);
+        assert!(!lcm.try_handle().unwrap());
+    }
+
+    #[test]
+    fn multiple_groups() {
+        let mut lcm =
+            Lcm::with_lcm_url("udpm://239.255.76.67:7672?ttl=0&groups=239.255.76.68").unwrap();
+        let mut primary = Lcm::with_lcm_url("udpm://239.255.76.67:7672?ttl=0").unwrap();
+        let mut secondary = Lcm::with_lcm_url("udpm://239.255.76.68:7672?ttl=0").unwrap();
+
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            lcm.subscribe_raw("GROUP_.*", 10, move |chan: &str, _: &[u8]| {
+                received.borrow_mut().push(chan.to_owned())
+            }).unwrap();
+        }
+
+        primary.publish_raw("GROUP_PRIMARY", &[1]).unwrap();
+        secondary.publish_raw("GROUP_SECONDARY", &[2]).unwrap();
+        while received.borrow().len() < 2 {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+
+        let mut received = received.borrow().clone();
+        received.sort();
+        assert_eq!(received, ["GROUP_PRIMARY", "GROUP_SECONDARY"]);
+    }
+
+    #[test]
+    fn multicast_info() {
+        let lcm = Lcm::with_lcm_url("udpm://239.255.76.67:7702?ttl=2&groups=239.255.76.68")
+            .unwrap();
+        assert_eq!(
+            lcm.multicast_info(),
+            Some(MulticastInfo {
+                group: "239.255.76.67".parse().unwrap(),
+                port: 7702,
+                ttl: 2,
+                groups: vec![
+                    "239.255.76.67".parse().unwrap(),
+                    "239.255.76.68".parse().unwrap(),
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn channel_prefix() {
+        let mut prefixed = udpm(7712).channel_prefix("RUN_1/");
+        let mut plain = udpm(7712);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            prefixed
+                .subscribe_raw("^PREFIXED$", 10, move |chan: &str, _: &[u8]| {
+                    received.borrow_mut().push(chan.to_owned())
+                })
+                .unwrap();
+        }
+        plain
+            .subscribe_raw("^PREFIXED$", 10, |_: &str, _: &[u8]| panic!("Unexpected message"))
+            .unwrap();
+        {
+            let received = received.clone();
+            prefixed
+                .subscribe_raw("A|B", 10, move |chan: &str, _: &[u8]| {
+                    received.borrow_mut().push(chan.to_owned())
+                })
+                .unwrap();
+        }
+        assert_eq!(prefixed.would_match("PREFIXED"), ["^PREFIXED$"]);
+        assert_eq!(prefixed.would_match("B"), ["A|B"]);
+
+        // Every alternative only matches prefixed channels.
+        plain.publish_raw("B", &[2]).unwrap();
+        prefixed.publish_raw("PREFIXED", &[1]).unwrap();
+        while received.borrow().is_empty() {
+            assert!(prefixed.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        prefixed.handle_timeout(Duration::from_millis(100)).unwrap();
+        assert_eq!(*received.borrow(), ["PREFIXED"]);
+        plain.handle_timeout(Duration::from_millis(100)).unwrap();
+    }
+
+    #[test]
+    fn prefix_pattern_alternatives() {
+        assert_eq!(prefix_pattern("RUN.1/", "^A|^B"), "^RUN\\.1/(?:A|B)");
+        assert_eq!(prefix_pattern("P/", "^(?:^A|B)$"), "^P/(?:(?:^A|B)$)");
+        assert_eq!(prefix_pattern("P/", "[|^]A|^B"), "^P/(?:[|^]A|B)");
+        assert_eq!(prefix_pattern("P/", "[]|]A|\\|^B"), "^P/(?:[]|]A|\\|^B)");
+
+        let regex = Regex::new(&prefix_pattern("P/", "^A|^B")).unwrap();
+        assert!(regex.is_match("P/B"));
+        assert!(!regex.is_match("B"));
+        assert!(!regex.is_match("P/XB"));
+    }
+
+    #[test]
+    fn unsubscribe_releases_backend() {
+        use std::sync::Arc;
+
+        let mut lcm = udpm(7715);
+        let token = Arc::new(());
+        let held = token.clone();
+        let subscription = lcm.subscribe_filtered(
+            "^QUIET$",
+            1,
+            move |_: &Count| {
+                let _ = &held;
+                true
+            },
+            |_: &str, _: Count| {},
+        ).unwrap();
+        assert_eq!(Arc::strong_count(&token), 2);
+
+        // Nothing is ever published on the channel, but the backend still
+        // drops the predicate.
+        lcm.unsubscribe(subscription);
+        let start = Instant::now();
+        while Arc::strong_count(&token) > 1 {
+            assert!(start.elapsed() < Duration::from_secs(1));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn set_ttl() {
+        let mut lcm = udpm(7704);
+        assert_eq!(lcm.multicast_info().unwrap().ttl, 0);
+
+        lcm.set_ttl(1).unwrap();
+        assert_eq!(lcm.multicast_info().unwrap().ttl, 1);
+
+        match lcm.set_ttl(256) {
+            Err(SettingError::InvalidTtl(256)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert_eq!(lcm.multicast_info().unwrap().ttl, 1);
+    }
+
+    #[test]
+    fn loopback_instances() {
+        let mut sender = udpm(7708);
+        let mut receiver = udpm(7708);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            receiver
+                .subscribe("^LOOPBACK$", 10, move |_: &str, m: Count| {
+                    received.borrow_mut().push(m.0)
+                })
+                .unwrap();
+        }
+
+        sender.publish("LOOPBACK", &Count(5)).unwrap();
+        assert!(receiver.handle_timeout(Duration::from_millis(500)).unwrap());
+        assert_eq!(*received.borrow(), [5]);
+    }
+
+    #[test]
+    fn channel_activity() {
+        let mut lcm = udpm(7709);
+        lcm.subscribe_raw("^ACTIVE$", 10, |_: &str, _: &[u8]| {}).unwrap();
+        assert!(lcm.channel_activity().is_empty());
+
+        let before = Instant::now();
+        lcm.publish_raw("ACTIVE", &[1]).unwrap();
+        lcm.publish_raw("ACTIVE", &[2]).unwrap();
+        while lcm.stats().messages_received.get("ACTIVE") != Some(&2) {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        thread::sleep(Duration::from_millis(50));
+
+        let activity = lcm.channel_activity();
+        assert_eq!(activity.len(), 1);
+        let (ref channel, count, last) = activity[0];
+        assert_eq!(channel, "ACTIVE");
+        assert_eq!(count, 2);
+        assert!(last >= before);
+        assert!(last.elapsed() >= Duration::from_millis(50));
+        assert!(last.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn udpm_capabilities() {
+        let lcm = udpm(7673);
+        assert_eq!(
+            lcm.capabilities(),
+            Capabilities {
+                can_seek: false,
+                can_publish: true,
+                can_subscribe: true,
+                has_addressing: true,
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_group() {
+        match Lcm::with_lcm_url("udpm://239.255.76.67:7672?groups=239.255.76") {
+            Err(InitError::InvalidMulticastGroup(_)) => {}
+            Err(e) => panic!("Unexpected error: {}", e),
+            Ok(_) => panic!("Invalid group was accepted"),
+        }
+    }
+
+    #[test]
+    fn typed_bytes_marshall() {
+        let bytes = Count(7).encode_with_hash().unwrap();
+        let typed = TypedBytes::<Count>::decode(&mut &bytes[..]).unwrap();
+        assert_eq!((typed.0).0, 7);
+        assert_eq!(typed.size(), 12);
+        assert_eq!(typed.encoded_size(), 12);
+
+        let mut buffer = Vec::new();
+        typed.encode(&mut buffer).unwrap();
+        assert_eq!(buffer, bytes);
+        assert_eq!(buffer, typed.encode_with_hash().unwrap());
+
+        assert!(TypedBytes::<Count>::decode(&mut &bytes[..8]).is_err());
+    }
+
+    #[test]
+    fn subscribe_limited() {
+        let mut lcm = udpm(7674);
+        let count = Rc::new(Cell::new(0));
+        {
+            let count = count.clone();
+            lcm.subscribe_limited("LIMITED", 10, 3, move |_: &str, _: Raw| {
+                count.set(count.get() + 1)
+            }).unwrap();
+        }
+
+        for i in 0..5 {
+            lcm.publish_raw("LIMITED", &[i; 8]).unwrap();
+        }
+        while lcm.handle_timeout(Duration::from_millis(200)).unwrap() {}
+
+        assert_eq!(count.get(), 3);
+        assert!(lcm.subscriptions.is_empty());
+        assert!(lcm.limited_subscriptions.is_empty());
+    }
+
+    #[test]
+    fn ordered_delivery() {
+        let mut lcm = Lcm::with_lcm_url("udpm://239.255.76.67:7675?ttl=0&ordered=true").unwrap();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            lcm.subscribe_raw("ORDERED", 10, move |_: &str, m: &[u8]| {
+                received.borrow_mut().push(m[0])
+            }).unwrap();
+        }
+
+        // Give the backend a chance to time out on the socket first.
+        ::std::thread::sleep(Duration::from_millis(250));
+        for i in 0..3 {
+            lcm.publish_raw("ORDERED", &[i]).unwrap();
+        }
+        while received.borrow().len() < 3 {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        assert_eq!(*received.borrow(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn handle_bounded() {
+        let mut lcm = udpm(7696);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        for &channel in &["BOUNDED_A", "BOUNDED_B"] {
+            let received = received.clone();
+            lcm.subscribe_raw(channel, 20, move |chan: &str, _: &[u8]| {
+                received.borrow_mut().push(chan.to_owned())
+            }).unwrap();
+        }
+
+        for _ in 0..10 {
+            lcm.publish_raw("BOUNDED_A", &[]).unwrap();
+            lcm.publish_raw("BOUNDED_B", &[]).unwrap();
+        }
+        // Let the backend queue everything before dispatching.
+        ::std::thread::sleep(Duration::from_millis(250));
+
+        assert!(lcm.handle_bounded(3, Duration::from_millis(500)).unwrap());
+        assert_eq!(received.borrow().len(), 3);
+        assert!(lcm.handle_bounded(3, Duration::from_millis(500)).unwrap());
+        assert_eq!(received.borrow().len(), 6);
+
+        // The second call started with the subscription that the first one
+        // didn't get to.
+        let count_b = received.borrow().iter().filter(|c| *c == "BOUNDED_B").count();
+        assert_eq!(count_b, 3);
+
+        while received.borrow().len() < 20 {
+            assert!(lcm.handle_bounded(3, Duration::from_millis(500)).unwrap());
+        }
+        assert_eq!(received.borrow().len(), 20);
+    }
+
+    #[test]
+    fn handle_bounded_exact() {
+        let mut lcm = udpm(7717);
+        let count = Rc::new(Cell::new(0));
+        {
+            let count = count.clone();
+            lcm.subscribe_raw("EXACT", 10, move |_: &str, _: &[u8]| count.set(count.get() + 1))
+                .unwrap();
+        }
+        assert!(!lcm.handle_bounded(0, Duration::from_millis(500)).unwrap());
+
+        for _ in 0..3 {
+            lcm.publish_raw("EXACT", &[]).unwrap();
+        }
+        ::std::thread::sleep(Duration::from_millis(250));
+
+        // Draining the queue exactly leaves nothing pending.
+        assert!(lcm.handle_bounded(3, Duration::from_millis(500)).unwrap());
+        assert_eq!(count.get(), 3);
+        assert!(!lcm.handle_timeout(Duration::from_millis(100)).unwrap());
+        assert_eq!(count.get(), 3);
+    }
+
+    #[test]
+    fn replay_log() {
+        use logfile::write_event;
+        use LogEvent;
+
+        let event = |event_number: i64, channel: &str| LogEvent {
+            event_number,
+            timestamp: 1_500_000_000_000_000 + event_number * 50_000,
+            channel: channel.into(),
+            data: vec![event_number as u8],
+        };
+        let mut log = Vec::new();
+        write_event(&mut log, &event(0, "REPLAY_A")).unwrap();
+        write_event(&mut log, &event(1, "UNREPLAYED")).unwrap();
+        write_event(&mut log, &event(2, "REPLAY_B")).unwrap();
+        write_event(&mut log, &event(3, &"REPLAY_".repeat(10))).unwrap();
+        write_event(&mut log, &event(4, "REPLAY_A")).unwrap();
+        let path = env::temp_dir().join(format!("lcm_replay_log_{}.lcmlog", process::id()));
+        fs::write(&path, log).unwrap();
+
+        let mut lcm = udpm(7698);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            lcm.subscribe_raw("^REPLAY_.*$", 10, move |chan: &str, m: &[u8]| {
+                received.borrow_mut().push((chan.to_owned(), m[0]))
+            }).unwrap();
+        }
+
+        // The three events span 200ms of recorded time.
+        let options = ReplayOptions {
+            speed: 2.0,
+            channel: Some("^REPLAY_".into()),
+            ..ReplayOptions::default()
+        };
+        let start = Instant::now();
+        assert_eq!(lcm.replay_log_with(&path, &options).unwrap(), 3);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        fs::remove_file(&path).unwrap();
+
+        while received.borrow().len() < 3 {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        assert_eq!(
+            *received.borrow(),
+            [
+                ("REPLAY_A".to_owned(), 0),
+                ("REPLAY_B".to_owned(), 2),
+                ("REPLAY_A".to_owned(), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn replay_log_repeat() {
+        use logfile::write_event;
+        use LogEvent;
+
+        let mut log = Vec::new();
+        for &(event_number, timestamp) in &[(0, 0), (1, 400_000)] {
+            write_event(&mut log, &LogEvent {
+                event_number,
+                timestamp,
+                channel: "REPEAT".into(),
+                data: vec![event_number as u8],
+            }).unwrap();
+        }
+        let path = env::temp_dir().join(format!("lcm_replay_repeat_{}.lcmlog", process::id()));
+        fs::write(&path, log).unwrap();
+
+        let mut lcm = udpm(7719);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            lcm.subscribe_raw("^REPEAT$", 10, move |_: &str, m: &[u8]| {
+                received.borrow_mut().push(m[0])
+            }).unwrap();
+        }
+
+        // Each pass takes 400ms. Removing the log halfway through the second
+        // one lets it finish, but stops the replay when it starts again.
+        let remover = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(600));
+                fs::remove_file(&path).unwrap();
+            })
+        };
+        let options = ReplayOptions {
+            repeat: true,
+            ..ReplayOptions::default()
+        };
+        match lcm.replay_log_with(&path, &options) {
+            Err(Error::Log(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        remover.join().unwrap();
+
+        while received.borrow().len() < 4 {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        assert_eq!(*received.borrow(), [0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn replay_invalid_speed() {
+        let mut lcm = udpm(7718);
+        let path = env::temp_dir().join(format!("lcm_replay_speed_{}.lcmlog", process::id()));
+        for &speed in &[0.0, -1.0, f64::NAN] {
+            let options = ReplayOptions {
+                speed,
+                ..ReplayOptions::default()
+            };
+            match lcm.replay_log_with(&path, &options) {
+                Err(Error::InvalidReplaySpeed(_)) => {}
+                res => panic!("Unexpected result: {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn replay_is_byte_identical() {
+        use logfile::write_event;
+        use LogEvent;
+
+        let mut lcm = udpm(7699);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            lcm.subscribe_raw("^VERBATIM$", 10, move |_: &str, m: &[u8]| {
+                received.borrow_mut().push(m.to_owned())
+            }).unwrap();
+        }
+
+        // Record a message the way a logger would...
+        lcm.publish("VERBATIM", &Count(42)).unwrap();
+        while received.borrow().is_empty() {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        let recorded = received.borrow_mut().remove(0);
+        assert_eq!(recorded, Count(42).encode_with_hash().unwrap());
+
+        let mut log = Vec::new();
+        write_event(&mut log, &LogEvent {
+            event_number: 0,
+            timestamp: 0,
+            channel: "VERBATIM".into(),
+            data: recorded.clone(),
+        }).unwrap();
+        let path = env::temp_dir().join(format!("lcm_verbatim_{}.lcmlog", process::id()));
+        fs::write(&path, log).unwrap();
+
+        // ...and check that replaying it sends exactly the same bytes.
+        assert_eq!(lcm.replay_log(&path, 1.0).unwrap(), 1);
+        fs::remove_file(&path).unwrap();
+        while received.borrow().is_empty() {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        assert_eq!(*received.borrow(), [recorded]);
+    }
+
+    #[test]
+    fn resolve_url_from_env() {
+        use std::collections::HashMap;
+
+        let home = env::temp_dir().join(format!("lcm_resolve_url_{}", process::id()));
+        fs::create_dir_all(home.join(".config").join("lcm")).unwrap();
+        let mut vars = HashMap::new();
+        vars.insert("HOME", home.clone().into_os_string());
+        let resolve =
+            |vars: &HashMap<&str, OsString>| resolve_url_with(|name| vars.get(name).cloned());
+
+        vars.insert("LCM_DEFAULT_URL", "udpm://239.255.76.67:7676?ttl=1".into());
+        assert_eq!(resolve(&vars), "udpm://239.255.76.67:7676?ttl=1");
+
+        vars.insert("LCM_DEFAULT_URL", "".into());
+        assert_eq!(resolve(&vars), default_url());
+
+        vars.remove("LCM_DEFAULT_URL");
+        assert_eq!(resolve(&vars), default_url());
+        assert_eq!(default_url(), "udpm://239.255.76.67:7667?ttl=0");
+
+        // The dotfile is used when there is no URL in the environment...
+        fs::write(home.join(".lcmrc"), "# Comment\n\nudpm://239.255.76.67:7001?ttl=0\n").unwrap();
+        assert_eq!(resolve(&vars), "udpm://239.255.76.67:7001?ttl=0");
+
+        // ...but the XDG config file takes precedence over it...
+        fs::write(home.join(".config/lcm/url"), "udpm://239.255.76.67:7002?ttl=0").unwrap();
+        assert_eq!(resolve(&vars), "udpm://239.255.76.67:7002?ttl=0");
+
+        // ...and is found through XDG_CONFIG_HOME when that is set.
+        let xdg = home.join("xdg");
+        fs::create_dir_all(xdg.join("lcm")).unwrap();
+        vars.insert("XDG_CONFIG_HOME", xdg.clone().into_os_string());
+        assert_eq!(resolve(&vars), "udpm://239.255.76.67:7001?ttl=0");
+        fs::write(xdg.join("lcm/url"), "udpm://239.255.76.67:7003?ttl=0\n").unwrap();
+        assert_eq!(resolve(&vars), "udpm://239.255.76.67:7003?ttl=0");
+
+        // The environment variable still overrides everything.
+        vars.insert("LCM_DEFAULT_URL", "udpm://239.255.76.67:7676?ttl=1".into());
+        assert_eq!(resolve(&vars), "udpm://239.255.76.67:7676?ttl=1");
+
+        fs::remove_dir_all(&home).unwrap();
+        vars.remove("LCM_DEFAULT_URL");
+        assert_eq!(resolve(&vars), default_url());
+    }
+
+    #[test]
+    fn validate_url_missing_port() {
+        match validate_url("udpm://239.255.76.67?ttl=0") {
+            Err(InitError::InvalidLcmUrl(_)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert!(Lcm::with_lcm_url("udpm://239.255.76.67?ttl=0").is_err());
+    }
+
+    #[test]
+    fn validate_url_unknown_option() {
+        assert!(validate_url("udpm://239.255.76.67:7667?ttl=0").is_ok());
+        assert!(validate_url("udpm://239.255.76.67:7667?tll=0").is_ok());
+        match validate_url("bogus://239.255.76.67:7667") {
+            Err(InitError::UnknownProvider(ref s)) if s == "bogus" => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn unknown_provider() {
+        match Lcm::with_lcm_url("bogus://239.255.76.67:7667") {
+            Err(InitError::UnknownProvider(ref s)) if s == "bogus" => {}
+            Err(e) => panic!("Unexpected error: {:?}", e),
+            Ok(_) => panic!("Unexpectedly created an instance"),
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "file"))]
+    fn disabled_file_provider() {
+        match Lcm::with_lcm_url("file:///tmp/lcm.log") {
+            Err(InitError::ProviderDisabled(ref s)) if s == "file" => {}
+            Err(e) => panic!("Unexpected error: {:?}", e),
+            Ok(_) => panic!("Unexpectedly created an instance"),
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "compression"))]
+    fn disabled_compression() {
+        match Lcm::with_lcm_url("udpm://239.255.76.67:7700?compress=true") {
+            Err(InitError::FeatureDisabled(ref s)) if s == "compression" => {}
+            Err(e) => panic!("Unexpected error: {:?}", e),
+            Ok(_) => panic!("Unexpectedly created an instance"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compressed_loopback() {
+        let mut lcm = Lcm::with_lcm_url("udpm://239.255.76.67:7700?ttl=0&compress=true").unwrap();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            lcm.subscribe_raw("^COMPRESSED$", 10, move |_: &str, m: &[u8]| {
+                received.borrow_mut().push(m.to_vec())
+            }).unwrap();
+        }
+
+        let message: Vec<u8> = (0..100_000).map(|i| (i % 16) as u8).collect();
+        lcm.publish_raw("COMPRESSED", &message).unwrap();
+        while received.borrow().is_empty() {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        assert_eq!(*received.borrow(), [message]);
+
+        // Uncompressed, the message would have needed 73 datagrams.
+        assert!(lcm.stats().datagrams_received < 10);
+    }
+
+    #[test]
+    #[cfg(not(feature = "udpm"))]
+    fn disabled_udpm_provider() {
+        match Lcm::with_lcm_url("udpm://239.255.76.67:7667") {
+            Err(InitError::ProviderDisabled(ref s)) if s == "udpm" => {}
+            Err(e) => panic!("Unexpected error: {:?}", e),
+            Ok(_) => panic!("Unexpectedly created an instance"),
+        }
+    }
+
+    #[test]
+    fn forward_raw_messages() {
+        let mut lcm = udpm(7678);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        lcm.forward("^FORWARD_A$", "FORWARD_B").unwrap();
+        {
+            let received = received.clone();
+            lcm.subscribe_raw("^FORWARD_B$", 10, move |_: &str, m: &[u8]| {
+                received.borrow_mut().push(m.to_vec())
+            }).unwrap();
+        }
+
+        lcm.publish_raw("FORWARD_A", &[1, 2, 3]).unwrap();
+        while received.borrow().is_empty() {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        assert_eq!(*received.borrow(), [vec![1, 2, 3]]);
+    }
+
+    #[test]
+    fn subscribe_raw_typed() {
+        let mut lcm = udpm(7679);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            lcm.subscribe_raw_typed("^RAW_TYPED$", 10, move |_: &str, m: Count, bytes: &[u8]| {
+                received.borrow_mut().push((m.0, bytes.to_vec()))
+            }).unwrap();
+        }
+
+        lcm.publish("RAW_TYPED", &Count(42)).unwrap();
+        while received.borrow().is_empty() {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        assert_eq!(*received.borrow(), [(42, Count(42).encode_with_hash().unwrap())]);
+    }
+
+    #[test]
+    fn subscribe_latest() {
+        let mut lcm = udpm(7680);
+        let (_, latest) = lcm.subscribe_latest::<Count>("^LATEST$").unwrap();
+        assert!(latest.take().is_none());
+
+        for i in 0..10 {
+            lcm.publish("LATEST", &Count(i)).unwrap();
+        }
+        while latest.get().map(|m| m.0) != Some(9) {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        assert_eq!(latest.take().map(|m| m.0), Some(9));
+        assert!(latest.take().is_none());
+    }
+
+    #[test]
+    fn subscribe_timestamped() {
+        let mut lcm = udpm(7685);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            lcm.subscribe_timestamped("^STAMPED$", 10, move |_: &str, m: Count, t| {
+                received.borrow_mut().push((m.0, t))
+            }).unwrap();
+        }
+
+        let start = Instant::now();
+        lcm.publish("STAMPED", &Count(0)).unwrap();
+        lcm.publish("STAMPED", &Count(1)).unwrap();
+        while received.borrow().len() < 2 {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+
+        let received = received.borrow();
+        assert_eq!(received[0].0, 0);
+        assert_eq!(received[1].0, 1);
+        assert!(received[0].1 >= start);
+        assert!(received[1].1 >= received[0].1);
+        assert!(received[1].1 <= Instant::now());
+    }
+
+    #[test]
+    fn subscribe_filtered() {
+        let mut lcm = udpm(7697);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            let is_even = |m: &Count| m.0 % 2 == 0;
+            lcm.subscribe_filtered("^FILTERED$", 20, is_even, move |_: &str, m: Count| {
+                received.borrow_mut().push(m.0)
+            }).unwrap();
+        }
+
+        for i in 0..10 {
+            lcm.publish("FILTERED", &Count(i)).unwrap();
+        }
+        lcm.publish("FILTERED", &Count(100)).unwrap();
+        while received.borrow().last() != Some(&100) {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        assert_eq!(*received.borrow(), [0, 2, 4, 6, 8, 100]);
+    }
+
+    #[test]
+    fn publish_raw_message() {
+        let mut lcm = udpm(7701);
+        let counts = Rc::new(RefCell::new(Vec::new()));
+        let raws = Rc::new(RefCell::new(Vec::new()));
+        {
+            let counts = counts.clone();
+            lcm.subscribe("^RAW$", 10, move |_: &str, m: Count| counts.borrow_mut().push(m.0))
+                .unwrap();
+            let raws = raws.clone();
+            lcm.subscribe("^RAW$", 10, move |_: &str, m: Raw| raws.borrow_mut().push(m))
+                .unwrap();
+        }
+
+        let mut data = Vec::new();
+        Count(42).encode(&mut data).unwrap();
+        let raw = Raw {
+            hash: Count::HASH,
+            data,
+        };
+        lcm.publish("RAW", &raw).unwrap();
+        while raws.borrow().is_empty() || counts.borrow().is_empty() {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+
+        assert_eq!(*counts.borrow(), [42]);
+        assert_eq!(*raws.borrow(), [raw]);
+    }
+
+    #[test]
+    fn subscribe_router() {
+        let mut lcm = udpm(7705);
+        let counts = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut router = TypedRouter::new();
+            let counts = counts.clone();
+            router.route(move |_: &str, m: Count| counts.borrow_mut().push(m.0));
+            lcm.subscribe_router("^ROUTED$", 10, router).unwrap();
+        }
+
+        // Messages of other types are ignored.
+        lcm.publish("ROUTED", &Count(7)).unwrap();
+        lcm.publish("ROUTED", &Raw { hash: 0xBAD, data: vec![1, 2, 3, 4] }).unwrap();
+        lcm.publish("ROUTED", &Count(8)).unwrap();
+        while counts.borrow().len() < 2 {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        assert_eq!(*counts.borrow(), [7, 8]);
+    }
+
+    #[test]
+    fn would_match() {
+        let mut lcm = udpm(7706);
+        lcm.subscribe_raw("^POSE$", 10, |_: &str, _: &[u8]| {}).unwrap();
+        let all = lcm.subscribe_raw(".*", 10, |_: &str, _: &[u8]| {}).unwrap();
+        lcm.subscribe_raw("^POSE_.*", 10, |_: &str, _: &[u8]| {}).unwrap();
+        lcm.subscribe_raw("STATUS", 10, |_: &str, _: &[u8]| {}).unwrap();
+
+        assert_eq!(lcm.would_match("POSE"), ["^POSE$", ".*"]);
+        assert_eq!(lcm.would_match("POSE_ESTIMATE"), [".*", "^POSE_.*"]);
+        assert_eq!(lcm.would_match("ROBOT_STATUS"), [".*", "STATUS"]);
+
+        lcm.unsubscribe(all);
+        assert_eq!(lcm.would_match("POSE"), ["^POSE$"]);
+        assert!(lcm.would_match("OTHER").is_empty());
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let mut lcm = udpm(7703);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            lcm.subscribe("^PAUSED$", 10, move |_: &str, m: Count| received.borrow_mut().push(m.0))
+                .unwrap();
+        }
+
+        lcm.publish("PAUSED", &Count(0)).unwrap();
+        assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+
+        lcm.pause();
+        for i in 1..4 {
+            lcm.publish("PAUSED", &Count(i)).unwrap();
+        }
+        assert!(!lcm.handle_timeout(Duration::from_millis(200)).unwrap());
+
+        lcm.resume();
+        lcm.publish("PAUSED", &Count(4)).unwrap();
+        assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+
+        assert_eq!(*received.borrow(), [0, 4]);
+        assert_eq!(lcm.stats().messages_received["PAUSED"], 5);
+    }
+
+    #[test]
+    fn subscribe_with_meta() {
+        let mut lcm = udpm(7689);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            lcm.subscribe_with_meta("^META$", 10, move |_: &str, m: Raw, meta| {
+                received.borrow_mut().push((m.encoded_size(), meta))
+            }).unwrap();
+        }
+
+        lcm.publish_raw("META", &[0; 10]).unwrap();
+        lcm.publish_raw("META", &[0; 10_000]).unwrap();
+        while received.borrow().len() < 2 {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+
+        let received = received.borrow();
+        assert_eq!(received[0].0, 10);
+        assert!(!received[0].1.fragmented);
+        assert_eq!(received[0].1.fragment_count, 1);
+        assert_eq!(received[1].0, 10_000);
+        assert!(received[1].1.fragmented);
+        assert!(received[1].1.fragment_count > 1);
+        assert_eq!(received[0].1.sender, received[1].1.sender);
+    }
+
+    #[test]
+    fn fragment_count() {
+        let mut lcm = udpm(7690);
+        lcm.subscribe_raw("^FRAGMENTS$", 10, |_: &str, _: &[u8]| {}).unwrap();
+
+        let mut expected = 0;
+        // The largest small message, the smallest fragmented one, and one that
+        // exactly fills two fragments.
+        for &size in &[10, 1382, 1383, 2750, 10_000] {
+            expected += lcm.fragment_count("FRAGMENTS", size) as u64;
+            lcm.publish_raw("FRAGMENTS", &vec![0; size]).unwrap();
+        }
+        while lcm.handle_timeout(Duration::from_millis(200)).unwrap() {}
+        assert_eq!(lcm.stats().datagrams_received, expected);
+    }
+
+    #[test]
+    #[cfg(feature = "udpm")]
+    fn bind_addr() {
+        let url = "udpm://239.255.76.67:7691?ttl=0&bind_addr=127.0.0.1";
+        let mut lcm = Lcm::with_lcm_url(url).unwrap();
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            lcm.subscribe_raw("^BOUND$", 10, move |_: &str, m: &[u8]| {
+                received.borrow_mut().push(m.to_vec())
+            }).unwrap();
+        }
+
+        // A short datagram with sequence number 0, sent directly to the bound
+        // address.
+        let mut datagram = b"LC02\0\0\0\0BOUND\0".to_vec();
+        datagram.extend(b"payload");
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.send_to(&datagram, "127.0.0.1:7691").unwrap();
+
+        while received.borrow().is_empty() {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        assert_eq!(*received.borrow(), [b"payload".to_vec()]);
+    }
+
+    #[test]
+    fn subscription_hooks() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut lcm = udpm(7693);
+        {
+            let events = events.clone();
+            lcm.on_subscribe(move |_, channel| events.borrow_mut().push(format!("+{}", channel)));
+        }
+        {
+            let events = events.clone();
+            lcm.on_unsubscribe(move |_| events.borrow_mut().push("-".into()));
+        }
+
+        let a = lcm.subscribe_raw("^HOOK_A$", 10, |_: &str, _: &[u8]| {}).unwrap();
+        lcm.subscribe_raw("^HOOK_B$", 10, |_: &str, _: &[u8]| {}).unwrap();
+        lcm.unsubscribe(a);
+        lcm.unsubscribe_all();
+        assert_eq!(*events.borrow(), ["+^HOOK_A$", "+^HOOK_B$", "-", "-"]);
+    }
+
+    #[test]
+    fn self_test() {
+        let mut lcm = udpm(7694);
+        assert!(lcm.self_test(Duration::from_secs(1)).unwrap());
+        assert!(lcm.subscriptions.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "udpm")]
+    fn self_test_without_loopback() {
+        use std::net::Ipv4Addr;
+
+        let socket = UdpSocket::bind("0.0.0.0:7695").unwrap();
+        socket
+            .join_multicast_v4(&Ipv4Addr::new(239, 255, 76, 67), &Ipv4Addr::new(0, 0, 0, 0))
+            .unwrap();
+        socket.set_multicast_ttl_v4(0).unwrap();
+        socket.set_multicast_loop_v4(false).unwrap();
+        let mut lcm = Lcm::with_socket("udpm://239.255.76.67:7695", socket).unwrap();
+
+        assert!(!lcm.self_test(Duration::from_millis(200)).unwrap());
+        assert!(lcm.subscriptions.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "udpm")]
+    fn with_socket() {
+        use std::net::Ipv4Addr;
+
+        let socket = UdpSocket::bind("0.0.0.0:7686").unwrap();
+        socket
+            .join_multicast_v4(&Ipv4Addr::new(239, 255, 76, 67), &Ipv4Addr::new(0, 0, 0, 0))
+            .unwrap();
+        socket.set_multicast_ttl_v4(0).unwrap();
+        let mut lcm = Lcm::with_socket("udpm://239.255.76.67:7686", socket).unwrap();
+
+        let received = Rc::new(Cell::new(None));
+        {
+            let received = received.clone();
+            lcm.subscribe("^SOCKET$", 10, move |_: &str, m: Count| received.set(Some(m.0)))
+                .unwrap();
+        }
+
+        lcm.publish("SOCKET", &Count(7)).unwrap();
+        while received.get().is_none() {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        assert_eq!(received.get(), Some(7));
+    }
+
+    #[test]
+    #[cfg(feature = "udpm")]
+    fn with_socket_wrong_provider() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        match Lcm::with_socket("file:///tmp/lcm.log", socket) {
+            Err(InitError::SocketNotSupported(_)) | Err(InitError::ProviderDisabled(_)) => {}
+            Err(e) => panic!("Unexpected error: {}", e),
+            Ok(_) => panic!("A socket should only be accepted for UDPM"),
+        }
+    }
+
+    #[test]
+    fn reuse_port() {
+        let url = "udpm://239.255.76.67:7688?ttl=0&reuse_port=true";
+        let mut first = Lcm::with_lcm_url(url).unwrap();
+        let mut second = Lcm::with_lcm_url(url).unwrap();
+
+        let received = Rc::new(Cell::new(0));
+        for lcm in [&mut first, &mut second].iter_mut() {
+            let received = received.clone();
+            lcm.subscribe("^REUSE$", 10, move |_: &str, _: Count| {
+                received.set(received.get() + 1)
+            }).unwrap();
+        }
+
+        first.publish("REUSE", &Count(0)).unwrap();
+        for lcm in [&mut first, &mut second].iter_mut() {
+            let before = received.get();
+            while received.get() == before {
+                assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+            }
+        }
+        assert_eq!(received.get(), 2);
+    }
+
+    #[test]
+    fn subscribe_all() {
+        let mut lcm = udpm(7681);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            let channels = ["^ALL_A$", "^ALL_B$", "^ALL_C$"];
+            let subscriptions = lcm.subscribe_all(&channels, 10, move |chan: &str, m: Count| {
+                received.borrow_mut().push((chan.to_owned(), m.0))
+            }).unwrap();
+            assert_eq!(subscriptions.len(), 3);
+        }
+
+        for (i, channel) in ["ALL_A", "ALL_B", "ALL_C"].iter().enumerate() {
+            lcm.publish(channel, &Count(i as i32)).unwrap();
+        }
+        while received.borrow().len() < 3 {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+
+        let mut received = received.borrow().clone();
+        received.sort();
+        assert_eq!(
+            received,
+            [("ALL_A".into(), 0), ("ALL_B".into(), 1), ("ALL_C".into(), 2)]
+        );
+    }
+
+    #[test]
+    fn subscribe_all_invalid_channel() {
+        let mut lcm = udpm(7682);
+        let res = lcm.subscribe_all(&["^ALL_A$", "(", "^ALL_C$"], 10, |_: &str, _: Count| {});
+        assert!(res.is_err());
+        assert!(lcm.subscriptions.is_empty());
+    }
+
+    #[test]
+    fn stats_forget_quiet_channels() {
+        let mut stats = Stats::default();
+        let start = Instant::now();
+        for i in 0..MAX_TRACKED_CHANNELS {
+            let received = start + Duration::from_millis(i as u64);
+            stats.record_message(&format!("CHANNEL_{}", i), received);
+        }
+        stats.record_message("CHANNEL_0", start + Duration::from_secs(10));
+
+        // CHANNEL_1 has now gone the longest without a message.
+        stats.record_message("NEW", start + Duration::from_secs(11));
+        assert_eq!(stats.messages_received.len(), MAX_TRACKED_CHANNELS);
+        assert_eq!(stats.last_received.len(), MAX_TRACKED_CHANNELS);
+        assert_eq!(stats.messages_received.get("CHANNEL_0"), Some(&2));
+        assert_eq!(stats.messages_received.get("CHANNEL_1"), None);
+        assert_eq!(stats.messages_received.get("NEW"), Some(&1));
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn metrics_text() {
+        let mut stats = Stats {
+            datagrams_received: 4,
+            datagrams_dropped: 1,
+            bytes_received: 100,
+            messages_received: BTreeMap::new(),
+            last_received: BTreeMap::new(),
+        };
+        stats.messages_received.insert("POSE".into(), 2);
+        stats.messages_received.insert("ODD\"NAME".into(), 1);
+
+        let text = stats.to_prometheus();
+        let lines: Vec<_> = text.lines().collect();
+        assert!(lines.contains(&"# TYPE lcm_datagrams_received_total counter"));
+        assert!(lines.contains(&"lcm_datagrams_received_total 4"));
+        assert!(lines.contains(&"lcm_datagrams_dropped_total 1"));
+        assert!(lines.contains(&"lcm_received_bytes_total 100"));
+        assert!(lines.contains(&"# TYPE lcm_messages_received_total counter"));
+        assert!(lines.contains(&"lcm_messages_received_total{channel=\"POSE\"} 2"));
+        assert!(lines.contains(&"lcm_messages_received_total{channel=\"ODD\\\"NAME\"} 1"));
+        assert_eq!(lines.iter().filter(|l| l.starts_with("# HELP")).count(), 4);
+    }
+
+    #[test]
+    fn unsubscribe_all() {
+        let mut lcm = udpm(7684);
+        for channel in &["^NONE_A$", "^NONE_B$", "^NONE_C$"] {
+            lcm.subscribe_raw(channel, 10, |_: &str, _: &[u8]| panic!("Unexpected message"))
+                .unwrap();
+        }
+        lcm.forward("^NONE_A$", "NONE_B").unwrap();
+
+        lcm.unsubscribe_all();
+        assert!(lcm.subscriptions.is_empty());
+
+        for channel in &["NONE_A", "NONE_B", "NONE_C"] {
+            lcm.publish_raw(channel, &[1, 2, 3]).unwrap();
+        }
+        while lcm.handle_timeout(Duration::from_millis(200)).unwrap() {}
+    }
+
+    #[test]
+    fn available_providers() {
+        assert_eq!(super::available_providers().contains(&"udpm"), cfg!(feature = "udpm"));
+        assert_eq!(super::available_providers().contains(&"file"), cfg!(feature = "file"));
     }
 }
@@ -0,0 +1,644 @@
+use std::thread;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::sync::mpsc;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::borrow::Borrow;
+use std::path::PathBuf;
+use url::Url;
+
+use lcm::{replay_offset, Capabilities, MessageMeta, MulticastInfo, Stats, Subscription,
+          TrampolineError, SubscribeMsg};
+use logfile::write_event;
+use {LogEvent, LogReader};
+use error::*;
+
+/// The options recognized in the LCM URL.
+const OPTIONS: &[&str] = &["mode", "speed", "start_timestamp"];
+
+/// Whether the log is being played back or recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    /// Events are read from the log and passed to the subscriptions.
+    Read,
+    /// Published messages are appended to the log.
+    Write,
+}
+
+/// The settings parsed from an LCM URL.
+struct Options {
+    path: PathBuf,
+    mode: Mode,
+    speed: f64,
+    start_timestamp: i64,
+}
+impl Options {
+    /// Parses the path and query of a `file://` URL.
+    fn parse(url: &Url) -> Result<Self, InitError> {
+        let path = url.to_file_path()
+            .map_err(|_| InitError::InvalidOption("path".into()))?;
+
+        let mut mode = Mode::Read;
+        let mut speed = 1.0;
+        let mut start_timestamp = i64::MIN;
+        for (key, value) in url.query_pairs() {
+            match key.borrow() {
+                "mode" => {
+                    mode = match value.borrow() {
+                        "r" => Mode::Read,
+                        "w" => Mode::Write,
+                        _ => return Err(InitError::InvalidOption(key.to_string())),
+                    }
+                }
+                "speed" => {
+                    speed = match value.parse() {
+                        // Other LCM implementations use zero for "as fast as
+                        // possible", which is an infinite speed here.
+                        Ok(s) if s == 0.0 => f64::INFINITY,
+                        Ok(s) if s > 0.0 => s,
+                        _ => return Err(InitError::InvalidOption(key.to_string())),
+                    }
+                }
+                "start_timestamp" => {
+                    start_timestamp = value
+                        .parse()
+                        .map_err(|_| InitError::InvalidOption(key.to_string()))?
+                }
+                _ => warn!(
+                    "Ignoring unrecognized file option \"{}\". Recognized options are: {}.",
+                    key,
+                    OPTIONS.join(", ")
+                ),
+            }
+        }
+
+        Ok(Options {
+            path,
+            mode,
+            speed,
+            start_timestamp,
+        })
+    }
+}
+
+/// The log file provider.
+///
+/// In read mode (`mode=r`, the default), the events in the log are passed to
+/// the subscriptions in the order they were logged, with the recorded delays
+/// between them scaled by the `speed` option. A speed of zero or `inf` plays
+/// the log back as quickly as possible. Events logged before `start_timestamp`, in
+/// microseconds since the Unix epoch, are skipped. In write mode (`mode=w`),
+/// the log is truncated and every published message is appended to it.
+///
+/// There is no background thread, so events are only read while handling
+/// messages.
+pub struct FileProvider {
+    /// Whether the log is being read or written.
+    mode: Mode,
+
+    /// How fast the log is played back relative to the recorded timing.
+    speed: f64,
+
+    /// Events logged before this time are skipped.
+    start_timestamp: i64,
+
+    /// The log being played back, until its end is reached.
+    reader: Option<LogReader<BufReader<File>>>,
+
+    /// The log being recorded.
+    writer: Option<BufWriter<File>>,
+
+    /// An event that was read but is not due yet.
+    pending: Option<LogEvent>,
+
+    /// When the playback started, along with the timestamp of the first
+    /// event.
+    started: Option<(Instant, i64)>,
+
+    /// The number to give the next event written to the log.
+    next_event_number: i64,
+
+    /// Channel used to receive new subscriptions.
+    subscribe_rx: mpsc::Receiver<SubscribeMsg>,
+
+    /// Channel used to receive removed subscriptions.
+    unsubscribe_rx: mpsc::Receiver<Subscription>,
+
+    /// The current subscriptions.
+    subscriptions: Vec<SubscribeMsg>,
+
+    /// Events are dropped instead of passed to the subscriptions.
+    paused: bool,
+
+    /// Statistics about the events that have been read.
+    stats: Stats,
+
+    /// The function to call once the end of the log is reached.
+    exit_hook: Option<Box<FnOnce() + Send>>,
+}
+impl FileProvider {
+    /// Creates a new file provider using the given LCM URL.
+    pub fn new(
+        url: &Url,
+        subscribe_rx: mpsc::Receiver<SubscribeMsg>,
+        unsubscribe_rx: mpsc::Receiver<Subscription>,
+    ) -> Result<Self, InitError> {
+        let Options {
+            path,
+            mode,
+            speed,
+            start_timestamp,
+        } = Options::parse(url)?;
+
+        let (reader, writer) = match mode {
+            Mode::Read => {
+                debug!("Reading the log {}", path.display());
+                let file = File::open(&path)?;
+                (Some(LogReader::new(BufReader::new(file))), None)
+            }
+            Mode::Write => {
+                debug!("Writing the log {}", path.display());
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(&path)?;
+                (None, Some(BufWriter::new(file)))
+            }
+        };
+
+        Ok(FileProvider {
+            mode,
+            speed,
+            start_timestamp,
+            reader,
+            writer,
+            pending: None,
+            started: None,
+            next_event_number: 0,
+            subscribe_rx,
+            unsubscribe_rx,
+            subscriptions: Vec::new(),
+            paused: false,
+            stats: Stats::default(),
+            exit_hook: None,
+        })
+    }
+
+    /// Checks that the options in the LCM URL are valid, without opening the
+    /// log.
+    pub fn validate_url(url: &Url) -> Result<(), InitError> {
+        Options::parse(url).map(|_| ())
+    }
+
+    /// Appends a message to the log, timestamped with the current time.
+    pub fn publish(&mut self, channel: &str, message_buf: &[u8]) -> Result<(), PublishError> {
+        let writer = match self.writer {
+            Some(ref mut writer) => writer,
+            None => {
+                warn!("Can't publish to a log that was opened for reading.");
+                return Err(PublishError::ProviderIssue);
+            }
+        };
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64 * 1_000_000 + i64::from(d.subsec_micros()))
+            .unwrap_or(0);
+        write_event(writer, &LogEvent {
+            event_number: self.next_event_number,
+            timestamp,
+            channel: channel.into(),
+            data: message_buf.into(),
+        })?;
+        self.next_event_number += 1;
+        Ok(())
+    }
+
+    /// Writes any buffered events to the log.
+    pub fn flush(&mut self) -> Result<(), PublishError> {
+        if let Some(ref mut writer) = self.writer {
+            writer.flush()?;
+            writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the next event that a subscription receives.
+    ///
+    /// Returns `HandleError::EndOfLog` once there are no more events.
+    pub fn handle(&mut self) -> Result<(), HandleError> {
+        self.check_readable()?;
+        while !self.next_event(None)? {}
+        Ok(())
+    }
+
+    /// Waits for the next event that a subscription receives, with a
+    /// timeout.
+    ///
+    /// Returns `Ok(false)` if no event was received before the timeout.
+    pub fn handle_timeout(&mut self, timeout: Duration) -> Result<bool, HandleError> {
+        self.check_readable()?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.next_event(Some(deadline))? {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Passes the events that are already due to the subscriptions, without
+    /// waiting for later ones.
+    ///
+    /// Returns `Ok(true)` as soon as a subscription receives an event.
+    pub fn try_handle(&mut self) -> Result<bool, HandleError> {
+        self.check_readable()?;
+        loop {
+            if self.next_event(Some(Instant::now()))? {
+                return Ok(true);
+            }
+            // An event that isn't due yet is kept for later.
+            if self.pending.is_some() {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Returns the features supported by this provider.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_seek: self.mode == Mode::Read,
+            can_publish: self.mode == Mode::Write,
+            can_subscribe: self.mode == Mode::Read,
+            has_addressing: false,
+        }
+    }
+
+    /// Logs have no multicast settings.
+    pub fn multicast_info(&self) -> Option<MulticastInfo> {
+        None
+    }
+
+    /// Logs have no time-to-live.
+    pub fn set_ttl(&mut self, _ttl: u32) -> Result<(), SettingError> {
+        Err(SettingError::NotSupported)
+    }
+
+    /// Events in a log are numbered as they are written, not as they are
+    /// published.
+    pub fn sequence_number(&self) -> Option<u32> {
+        None
+    }
+
+    /// Events in a log are numbered as they are written, not as they are
+    /// published.
+    pub fn set_sequence_number(&mut self, _sequence_number: u32) -> Result<(), SettingError> {
+        Err(SettingError::NotSupported)
+    }
+
+    /// Makes the provider drop the events it reads until `resume` is called.
+    ///
+    /// The playback keeps its timing while paused.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Makes the provider pass the events it reads to the subscriptions
+    /// again.
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Sets the function to call once the end of the log is reached.
+    ///
+    /// If the end has already been reached, the function is called
+    /// immediately. A log that is being written never ends, so the function
+    /// is never called.
+    pub fn on_backend_exit(&mut self, hook: Box<FnOnce() + Send>) {
+        if self.mode == Mode::Read && self.reader.is_none() {
+            hook();
+        } else {
+            self.exit_hook = Some(hook);
+        }
+    }
+
+    /// Each message is a single event in the log.
+    pub fn fragment_count(&self, _channel: &str, _message_size: usize) -> usize {
+        1
+    }
+
+    /// Returns the statistics about the events that have been read.
+    pub fn stats(&self) -> Stats {
+        self.stats.clone()
+    }
+
+    /// Fails if the log was opened for writing.
+    fn check_readable(&self) -> Result<(), HandleError> {
+        if self.mode == Mode::Write {
+            warn!("Can't handle messages from a log that was opened for writing.");
+            return Err(HandleError::ProviderIssue);
+        }
+        Ok(())
+    }
+
+    /// Reads the next event and passes it to the subscriptions once it is
+    /// due.
+    ///
+    /// If the event won't be due before the deadline, this sleeps until the
+    /// deadline and keeps the event for the next call. Returns true if a
+    /// subscription received the event.
+    fn next_event(&mut self, deadline: Option<Instant>) -> Result<bool, HandleError> {
+        let event = match self.pending.take() {
+            Some(event) => event,
+            None => self.read_event()?,
+        };
+
+        let due = self.due(&event);
+        let now = Instant::now();
+        if due > now {
+            match deadline {
+                Some(deadline) if deadline < due => {
+                    thread::sleep(deadline.saturating_duration_since(now));
+                    self.pending = Some(event);
+                    return Ok(false);
+                }
+                _ => thread::sleep(due - now),
+            }
+        }
+
+        Ok(self.forward(&event))
+    }
+
+    /// Reads the next event at or after the start timestamp.
+    ///
+    /// A truncated or corrupt event is treated as the end of the log.
+    fn read_event(&mut self) -> Result<LogEvent, HandleError> {
+        loop {
+            let next = match self.reader {
+                Some(ref mut reader) => reader.next(),
+                None => return Err(HandleError::EndOfLog),
+            };
+            match next {
+                Some(Ok(ref event)) if event.timestamp < self.start_timestamp => continue,
+                Some(Ok(event)) => return Ok(event),
+                Some(Err(e)) => warn!("Stopped reading the log after an invalid event: {}", e),
+                None => debug!("Reached the end of the log"),
+            }
+
+            self.reader = None;
+            if let Some(hook) = self.exit_hook.take() {
+                hook();
+            }
+            return Err(HandleError::EndOfLog);
+        }
+    }
+
+    /// Returns the time at which the event should be delivered.
+    fn due(&mut self, event: &LogEvent) -> Instant {
+        let (started, first_timestamp) =
+            *self.started.get_or_insert_with(|| (Instant::now(), event.timestamp));
+        started + replay_offset(first_timestamp, event.timestamp, self.speed)
+    }
+
+    /// Passes the event to every matching subscription.
+    ///
+    /// Returns true if any of them queued it.
+    fn forward(&mut self, event: &LogEvent) -> bool {
+        let now = Instant::now();
+        self.stats.bytes_received += event.data.len() as u64;
+        self.stats.record_message(&event.channel, now);
+
+        if self.paused {
+            trace!("Dropping event {} while paused", event.event_number);
+            return false;
+        }
+
+        let removed: Vec<_> = self.unsubscribe_rx.try_iter().collect();
+        self.subscriptions.extend(self.subscribe_rx.try_iter());
+        let meta = MessageMeta {
+            received: now,
+            sender: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0),
+            fragmented: false,
+            fragment_count: 1,
+        };
+        let mut forwarded = false;
+        self.subscriptions.retain(|&(ref sub, ref re, ref f)| {
+            if removed.contains(sub) {
+                return false;
+            }
+            if !re.is_match(&event.channel) {
+                return true;
+            }
+            match (*f)(&event.channel, &event.data, &meta) {
+                Err(TrampolineError::MessageChannelClosed) => false,
+                Err(e) => {
+                    warn!("Error decoding message: {}", e);
+                    true
+                }
+                Ok(queued) => {
+                    forwarded |= queued;
+                    true
+                }
+            }
+        });
+
+        forwarded
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::BufReader;
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+    use url::Url;
+
+    use logfile::write_event;
+    use {Lcm, LogEvent, LogReader};
+    use error::*;
+
+    /// Writes a log whose events are 100ms apart, each holding its number.
+    fn make_log(name: &str, channels: &[&str]) -> PathBuf {
+        let mut log = Vec::new();
+        for (i, channel) in channels.iter().enumerate() {
+            write_event(&mut log, &LogEvent {
+                event_number: i as i64,
+                timestamp: 1_500_000_000_000_000 + i as i64 * 100_000,
+                channel: (*channel).into(),
+                data: vec![i as u8],
+            }).unwrap();
+        }
+        let path = env::temp_dir().join(format!("lcm_file_{}_{}.lcmlog", name, process::id()));
+        fs::write(&path, log).unwrap();
+        path
+    }
+
+    fn log_url(path: &Path, query: &str) -> String {
+        format!("{}?{}", Url::from_file_path(path).unwrap(), query)
+    }
+
+    /// Subscribes to every channel, recording the channel and the first byte.
+    fn record(lcm: &mut Lcm) -> Rc<RefCell<Vec<(String, u8)>>> {
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            lcm.subscribe_raw(".*", 10, move |chan: &str, m: &[u8]| {
+                received.borrow_mut().push((chan.to_owned(), m[0]))
+            }).unwrap();
+        }
+        received
+    }
+
+    #[test]
+    fn read_in_order() {
+        let path = make_log("read", &["FIRST", "SECOND", "THIRD"]);
+        let mut lcm = Lcm::with_lcm_url(&log_url(&path, "mode=r&speed=0")).unwrap();
+        let received = record(&mut lcm);
+        fs::remove_file(&path).unwrap();
+
+        for _ in 0..3 {
+            lcm.handle().unwrap();
+        }
+        match lcm.handle() {
+            Err(HandleError::EndOfLog) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert_eq!(
+            *received.borrow(),
+            [
+                ("FIRST".to_owned(), 0),
+                ("SECOND".to_owned(), 1),
+                ("THIRD".to_owned(), 2),
+            ]
+        );
+        assert!(lcm.capabilities().can_seek);
+        assert!(!lcm.capabilities().can_publish);
+    }
+
+    #[test]
+    fn playback_speed() {
+        let path = make_log("speed", &["FIRST", "SECOND", "THIRD"]);
+        let mut lcm = Lcm::with_lcm_url(&log_url(&path, "speed=2")).unwrap();
+        let received = record(&mut lcm);
+        fs::remove_file(&path).unwrap();
+
+        // The first event is due immediately, and the others 50ms apart.
+        let start = Instant::now();
+        assert!(lcm.handle_timeout(Duration::from_millis(10)).unwrap());
+        assert!(!lcm.handle_timeout(Duration::from_millis(10)).unwrap());
+        assert_eq!(received.borrow().len(), 1);
+        while received.borrow().len() < 3 {
+            assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(100));
+        assert!(elapsed < Duration::from_millis(200));
+    }
+
+    #[test]
+    fn try_handle() {
+        let path = make_log("try", &["FIRST", "SECOND"]);
+        let mut lcm = Lcm::with_lcm_url(&log_url(&path, "speed=1")).unwrap();
+        let received = record(&mut lcm);
+        fs::remove_file(&path).unwrap();
+
+        // Only the first event is due, and the second is kept for later.
+        let start = Instant::now();
+        assert!(lcm.try_handle().unwrap());
+        assert!(!lcm.try_handle().unwrap());
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert_eq!(*received.borrow(), [("FIRST".to_owned(), 0)]);
+
+        assert!(lcm.handle_timeout(Duration::from_millis(500)).unwrap());
+        match lcm.try_handle() {
+            Err(HandleError::EndOfLog) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn start_timestamp() {
+        let path = make_log("start", &["FIRST", "SECOND", "THIRD"]);
+        let url = log_url(&path, "speed=0&start_timestamp=1500000000100000");
+        let mut lcm = Lcm::with_lcm_url(&url).unwrap();
+        let received = record(&mut lcm);
+        fs::remove_file(&path).unwrap();
+
+        while lcm.handle().is_ok() {}
+        assert_eq!(
+            *received.borrow(),
+            [("SECOND".to_owned(), 1), ("THIRD".to_owned(), 2)]
+        );
+    }
+
+    #[test]
+    fn truncated_final_event() {
+        let path = make_log("truncated", &["FIRST", "SECOND"]);
+        let len = fs::metadata(&path).unwrap().len();
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 2)
+            .unwrap();
+        let mut lcm = Lcm::with_lcm_url(&log_url(&path, "speed=0")).unwrap();
+        let received = record(&mut lcm);
+        fs::remove_file(&path).unwrap();
+
+        lcm.handle().unwrap();
+        match lcm.handle_timeout(Duration::from_millis(10)) {
+            Err(HandleError::EndOfLog) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert_eq!(*received.borrow(), [("FIRST".to_owned(), 0)]);
+    }
+
+    #[test]
+    fn write_log() {
+        let path = env::temp_dir().join(format!("lcm_file_write_{}.lcmlog", process::id()));
+        {
+            let mut lcm = Lcm::with_lcm_url(&log_url(&path, "mode=w")).unwrap();
+            assert!(lcm.capabilities().can_publish);
+            lcm.publish_raw("FIRST", &[1, 2]).unwrap();
+            lcm.publish_raw("SECOND", &[3]).unwrap();
+        }
+
+        let file = File::open(&path).unwrap();
+        let events: Vec<_> = LogReader::new(BufReader::new(file))
+            .map(|e| e.unwrap())
+            .collect();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            (events[0].event_number, &events[0].channel[..], &events[0].data[..]),
+            (0, "FIRST", &[1, 2][..])
+        );
+        assert_eq!(
+            (events[1].event_number, &events[1].channel[..], &events[1].data[..]),
+            (1, "SECOND", &[3][..])
+        );
+        assert!(events[0].timestamp <= events[1].timestamp);
+    }
+
+    #[test]
+    fn invalid_options() {
+        for &query in &["mode=a", "speed=-1", "speed=NaN", "speed=fast", "start_timestamp=now"] {
+            let url = format!("file:///tmp/lcm.log?{}", query);
+            match ::lcm::validate_url(&url) {
+                Err(InitError::InvalidOption(_)) => {}
+                res => panic!("Unexpected result for {}: {:?}", query, res),
+            }
+        }
+        ::lcm::validate_url("file:///tmp/lcm.log?mode=w&speed=0.5").unwrap();
+        ::lcm::validate_url("file:///tmp/lcm.log?speed=inf").unwrap();
+    }
+}
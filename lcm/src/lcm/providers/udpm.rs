@@ -1,14 +1,19 @@
+use std::cmp;
 use std::thread;
 use std::io::{self, Write};
-use std::collections::HashMap;
-use std::time::Duration;
-use std::sync::mpsc;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
 use std::borrow::Borrow;
-use url::Url;
+use url::{ParseError, Url};
 use byteorder::{ByteOrder, NetworkEndian, WriteBytesExt};
+#[cfg(feature = "compression")]
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
 
-use lcm::{MAX_MESSAGE_SIZE, TrampolineError, SubscribeMsg};
+use lcm::{Capabilities, MessageMeta, MulticastInfo, MAX_CHANNEL_NAME_LENGTH, MAX_MESSAGE_SIZE,
+          Stats, Subscription, TrampolineError, SubscribeMsg};
 use error::*;
 
 /// LCM's magic number for short messages.
@@ -27,6 +32,58 @@ pub const SMALL_HEADER_SIZE: usize = 8;
 /// The header size for fragmented datagrams.
 pub const FRAG_HEADER_SIZE: usize = 20;
 
+/// The number of recent sequence numbers remembered for each sender when
+/// deduplicating messages.
+const DEDUP_WINDOW_SIZE: usize = 64;
+
+/// The default number of fragmented messages from a single sender that can
+/// be reassembled at the same time.
+const MAX_REASSEMBLIES: usize = 4;
+
+/// The default delay before a datagram is resent after a transient error.
+const PUBLISH_BACKOFF: Duration = Duration::from_millis(1);
+
+/// The longest that the delay between resends grows to, unless the
+/// "publish_backoff_ms" option starts it higher.
+const MAX_PUBLISH_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The number of messages from a single sender that can be held back while
+/// waiting for a missing message, when delivering messages in order.
+const REORDER_WINDOW_SIZE: usize = 64;
+
+/// How long a message is held back while waiting for a missing message, when
+/// delivering messages in order.
+const REORDER_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How often the backend checks for removed subscriptions while no datagrams
+/// arrive.
+const SUBSCRIPTION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The default size above which reassembling a message logs a warning.
+const WARN_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+
+/// The name of the thread that receives datagrams, as seen by debuggers.
+const BACKEND_THREAD_NAME: &str = "lcm-udpm-backend";
+
+/// The options that may appear in the query of a UDPM URL.
+const OPTIONS: &[&str] = &[
+    "ttl",
+    "recv_buf_size",
+    "dedup",
+    "ordered",
+    "groups",
+    "max_message_size",
+    "warn_message_size",
+    "reuse_port",
+    "bind_addr",
+    "compress",
+    "drop_truncated",
+    "max_reassemblies",
+    "publish_retries",
+    "publish_backoff_ms",
+    "if_index",
+];
+
 /// The UDP Multicast provider.
 ///
 /// It starts a new thread to handle the incoming messages. Those messages are
@@ -46,10 +103,92 @@ pub struct UdpmProvider {
 
     /// The sequence number for the outgoing messages.
     sequence_number: u32,
+
+    /// The statistics kept by the backend.
+    stats: Arc<Mutex<Stats>>,
+
+    /// The function to call once the backend thread exits.
+    exit: Arc<Mutex<BackendExit>>,
+
+    /// Set while the backend should drop messages instead of forwarding them.
+    paused: Arc<AtomicBool>,
+
+    /// Whether messages are compressed before they are sent.
+    compress: bool,
+
+    /// The time-to-live of outgoing multicast packets.
+    ttl: u32,
+
+    /// The multicast groups that the socket joined.
+    groups: Vec<IpAddr>,
+
+    /// The index of the interface used for IPv6 multicast, or 0 to let the
+    /// system choose.
+    if_index: u32,
+
+    /// The number of times a datagram is resent after a transient error.
+    publish_retries: usize,
+
+    /// The delay before the first resend, which doubles after each one.
+    publish_backoff: Duration,
 }
 impl UdpmProvider {
+    /// Checks that the URL describes a usable UDPM provider.
+    ///
+    /// The host and port must both be present. Unrecognized options are
+    /// logged but are not considered an error.
+    pub fn validate_url(url: &Url) -> Result<(), InitError> {
+        if url.host().is_none() {
+            return Err(InitError::InvalidLcmUrl(ParseError::EmptyHost));
+        }
+        if url.port().is_none() {
+            return Err(InitError::InvalidLcmUrl(ParseError::InvalidPort));
+        }
+
+        for (key, _) in url.query_pairs() {
+            if !OPTIONS.contains(&key.borrow()) {
+                warn!(
+                    "Ignoring unrecognized UDPM option \"{}\". Recognized options are: {}.",
+                    key,
+                    OPTIONS.join(", ")
+                );
+            }
+        }
+
+        Ok(())
+    }
+
     /// Creates a new UDPM provider using the given settings.
-    pub fn new(url: &Url, subscribe_rx: mpsc::Receiver<SubscribeMsg>) -> Result<Self, InitError> {
+    pub fn new(
+        url: &Url,
+        subscribe_rx: mpsc::Receiver<SubscribeMsg>,
+        unsubscribe_rx: mpsc::Receiver<Subscription>,
+    ) -> Result<Self, InitError> {
+        UdpmProvider::start(url, None, subscribe_rx, unsubscribe_rx)
+    }
+
+    /// Creates a new UDPM provider that uses an existing socket.
+    ///
+    /// The socket is used as-is for both sending and receiving, so it must
+    /// already be bound to the port in the URL and be a member of any
+    /// multicast groups. The "ttl", "groups", "reuse_port", "bind_addr", and
+    /// "if_index" options are ignored.
+    pub fn with_socket(
+        url: &Url,
+        socket: UdpSocket,
+        subscribe_rx: mpsc::Receiver<SubscribeMsg>,
+        unsubscribe_rx: mpsc::Receiver<Subscription>,
+    ) -> Result<Self, InitError> {
+        UdpmProvider::start(url, Some(socket), subscribe_rx, unsubscribe_rx)
+    }
+
+    /// Creates the provider, setting up a new socket if one isn't supplied.
+    fn start(
+        url: &Url,
+        socket: Option<UdpSocket>,
+        subscribe_rx: mpsc::Receiver<SubscribeMsg>,
+        unsubscribe_rx: mpsc::Receiver<Subscription>,
+    ) -> Result<Self, InitError> {
         // Parse the network string into the address and port
         let addr = url.to_socket_addrs()?
             .next()
@@ -57,38 +196,142 @@ impl UdpmProvider {
 
         // Parse additional options
         let mut ttl = 0;
+        let mut recv_buf_size = None;
+        let mut dedup = false;
+        let mut ordered = false;
+        let mut reuse_port = false;
+        let mut compress = false;
+        let mut drop_truncated = false;
+        let mut bind_addr = match addr {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)),
+        };
+        let mut max_message_size = MAX_MESSAGE_SIZE;
+        let mut max_reassemblies = MAX_REASSEMBLIES;
+        let mut warn_message_size = WARN_MESSAGE_SIZE;
+        let mut publish_retries = 0;
+        let mut publish_backoff = PUBLISH_BACKOFF;
+        let mut if_index = 0;
+        let mut groups = vec![addr.ip()];
         for (key, value) in url.query_pairs() {
             match key.borrow() {
                 "ttl" => ttl = value.parse().map_err(InitError::InvalidTtl)?,
-                "recv_buf_size" => { /* TODO: support this option */ }
+                "recv_buf_size" => recv_buf_size = Some(parse_size(&key, &value)?),
+                "dedup" => dedup = parse_flag(&key, &value)?,
+                "ordered" => ordered = parse_flag(&key, &value)?,
+                "reuse_port" => reuse_port = parse_flag(&key, &value)?,
+                "compress" => compress = parse_flag(&key, &value)?,
+                "drop_truncated" => drop_truncated = parse_flag(&key, &value)?,
+                "bind_addr" => bind_addr = value.parse().map_err(InitError::InvalidBindAddress)?,
+                "max_message_size" => max_message_size = parse_size(&key, &value)?,
+                "max_reassemblies" => max_reassemblies = parse_size(&key, &value)?,
+                "warn_message_size" => warn_message_size = parse_size(&key, &value)?,
+                "publish_retries" => publish_retries = parse_size(&key, &value)?,
+                "publish_backoff_ms" => {
+                    publish_backoff = value
+                        .parse()
+                        .map(Duration::from_millis)
+                        .map_err(|_| InitError::InvalidOption(key.to_string()))?
+                }
+                "if_index" => {
+                    if_index = value
+                        .parse()
+                        .map_err(|_| InitError::InvalidOption(key.to_string()))?
+                }
+                "groups" => for group in value.split(',') {
+                    groups.push(group.parse().map_err(InitError::InvalidMulticastGroup)?);
+                },
+                // These were already logged by `validate_url`.
                 _ => {}
             }
         }
 
-        debug!(
-            "Starting UDPM provider with multicast (ip = {}, port = {}, ttl = {})",
-            addr.ip(),
-            addr.port(),
-            ttl
-        );
-        let socket = UdpmProvider::setup_udp_socket(addr, ttl)?;
+        if compress && !cfg!(feature = "compression") {
+            return Err(InitError::FeatureDisabled("compression".into()));
+        }
+
+        let socket = match socket {
+            Some(socket) => {
+                debug!(
+                    "Starting UDPM provider on a supplied socket (ip = {}, port = {})",
+                    addr.ip(),
+                    addr.port()
+                );
+                ttl = if addr.is_ipv6() {
+                    net2::UdpSocketExt::multicast_hops_v6(&socket)?
+                } else {
+                    socket.multicast_ttl_v4()?
+                };
+                groups.clear();
+                socket
+            }
+            None => {
+                debug!(
+                    "Starting UDPM provider with multicast (ip = {}, port = {}, ttl = {})",
+                    addr.ip(),
+                    addr.port(),
+                    ttl
+                );
+                UdpmProvider::setup_udp_socket(addr, bind_addr, &groups, ttl, if_index, reuse_port)?
+            }
+        };
+        if let Some(size) = recv_buf_size {
+            set_recv_buffer_size(&socket, size)?;
+        }
         let (notify_tx, notify_rx) = mpsc::sync_channel(1);
 
-        let receiver = Backend::new(socket.try_clone()?, notify_tx, subscribe_rx);
+        let mut receiver = Backend::new(
+            socket.try_clone()?,
+            notify_tx,
+            subscribe_rx,
+            unsubscribe_rx,
+            dedup,
+            ordered,
+        )?;
+        receiver.max_message_size = cmp::min(max_message_size, MAX_MESSAGE_SIZE);
+        receiver.max_reassemblies = cmp::max(max_reassemblies, 1);
+        receiver.warn_message_size = warn_message_size;
+        receiver.compressed = compress;
+        receiver.drop_truncated = drop_truncated;
+        let stats = receiver.stats.clone();
+        let paused = receiver.paused.clone();
+
+        let exit = Arc::new(Mutex::new(BackendExit::default()));
+        let backend_exit = exit.clone();
 
         debug!("Starting read thread");
-        thread::spawn(move || {
-            let res = receiver.run();
-            if let Err(e) = res {
-                error!("Read thread failed with message: {}", e);
-            }
-        });
+        thread::Builder::new()
+            .name(BACKEND_THREAD_NAME.into())
+            .spawn(move || {
+                let res = receiver.run();
+                if let Err(e) = res {
+                    error!("Read thread failed with message: {}", e);
+                }
+
+                let hook = {
+                    let mut exit = backend_exit.lock().expect("Exit hook was poisoned");
+                    exit.exited = true;
+                    exit.hook.take()
+                };
+                if let Some(hook) = hook {
+                    hook();
+                }
+            })?;
 
         Ok(UdpmProvider {
             socket,
             addr,
             notify_rx,
             sequence_number: 0,
+            stats,
+            exit,
+            paused,
+            compress,
+            ttl,
+            groups,
+            if_index,
+            publish_retries,
+            publish_backoff,
         })
     }
 
@@ -97,9 +340,16 @@ impl UdpmProvider {
     /// This message will be sent directly by the `UdpmProvider` without being
     /// sent to the backend.
     pub fn publish(&mut self, channel: &str, message_buf: &[u8]) -> Result<(), PublishError> {
+        let compressed;
+        let message_buf = if self.compress {
+            compressed = compress(message_buf)?;
+            &compressed[..]
+        } else {
+            message_buf
+        };
+
         // Determine if we need to split this message up into fragments
-        let available = MAX_DATAGRAM_SIZE - SMALL_HEADER_SIZE - (channel.len() + 1);
-        if message_buf.len() > available {
+        if fragment_count(channel, message_buf.len()) > 1 {
             // We need to break this into fragments
             self.send_frag_datagram(channel, &message_buf)?;
         } else {
@@ -107,7 +357,15 @@ impl UdpmProvider {
             self.send_small_datagram(channel, &message_buf)?;
         }
 
-        self.sequence_number += 1;
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Sends any messages that have been published but not yet sent.
+    ///
+    /// Datagrams are sent as soon as they are published, so there is never
+    /// anything to do.
+    pub fn flush(&mut self) -> Result<(), PublishError> {
         Ok(())
     }
 
@@ -124,52 +382,204 @@ impl UdpmProvider {
     /// Waits for and dispatches messages, with a timeout.
     ///
     /// Does the same thing as `UdpmProvider::handle` but with a timeout.
-    pub fn handle_timeout(&mut self, timeout: Duration) -> Result<(), HandleError> {
+    /// Returns `Ok(false)` if the timeout elapsed without a notification.
+    pub fn handle_timeout(&mut self, timeout: Duration) -> Result<bool, HandleError> {
         debug!("Waiting on notify channel");
-        if let Err(mpsc::RecvTimeoutError::Disconnected) = self.notify_rx.recv_timeout(timeout) {
-            warn!("The provider has been shut down or otherwise killed.");
-            return Err(HandleError::ProviderIssue);
+        match self.notify_rx.recv_timeout(timeout) {
+            Ok(()) => Ok(true),
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(false),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                warn!("The provider has been shut down or otherwise killed.");
+                Err(HandleError::ProviderIssue)
+            }
+        }
+    }
+
+    /// Checks for messages without blocking.
+    ///
+    /// Returns `Ok(false)` if there is no notification waiting.
+    pub fn try_handle(&mut self) -> Result<bool, HandleError> {
+        match self.notify_rx.try_recv() {
+            Ok(()) => Ok(true),
+            Err(mpsc::TryRecvError::Empty) => Ok(false),
+            Err(mpsc::TryRecvError::Disconnected) => {
+                warn!("The provider has been shut down or otherwise killed.");
+                Err(HandleError::ProviderIssue)
+            }
+        }
+    }
+
+    /// Returns the features supported by this provider.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_seek: false,
+            can_publish: true,
+            can_subscribe: true,
+            has_addressing: true,
+        }
+    }
+
+    /// Returns the multicast settings of the socket.
+    pub fn multicast_info(&self) -> Option<MulticastInfo> {
+        Some(MulticastInfo {
+            group: self.addr.ip(),
+            port: self.addr.port(),
+            ttl: self.ttl,
+            groups: self.groups.clone(),
+        })
+    }
+
+    /// Changes the time-to-live of outgoing multicast packets.
+    pub fn set_ttl(&mut self, ttl: u32) -> Result<(), SettingError> {
+        set_multicast_ttl(&self.socket, self.addr, ttl)?;
+        // A supplied socket has no groups, and its interface is left alone.
+        if !self.groups.is_empty() {
+            set_multicast_interface(&self.socket, self.addr, ttl, self.if_index)?;
         }
+        self.ttl = ttl;
+        Ok(())
+    }
+
+    /// Returns the sequence number of the next outgoing message.
+    pub fn sequence_number(&self) -> Option<u32> {
+        Some(self.sequence_number)
+    }
+
+    /// Sets the sequence number of the next outgoing message.
+    pub fn set_sequence_number(&mut self, sequence_number: u32) -> Result<(), SettingError> {
+        self.sequence_number = sequence_number;
         Ok(())
     }
 
+    /// Makes the backend drop incoming messages until `resume` is called.
+    pub fn pause(&mut self) {
+        self.paused.store(true, Ordering::Release);
+    }
+
+    /// Makes the backend forward incoming messages again.
+    pub fn resume(&mut self) {
+        self.paused.store(false, Ordering::Release);
+    }
+
+    /// Sets the function to call once the backend thread exits.
+    ///
+    /// If the thread has already exited, the function is called immediately.
+    pub fn on_backend_exit(&mut self, hook: Box<FnOnce() + Send>) {
+        let hook = {
+            let mut exit = self.exit.lock().expect("Exit hook was poisoned");
+            if !exit.exited {
+                exit.hook = Some(hook);
+                return;
+            }
+            hook
+        };
+        hook();
+    }
+
+    /// Returns the number of datagrams used to publish a message of
+    /// `message_size` bytes on the channel.
+    pub fn fragment_count(&self, channel: &str, message_size: usize) -> usize {
+        fragment_count(channel, message_size)
+    }
+
+    /// Returns the statistics about the received datagrams.
+    pub fn stats(&self) -> Stats {
+        self.stats.lock().expect("Statistics were poisoned").clone()
+    }
+
     /// Set up the UDP socket.
-    fn setup_udp_socket(addr: SocketAddr, ttl: u32) -> io::Result<UdpSocket> {
+    ///
+    /// The socket joins every one of the multicast `groups`, but messages
+    /// are only ever sent to `addr`.
+    ///
+    /// The socket is bound to `bind_addr`, which is normally the unspecified
+    /// address. This only filters the datagrams by their destination address;
+    /// it does not choose the interface that messages are sent from. In
+    /// particular, on Linux a socket bound to an interface's own address
+    /// receives unicast datagrams sent to it, but not multicast ones. To only
+    /// receive traffic for the multicast group, bind to the group address.
+    ///
+    /// `SO_REUSEADDR` is always set, which is enough for every process on the
+    /// host to receive the multicast traffic. If `reuse_port` is true,
+    /// `SO_REUSEPORT` is also set. Multicast datagrams are still delivered to
+    /// every socket, but unicast datagrams sent to the port are balanced
+    /// between the sockets instead of all going to the most recent one.
+    ///
+    /// IPv6 groups are joined on the interface `if_index`, which is 0 to let
+    /// the system choose. The socket has the same address family as `addr`.
+    fn setup_udp_socket(
+        addr: SocketAddr,
+        bind_addr: IpAddr,
+        groups: &[IpAddr],
+        ttl: u32,
+        if_index: u32,
+        reuse_port: bool,
+    ) -> Result<UdpSocket, InitError> {
         use net2::UdpBuilder;
 
-        let builder = UdpBuilder::new_v4()?;
+        let builder = match addr {
+            SocketAddr::V4(_) => UdpBuilder::new_v4()?,
+            SocketAddr::V6(_) => UdpBuilder::new_v6()?,
+        };
 
         debug!("Setting SO_REUSEADDR");
         builder.reuse_address(true)?;
 
         // The UDPM source for the C version of LCM says that the SO_REUSEPORT
-        // only needs to be set on MacOS and FreeBSD.
-        #[cfg(any(target_os = "macos", target_os = "freebsd"))]
+        // only needs to be set on MacOS and FreeBSD. Elsewhere it is opt-in.
+        let reuse_port = reuse_port || cfg!(any(target_os = "macos", target_os = "freebsd"));
+
+        #[cfg(unix)]
+        {
+            if reuse_port {
+                use net2::unix::UnixUdpBuilderExt;
+                debug!("Setting SO_REUSEPORT");
+                builder.reuse_port(true)?;
+            }
+        }
+        #[cfg(not(unix))]
         {
-            use net2::unix::UnixUdpBuilderExt;
-            debug!("Setting SO_REUSEPORT");
-            builder.reuse_port(true)?;
+            if reuse_port {
+                warn!("SO_REUSEPORT is not supported on this platform. Ignoring.");
+            }
         }
 
         // FIXME:
         // The C version of LCM increases the receive buffer size on Win32. Do
-        // we need to do this and how?
-        warn!("Not checking receive buffer size");
-
-        debug!("Binding UDP socket");
-        let socket = {
-            let inaddr_any = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
-            builder.bind(SocketAddr::new(inaddr_any, addr.port()))?
-        };
+        // we need to do this, or is the "recv_buf_size" option enough?
+
+        debug!("Binding UDP socket to {}", bind_addr);
+        let socket = builder.bind(SocketAddr::new(bind_addr, addr.port()))?;
+
+        for group in groups {
+            debug!("Joining multicast group {}", group);
+            match *group {
+                IpAddr::V4(ref addr) => socket.join_multicast_v4(addr, &Ipv4Addr::new(0, 0, 0, 0)),
+                IpAddr::V6(ref addr) => socket.join_multicast_v6(addr, if_index),
+            }.map_err(|source| InitError::MulticastJoinFailed {
+                group: *group,
+                source,
+            })?;
+        }
 
-        debug!("Joining multicast group");
-        match addr.ip() {
-            IpAddr::V4(ref addr) => socket.join_multicast_v4(addr, &Ipv4Addr::new(0, 0, 0, 0))?,
-            IpAddr::V6(ref _addr) => unimplemented!("IPv6 is not supported."),
+        // With a TTL of 0, keep the traffic on this host. Other instances
+        // doing the same send through the loopback interface, so the groups
+        // need to be joined there as well. This fails harmlessly if the
+        // groups were already joined on loopback above.
+        if ttl == 0 {
+            for group in groups {
+                if let IpAddr::V4(ref addr) = *group {
+                    debug!("Joining multicast group {} on loopback", group);
+                    if let Err(e) = socket.join_multicast_v4(addr, &Ipv4Addr::new(127, 0, 0, 1)) {
+                        debug!("Unable to join {} on loopback: {}", group, e);
+                    }
+                }
+            }
         }
 
         debug!("Setting multicast packet TTL to {}", ttl);
-        socket.set_multicast_ttl_v4(ttl)?;
+        set_multicast_ttl(&socket, addr, ttl)?;
+        set_multicast_interface(&socket, addr, ttl, if_index)?;
 
         Ok(socket)
     }
@@ -178,12 +588,7 @@ impl UdpmProvider {
     fn send_frag_datagram(&mut self, channel: &str, message: &[u8]) -> Result<(), PublishError> {
         let mut buf = [0u8; MAX_DATAGRAM_SIZE];
 
-        let n_fragments = {
-            let available = MAX_DATAGRAM_SIZE - FRAG_HEADER_SIZE;
-            let first_available = available - channel.len() - 1;
-
-            1 + (message.len() + available - first_available) / available
-        };
+        let n_fragments = fragment_count(channel, message.len());
 
         if n_fragments > ::std::u16::MAX as usize {
             // Probably a redundant check
@@ -231,7 +636,7 @@ impl UdpmProvider {
                 (message_end + amount_written, amount_written)
             };
 
-            let sent = self.socket.send_to(&buf[0..datagram_size], self.addr)?;
+            let sent = self.send_datagram(&buf[0..datagram_size])?;
 
             if sent != datagram_size {
                 warn!(
@@ -248,6 +653,15 @@ impl UdpmProvider {
         Ok(())
     }
 
+    /// Sends a single datagram, retrying after transient errors as set by the
+    /// "publish_retries" and "publish_backoff_ms" options.
+    fn send_datagram(&self, datagram: &[u8]) -> io::Result<usize> {
+        let (socket, addr) = (&self.socket, self.addr);
+        send_with_retry(self.publish_retries, self.publish_backoff, || {
+            socket.send_to(datagram, addr)
+        })
+    }
+
     /// Sends the message using a "small message" datagram.
     ///
     /// This function will panic if the message does not actually fit within a
@@ -256,30 +670,9 @@ impl UdpmProvider {
         trace!("Sending small datagram on channel \"{}\"", channel);
         let mut buf = [0u8; MAX_DATAGRAM_SIZE];
 
-        let datagram_size = {
-            let mut buf = &mut buf[..];
-            let payload_start = SMALL_HEADER_SIZE + channel.len() + 1;
-            let payload_end = payload_start + message.len();
-
-            assert!(payload_end <= MAX_DATAGRAM_SIZE);
-
-            // We're writing to a slice, so these can never fail. Literally,
-            // the code for writing to a slice does not have a way to return an
-            // `Err`.
-            buf.write_u32::<NetworkEndian>(SHORT_HEADER_MAGIC).unwrap();
-            buf.write_u32::<NetworkEndian>(self.sequence_number)
-                .unwrap();
-            for &b in channel.as_bytes() {
-                buf.write_u8(b).unwrap();
-            }
-            buf.write_u8(0).unwrap();
-
-            buf.write_all(message).unwrap();
+        let datagram_size = write_small_datagram(&mut buf, self.sequence_number, channel, message);
 
-            payload_end
-        };
-
-        let sent = self.socket.send_to(&buf[0..datagram_size], self.addr)?;
+        let sent = self.send_datagram(&buf[0..datagram_size])?;
 
         if sent != datagram_size {
             warn!(
@@ -293,6 +686,219 @@ impl UdpmProvider {
     }
 }
 
+/// Returns the number of datagrams needed to send a message of `message_size`
+/// bytes on the channel.
+///
+/// A message that does not fit in a single "small message" datagram is split
+/// into fragments, the first of which also holds the channel name. When the
+/// data exactly fills the last fragment, an extra empty fragment is sent.
+fn fragment_count(channel: &str, message_size: usize) -> usize {
+    if message_size <= MAX_DATAGRAM_SIZE - SMALL_HEADER_SIZE - (channel.len() + 1) {
+        return 1;
+    }
+
+    let available = MAX_DATAGRAM_SIZE - FRAG_HEADER_SIZE;
+    let first_available = available - channel.len() - 1;
+
+    1 + (message_size + available - first_available) / available
+}
+
+/// Writes a "small message" datagram into the buffer.
+///
+/// Returns the size of the datagram. This function will panic if the message
+/// does not actually fit within a small datagram.
+fn write_small_datagram(
+    buf: &mut [u8; MAX_DATAGRAM_SIZE],
+    sequence_number: u32,
+    channel: &str,
+    message: &[u8],
+) -> usize {
+    let mut buf = &mut buf[..];
+    let payload_start = SMALL_HEADER_SIZE + channel.len() + 1;
+    let payload_end = payload_start + message.len();
+
+    assert!(payload_end <= MAX_DATAGRAM_SIZE);
+
+    // We're writing to a slice, so these can never fail. Literally, the code
+    // for writing to a slice does not have a way to return an `Err`.
+    buf.write_u32::<NetworkEndian>(SHORT_HEADER_MAGIC).unwrap();
+    buf.write_u32::<NetworkEndian>(sequence_number).unwrap();
+    for &b in channel.as_bytes() {
+        buf.write_u8(b).unwrap();
+    }
+    buf.write_u8(0).unwrap();
+
+    buf.write_all(message).unwrap();
+
+    payload_end
+}
+
+/// A datagram received from the network.
+#[derive(Debug, PartialEq)]
+pub enum Datagram<'a> {
+    /// A message that fits in a single datagram.
+    Short(ShortDatagram<'a>),
+
+    /// A piece of a message that was split into fragments.
+    Fragment(FragmentDatagram<'a>),
+}
+impl<'a> Datagram<'a> {
+    /// Parses a datagram of either kind, based on its magic number.
+    ///
+    /// Returns `None` if the datagram is malformed. This only looks at the
+    /// datagram itself, so it never panics regardless of the input.
+    pub fn parse(datagram: &'a [u8]) -> Option<Self> {
+        // If it's too short, it absolutely can't be an LCM message.
+        if datagram.len() < 4 {
+            debug!("Datagram too short to be message. Dropping.");
+            return None;
+        }
+
+        match NetworkEndian::read_u32(&datagram[0..4]) {
+            SHORT_HEADER_MAGIC => ShortDatagram::parse(datagram).map(Datagram::Short),
+            LONG_HEADER_MAGIC => FragmentDatagram::parse(datagram).map(Datagram::Fragment),
+            _ => {
+                debug!("Invalid magic in datagram. Dropping.");
+                None
+            }
+        }
+    }
+}
+
+/// The contents of a "small message" datagram.
+#[derive(Debug, PartialEq)]
+pub struct ShortDatagram<'a> {
+    /// The sequence number of the message.
+    sequence_number: u32,
+
+    /// The channel the message was published on.
+    channel: &'a str,
+
+    /// The encoded message.
+    message: &'a [u8],
+}
+impl<'a> ShortDatagram<'a> {
+    /// Parses a short datagram, including the magic number.
+    ///
+    /// Returns `None` if the datagram is malformed.
+    fn parse(datagram: &'a [u8]) -> Option<Self> {
+        if datagram.len() < SMALL_HEADER_SIZE {
+            debug!("Short datagram is missing its header. Dropping.");
+            return None;
+        }
+
+        let sequence_number = NetworkEndian::read_u32(&datagram[4..8]);
+        let (channel, message) = split_channel(&datagram[SMALL_HEADER_SIZE..])?;
+
+        Some(ShortDatagram {
+            sequence_number,
+            channel,
+            message,
+        })
+    }
+}
+
+/// The contents of a datagram holding one fragment of a message.
+#[derive(Debug, PartialEq)]
+pub struct FragmentDatagram<'a> {
+    /// The sequence number of the message.
+    sequence_number: u32,
+
+    /// The size of the whole message.
+    payload_size: usize,
+
+    /// Where this fragment goes in the message.
+    fragment_offset: usize,
+
+    /// The position of this fragment, starting at zero.
+    fragment_number: u16,
+
+    /// The number of fragments the message was split into.
+    n_fragments: u16,
+
+    /// The channel the message was published on. Only the first fragment
+    /// includes it.
+    channel: Option<&'a str>,
+
+    /// The part of the encoded message in this fragment.
+    message: &'a [u8],
+}
+impl<'a> FragmentDatagram<'a> {
+    /// Parses a fragment datagram, including the magic number.
+    ///
+    /// Returns `None` if the datagram is malformed.
+    fn parse(datagram: &'a [u8]) -> Option<Self> {
+        if datagram.len() < FRAG_HEADER_SIZE {
+            debug!("Fragment datagram is missing its header. Dropping.");
+            return None;
+        }
+
+        let sequence_number = NetworkEndian::read_u32(&datagram[4..8]);
+        let payload_size = NetworkEndian::read_u32(&datagram[8..12]) as usize;
+        let fragment_offset = NetworkEndian::read_u32(&datagram[12..16]) as usize;
+        let fragment_number = NetworkEndian::read_u16(&datagram[16..18]);
+        let n_fragments = NetworkEndian::read_u16(&datagram[18..20]);
+
+        if fragment_number >= n_fragments {
+            debug!(
+                "Fragment {} of a message with {} fragments. Dropping.",
+                fragment_number, n_fragments
+            );
+            return None;
+        }
+
+        let (channel, message) = if fragment_number == 0 {
+            let (channel, message) = split_channel(&datagram[FRAG_HEADER_SIZE..])?;
+            (Some(channel), message)
+        } else {
+            (None, &datagram[FRAG_HEADER_SIZE..])
+        };
+
+        Some(FragmentDatagram {
+            sequence_number,
+            payload_size,
+            fragment_offset,
+            fragment_number,
+            n_fragments,
+            channel,
+            message,
+        })
+    }
+}
+
+/// Splits the null terminated channel name from the message that follows it.
+///
+/// Returns `None` if the channel name is not terminated, is longer than
+/// `MAX_CHANNEL_NAME_LENGTH`, or is not valid UTF-8. The search for the
+/// terminator stops at the maximum length, so that malformed datagrams are
+/// rejected quickly.
+fn split_channel(data: &[u8]) -> Option<(&str, &[u8])> {
+    use std::str;
+
+    let channel_name_end = match data.iter()
+        .take(MAX_CHANNEL_NAME_LENGTH + 1)
+        .position(|&b| b == 0)
+    {
+        Some(p) => p,
+        None if data.len() > MAX_CHANNEL_NAME_LENGTH => {
+            debug!("Channel name in datagram is too long. Dropping.");
+            return None;
+        }
+        None => {
+            debug!("Unable to parse channel name in datagram. Dropping.");
+            return None;
+        }
+    };
+
+    match str::from_utf8(&data[..channel_name_end]) {
+        Ok(channel) => Some((channel, &data[channel_name_end + 1..])),
+        Err(_) => {
+            debug!("Invalid UTF-8 in channel name. Dropping.");
+            None
+        }
+    }
+}
+
 /// The LCM backend used for receiving UDPM messages without blocking the main
 /// thread.
 pub struct Backend {
@@ -306,12 +912,46 @@ pub struct Backend {
     /// The channel used to subscribe to a new topic.
     subscribe_rx: mpsc::Receiver<SubscribeMsg>,
 
+    /// The channel used to remove a subscription.
+    unsubscribe_rx: mpsc::Receiver<Subscription>,
+
     /// The list of subscribed channels and the closure used to send the
     /// messages back to the provider object.
     subscriptions: Vec<SubscribeMsg>,
 
     /// Partially complete messages.
-    fragments: HashMap<SocketAddr, FragmentBuffer>,
+    ///
+    /// Each sender can have several messages in progress, oldest first.
+    fragments: HashMap<SocketAddr, VecDeque<FragmentBuffer>>,
+
+    /// The recently processed messages, if duplicates should be dropped.
+    deduplicator: Option<Deduplicator>,
+
+    /// The messages being held back, if messages are delivered in order.
+    reorderer: Option<Reorderer>,
+
+    /// The statistics about the received datagrams.
+    stats: Arc<Mutex<Stats>>,
+
+    /// The largest message that will be reassembled from fragments.
+    max_message_size: usize,
+
+    /// The number of messages from each sender that can be reassembled at
+    /// the same time. Starting another one drops the oldest.
+    max_reassemblies: usize,
+
+    /// The size above which reassembling a message logs a warning.
+    warn_message_size: usize,
+
+    /// Whether messages need to be decompressed before they are delivered.
+    compressed: bool,
+
+    /// Set while messages should be dropped instead of forwarded.
+    paused: Arc<AtomicBool>,
+
+    /// Whether datagrams and messages that look truncated are dropped
+    /// instead of being forwarded as they are.
+    drop_truncated: bool,
 }
 impl Backend {
     /// Create a `Backend` with the specified channels.
@@ -319,14 +959,40 @@ impl Backend {
         socket: UdpSocket,
         notify_tx: mpsc::SyncSender<()>,
         subscribe_rx: mpsc::Receiver<SubscribeMsg>,
-    ) -> Self {
-        Backend {
+        unsubscribe_rx: mpsc::Receiver<Subscription>,
+        dedup: bool,
+        ordered: bool,
+    ) -> io::Result<Self> {
+        // Removed subscriptions, and messages that are held back, need to be
+        // dealt with even if no more datagrams arrive, so don't wait on the
+        // socket forever.
+        socket.set_read_timeout(Some(cmp::min(SUBSCRIPTION_POLL_INTERVAL, REORDER_TIMEOUT)))?;
+
+        Ok(Backend {
             socket,
             notify_tx,
             subscribe_rx,
+            unsubscribe_rx,
             subscriptions: Vec::new(),
             fragments: HashMap::new(),
-        }
+            deduplicator: if dedup {
+                Some(Deduplicator::default())
+            } else {
+                None
+            },
+            reorderer: if ordered {
+                Some(Reorderer::new(REORDER_TIMEOUT))
+            } else {
+                None
+            },
+            stats: Arc::default(),
+            max_message_size: MAX_MESSAGE_SIZE,
+            max_reassemblies: MAX_REASSEMBLIES,
+            warn_message_size: WARN_MESSAGE_SIZE,
+            compressed: false,
+            paused: Arc::default(),
+            drop_truncated: false,
+        })
     }
 
     /// Enter the `Backend` execution loop.
@@ -340,28 +1006,40 @@ impl Backend {
         loop {
             // Wait for an incoming datagram
             trace!("Waiting on socket");
-            let (count, from) = self.socket.recv_from(&mut buf)?;
+            let (count, from) = match self.socket.recv_from(&mut buf) {
+                Ok(res) => res,
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    // Nothing has arrived for a while. Drop any removed
+                    // subscriptions and release any messages that have
+                    // waited long enough.
+                    self.update_subscriptions();
+                    if self.release_expired() && !self.notify() {
+                        break;
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let received = Instant::now();
             trace!("Datagram on socket");
-
-            // If the message used the whole buffer then there is a good chance
-            // that some bytes were discarded. We should warn the user.
-            if count == buf.len() {
-                debug!("Read buffer fully utilized. Bytes may have been dropped.");
+            {
+                let mut stats = self.stats.lock().expect("Statistics were poisoned");
+                stats.datagrams_received += 1;
+                stats.bytes_received += count as u64;
             }
 
             // Make sure the subscription list is fully up-to-date
-            self.check_for_subscriptions();
-
-            // If it's too short, it absolutely can't be an LCM message.
-            if count < 4 {
-                debug!("Datagram too short to be message. Dropping.");
-                continue;
-            }
+            self.update_subscriptions();
 
             // Try to process the message. If at least one of the subscriptions
             // accepts the message, notify the `Lcm` object. If the notify
             // channel is shut down, exit the loop and kill the thread.
-            if self.process_datagram(&buf[0..count], from) && !self.notify() {
+            let filled_buffer = count == buf.len();
+            let forwarded = self.process_received(&buf[0..count], filled_buffer, from, received);
+            if (self.release_expired() || forwarded) && !self.notify() {
                 break;
             }
         }
@@ -369,154 +1047,316 @@ impl Backend {
         Ok(())
     }
 
-    /// Process the given datagram.
-    fn process_datagram(&mut self, datagram: &[u8], sender: SocketAddr) -> bool {
+    /// Process a datagram that was read from the socket.
+    ///
+    /// If the datagram used the whole read buffer then there is a good chance
+    /// that the kernel discarded the rest of it. Such datagrams are dropped
+    /// if `drop_truncated` is set.
+    fn process_received(
+        &mut self,
+        datagram: &[u8],
+        filled_buffer: bool,
+        sender: SocketAddr,
+        received: Instant,
+    ) -> bool {
+        if filled_buffer {
+            if self.drop_truncated {
+                debug!("Read buffer fully utilized. Dropping the possibly truncated datagram.");
+                return dropped(&self.stats);
+            }
+            debug!("Read buffer fully utilized. Bytes may have been dropped.");
+        }
+
+        self.process_datagram(datagram, sender, received)
+    }
+
+    /// Process the given datagram, which arrived at `received`.
+    fn process_datagram(
+        &mut self,
+        datagram: &[u8],
+        sender: SocketAddr,
+        received: Instant,
+    ) -> bool {
         trace!(
             "Incoming datagram of size {} from {}.",
             datagram.len(),
             sender
         );
 
-        match NetworkEndian::read_u32(&datagram[0..4]) {
-            SHORT_HEADER_MAGIC => self.process_short_datagram(datagram),
-            LONG_HEADER_MAGIC => self.process_frag_datagram(datagram, sender),
-            _ => {
-                debug!("Invalid magic in datagram. Dropping.");
-                false
-            }
+        match Datagram::parse(datagram) {
+            Some(Datagram::Short(d)) => self.process_short_datagram(d, sender, received),
+            Some(Datagram::Fragment(d)) => self.process_frag_datagram(d, sender, received),
+            None => dropped(&self.stats),
         }
     }
 
     /// Retrieve the message from a short datagram
-    fn process_short_datagram(&mut self, datagram: &[u8]) -> bool {
-        use std::str;
-
+    fn process_short_datagram(
+        &mut self,
+        datagram: ShortDatagram,
+        sender: SocketAddr,
+        received: Instant,
+    ) -> bool {
         trace!("Incoming short datagram.");
+        trace!("Short datagram has sequence number {}", datagram.sequence_number);
 
-        // Find the channel name. Anything after that is the message.
-        let (channel, message) = {
-            let channel_name_end = match datagram
-                .iter()
-                .skip(SMALL_HEADER_SIZE)
-                .position(|&b| b == 0)
-            {
-                Some(p) => p + SMALL_HEADER_SIZE,
-                None => {
-                    debug!("Unable to parse channel name in datagram. Dropping.");
-                    return false;
-                }
-            };
-
-            let name_slice = &datagram[SMALL_HEADER_SIZE..channel_name_end];
-            match str::from_utf8(name_slice) {
-                Ok(s) => (s, &datagram[channel_name_end + 1..]),
-                Err(_) => {
-                    debug!("Invalid UTF-8 in channel name. Dropping.");
-                    return false;
-                }
+        if let Some(ref mut deduplicator) = self.deduplicator {
+            if !deduplicator.insert(sender, datagram.sequence_number) {
+                debug!("Duplicate short datagram. Dropping.");
+                return dropped(&self.stats);
             }
-        };
+        }
 
-        Backend::forward_message(&mut self.subscriptions, channel, message)
+        let meta = MessageMeta {
+            received,
+            sender,
+            fragmented: false,
+            fragment_count: 1,
+        };
+        self.deliver(
+            datagram.sequence_number,
+            datagram.channel,
+            datagram.message,
+            meta,
+        )
     }
 
     /// Retrieve the message portion from a fragment datagram.
-    fn process_frag_datagram(&mut self, datagram: &[u8], sender: SocketAddr) -> bool {
-        use std::str;
-
+    ///
+    /// A reassembled message is considered received when its last fragment
+    /// arrives.
+    fn process_frag_datagram(
+        &mut self,
+        datagram: FragmentDatagram,
+        sender: SocketAddr,
+        received: Instant,
+    ) -> bool {
         trace!("Incoming fragment datagram.");
 
-        let sequence_number = NetworkEndian::read_u32(&datagram[4..8]);
-        let payload_size = NetworkEndian::read_u32(&datagram[8..12]) as usize;
-        let fragment_offset = NetworkEndian::read_u32(&datagram[12..16]) as usize;
-        let fragment_number = NetworkEndian::read_u16(&datagram[16..18]);
-        let n_fragments = NetworkEndian::read_u16(&datagram[18..20]);
-
-        if payload_size > MAX_MESSAGE_SIZE {
-            debug!("Message too long. Dropping.");
-            return false;
-        }
+        let FragmentDatagram {
+            sequence_number,
+            payload_size,
+            fragment_offset,
+            fragment_number,
+            n_fragments,
+            channel,
+            message,
+        } = datagram;
+
+        if payload_size > self.max_message_size {
+            debug!(
+                "Message of {} bytes is longer than the limit of {}. Dropping.",
+                payload_size, self.max_message_size
+            );
+            return dropped(&self.stats);
+        }
 
         trace!("Recieved fragment {} of {}", fragment_number, n_fragments);
 
-        let fragment = self.fragments
-            .entry(sender)
-            .or_insert_with(|| FragmentBuffer {
-                parts_remaining: 0,
-                sequence_number: 0,
-                channel: String::new(),
-                buffer: Vec::new(),
-            });
+        if let Some(ref deduplicator) = self.deduplicator {
+            if deduplicator.contains(sender, sequence_number) {
+                debug!("Fragment of an already processed message. Dropping.");
+                return dropped(&self.stats);
+            }
+        }
+
+        // Find the message this fragment belongs to. If it is a new one and
+        // the sender already has too many in progress, the oldest is dropped
+        // and its buffer reused.
+        let reassemblies = self.fragments.entry(sender).or_default();
+        let index = match reassemblies
+            .iter()
+            .position(|fragment| fragment.sequence_number == sequence_number)
+        {
+            Some(index) => index,
+            None => {
+                let mut fragment = if reassemblies.len() >= self.max_reassemblies {
+                    let oldest = reassemblies.pop_front().expect("The limit is at least one");
+                    debug!(
+                        "Dropping fragmented message. Missing {} parts.",
+                        oldest.parts_remaining
+                    );
+                    oldest
+                } else {
+                    FragmentBuffer {
+                        parts_remaining: 0,
+                        bytes_received: 0,
+                        sequence_number,
+                        channel: String::new(),
+                        buffer: Vec::new(),
+                    }
+                };
+                fragment.parts_remaining = 0;
+                reassemblies.push_back(fragment);
+                reassemblies.len() - 1
+            }
+        };
+        let fragment = &mut reassemblies[index];
 
-        // If there is already a fragment, check to see if it is a part of this
-        // message. If not, clear it out.
-        if fragment.sequence_number != sequence_number || fragment.buffer.len() != payload_size {
+        // Check that the fragment agrees with the message's size. If not,
+        // clear the message out. A buffer with no parts remaining was only
+        // just created.
+        if fragment.parts_remaining == 0 || fragment.buffer.len() != payload_size {
             if fragment.parts_remaining != 0 {
                 debug!(
                     "Dropping fragmented message. Missing {} parts.",
                     fragment.parts_remaining
                 );
             }
+            if payload_size > self.warn_message_size {
+                warn!(
+                    "Reassembling a message of {} bytes from {} fragments.",
+                    payload_size, n_fragments
+                );
+            }
             fragment.parts_remaining = n_fragments;
+            fragment.bytes_received = 0;
             fragment.sequence_number = sequence_number;
             fragment.channel.clear();
             fragment.buffer.resize(payload_size, 0);
         }
 
         // Place this fragment in the buffer.
-        let message = if fragment_number == 0 {
-            let channel_name_end =
-                match datagram.iter().skip(FRAG_HEADER_SIZE).position(|&b| b == 0) {
-                    Some(p) => p + FRAG_HEADER_SIZE,
-                    None => {
-                        debug!("Unable to parse channel name in datagram. Dropping.");
-                        return false;
-                    }
-                };
-
-            let name_slice = &datagram[FRAG_HEADER_SIZE..channel_name_end];
-            match str::from_utf8(name_slice) {
-                Ok(s) => {
-                    if fragment.channel.is_empty() {
-                        fragment.channel.push_str(s);
-                    }
-
-                    &datagram[channel_name_end + 1..]
-                }
-                Err(_) => {
-                    debug!("Invalid UTF-8 in channel name. Dropping.");
-                    return false;
-                }
+        if let Some(channel) = channel {
+            if fragment.channel.is_empty() {
+                fragment.channel.push_str(channel);
             }
-        } else {
-            &datagram[FRAG_HEADER_SIZE..]
-        };
+        }
+
+        if fragment_offset > fragment.buffer.len()
+            || message.len() > fragment.buffer.len() - fragment_offset
+        {
+            debug!("Fragment extends past the end of the message. Dropping.");
+            return dropped(&self.stats);
+        }
 
         fragment.parts_remaining -= 1;
+        fragment.bytes_received += message.len();
         fragment.buffer[fragment_offset..fragment_offset + message.len()].copy_from_slice(message);
 
         // If we aren't waiting on any more parts, forward the message.
         if fragment.parts_remaining == 0 {
-            Backend::forward_message(&mut self.subscriptions, &fragment.channel, &fragment.buffer)
+            let fragment = reassemblies
+                .remove(index)
+                .expect("The fragment buffer was just used");
+            if reassemblies.is_empty() {
+                self.fragments.remove(&sender);
+            }
+
+            // The fragments of a message never overlap, so if they don't add
+            // up to its size, at least one of them was cut short.
+            if fragment.bytes_received != payload_size {
+                debug!(
+                    "Fragments held {} bytes of a message of {} bytes.",
+                    fragment.bytes_received, payload_size
+                );
+                if self.drop_truncated {
+                    debug!("Dropping the truncated message.");
+                    return dropped(&self.stats);
+                }
+            }
+
+            if let Some(ref mut deduplicator) = self.deduplicator {
+                deduplicator.insert(sender, sequence_number);
+            }
+            let meta = MessageMeta {
+                received,
+                sender,
+                fragmented: true,
+                fragment_count: n_fragments,
+            };
+            self.deliver(sequence_number, &fragment.channel, &fragment.buffer, meta)
         } else {
             false
         }
     }
 
-    /// Sends the message to the callbacks.
+    /// Sends a complete message to the callbacks, or holds it back if an
+    /// earlier message from the same sender is still missing.
+    fn deliver(
+        &mut self,
+        sequence_number: u32,
+        channel: &str,
+        message: &[u8],
+        meta: MessageMeta,
+    ) -> bool {
+        let decompressed;
+        let message = if self.compressed {
+            decompressed = match decompress(message, self.max_message_size) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Dropping message on \"{}\" that could not be decompressed: {}", channel, e);
+                    return false;
+                }
+            };
+            &decompressed[..]
+        } else {
+            message
+        };
+
+        {
+            let mut stats = self.stats.lock().expect("Statistics were poisoned");
+            stats.record_message(channel, meta.received);
+        }
+
+        let subscriptions = &mut self.subscriptions;
+        let paused = &*self.paused;
+        match self.reorderer {
+            None => Backend::forward_message(subscriptions, paused, channel, message, &meta),
+            Some(ref mut reorderer) => {
+                let mut forwarded = false;
+                reorderer.push(
+                    sequence_number,
+                    channel,
+                    message,
+                    meta,
+                    &mut |channel, message, meta| {
+                        forwarded |=
+                            Backend::forward_message(subscriptions, paused, channel, message, meta)
+                    },
+                );
+                forwarded
+            }
+        }
+    }
+
+    /// Sends any held back messages that have waited too long to the
+    /// callbacks.
+    fn release_expired(&mut self) -> bool {
+        let subscriptions = &mut self.subscriptions;
+        let paused = &*self.paused;
+        match self.reorderer {
+            None => false,
+            Some(ref mut reorderer) => {
+                let mut forwarded = false;
+                reorderer.release_expired(Instant::now(), &mut |channel, message, meta| {
+                    forwarded |=
+                        Backend::forward_message(subscriptions, paused, channel, message, meta)
+                });
+                forwarded
+            }
+        }
+    }
+
+    /// Sends the message to the callbacks, unless the backend is paused.
     ///
     /// The function has this form to fight the borrow checker.
     fn forward_message(
         subscriptions: &mut Vec<SubscribeMsg>,
+        paused: &AtomicBool,
         channel: &str,
         message: &[u8],
+        meta: &MessageMeta,
     ) -> bool {
-        // FIXME:
-        // Dealing with unsubscriptions this way means that resources aren't
-        // released until the first message received on the unsubscribed
-        // channel.
+        if paused.load(Ordering::Acquire) {
+            trace!("Dropping message on channel \"{}\" while paused", channel);
+            return false;
+        }
+
+        // Subscriptions whose queues have been dropped, e.g., because the
+        // `Lcm` object is gone, are also removed here.
         let mut forwarded = false;
-        subscriptions.retain(|&(ref re, ref f)| {
+        subscriptions.retain(|&(_, ref re, ref f)| {
             trace!(
                 "Checking if channel \"{}\" matches regular expression \"{}\"",
                 channel,
@@ -524,14 +1364,14 @@ impl Backend {
             );
             if re.is_match(channel) {
                 trace!("Channel \"{}\" matched subscription \"{}\"", channel, re);
-                match (*f)(channel, message) {
+                match (*f)(channel, message, meta) {
                     Err(TrampolineError::MessageChannelClosed) => false,
                     Err(e) => {
                         warn!("Error decoding message: {}", e);
                         true
                     }
-                    Ok(_) => {
-                        forwarded = true;
+                    Ok(queued) => {
+                        forwarded |= queued;
                         true
                     }
                 }
@@ -543,9 +1383,17 @@ impl Backend {
         forwarded
     }
 
-    /// Checks to see if there are new pending subscriptions.
-    fn check_for_subscriptions(&mut self) {
+    /// Adds any new subscriptions and removes the ones that are gone.
+    ///
+    /// The removals are read first. A subscription is always sent before it
+    /// is removed, so it is then guaranteed to have arrived.
+    fn update_subscriptions(&mut self) {
+        let removed: Vec<_> = self.unsubscribe_rx.try_iter().collect();
         self.subscriptions.extend(self.subscribe_rx.try_iter());
+        if !removed.is_empty() {
+            self.subscriptions
+                .retain(|&(ref sub, _, _)| !removed.contains(sub));
+        }
     }
 
     /// Notifies the provider object that there is at least one message queued.
@@ -567,6 +1415,9 @@ struct FragmentBuffer {
     /// The number of fragments still necessary for this message.
     parts_remaining: u16,
 
+    /// The number of bytes of the message received so far.
+    bytes_received: usize,
+
     /// The sequence number of this message.
     sequence_number: u32,
 
@@ -576,3 +1427,1166 @@ struct FragmentBuffer {
     /// The received parts of the message.
     buffer: Vec<u8>,
 }
+
+/// Remembers the sequence numbers of recently processed messages.
+#[derive(Default)]
+struct Deduplicator {
+    /// The most recent sequence numbers for each sender, oldest first.
+    recent: HashMap<SocketAddr, VecDeque<u32>>,
+}
+impl Deduplicator {
+    /// Returns true if the message was already processed.
+    fn contains(&self, sender: SocketAddr, sequence_number: u32) -> bool {
+        match self.recent.get(&sender) {
+            Some(recent) => recent.contains(&sequence_number),
+            None => false,
+        }
+    }
+
+    /// Records a message as processed.
+    ///
+    /// Returns false if the message had already been processed.
+    fn insert(&mut self, sender: SocketAddr, sequence_number: u32) -> bool {
+        let recent = self.recent
+            .entry(sender)
+            .or_insert_with(|| VecDeque::with_capacity(DEDUP_WINDOW_SIZE));
+        if recent.contains(&sequence_number) {
+            return false;
+        }
+
+        if recent.len() == DEDUP_WINDOW_SIZE {
+            recent.pop_front();
+        }
+        recent.push_back(sequence_number);
+        true
+    }
+}
+
+/// Holds back messages so that each sender's messages are delivered in order.
+///
+/// Messages are held back until the missing messages arrive, until too many
+/// messages are waiting, or until the oldest waiting message has waited longer
+/// than the timeout. In the latter two cases, the missing messages are skipped.
+/// Messages that arrive after they were skipped are delivered as-is.
+struct Reorderer {
+    /// The messages being held back for each sender.
+    senders: HashMap<SocketAddr, ReorderBuffer>,
+
+    /// How long a message can be held back.
+    timeout: Duration,
+}
+impl Reorderer {
+    /// Creates a new `Reorderer` with the given timeout.
+    fn new(timeout: Duration) -> Self {
+        Reorderer {
+            senders: HashMap::new(),
+            timeout,
+        }
+    }
+
+    /// Adds a message, calling `forward` for every message that is ready.
+    fn push(
+        &mut self,
+        sequence_number: u32,
+        channel: &str,
+        message: &[u8],
+        meta: MessageMeta,
+        forward: &mut FnMut(&str, &[u8], &MessageMeta),
+    ) {
+        let sender = meta.sender;
+        let buffer = match self.senders.get_mut(&sender) {
+            Some(buffer) => buffer,
+            None => {
+                // This is the first message from this sender.
+                forward(channel, message, &meta);
+                self.senders.insert(
+                    sender,
+                    ReorderBuffer {
+                        next: sequence_number.wrapping_add(1),
+                        pending: VecDeque::new(),
+                    },
+                );
+                return;
+            }
+        };
+
+        let offset = sequence_number.wrapping_sub(buffer.next) as i32;
+        if offset < 0 {
+            debug!("Message arrived after it was skipped. Delivering out of order.");
+            forward(channel, message, &meta);
+        } else if offset as usize >= REORDER_WINDOW_SIZE {
+            debug!("Message is too far ahead. Skipping all missing messages.");
+            buffer.release_all(forward);
+            forward(channel, message, &meta);
+            buffer.next = sequence_number.wrapping_add(1);
+        } else {
+            let offset = offset as usize;
+            if buffer.pending.len() <= offset {
+                buffer.pending.resize(offset + 1, None);
+            }
+            buffer.pending[offset] = Some(PendingMessage {
+                channel: channel.into(),
+                message: message.into(),
+                meta,
+            });
+            buffer.release_ready(forward);
+        }
+    }
+
+    /// Skips any missing messages that held back others for too long, calling
+    /// `forward` for every message that is ready.
+    fn release_expired(&mut self, now: Instant, forward: &mut FnMut(&str, &[u8], &MessageMeta)) {
+        for buffer in self.senders.values_mut() {
+            while let Some(received) = buffer.oldest() {
+                if now.duration_since(received) < self.timeout {
+                    break;
+                }
+
+                debug!("Timed out waiting for message {}. Skipping.", buffer.next);
+                while let Some(None) = buffer.pending.front() {
+                    buffer.pending.pop_front();
+                    buffer.next = buffer.next.wrapping_add(1);
+                }
+                buffer.release_ready(forward);
+            }
+        }
+    }
+}
+
+/// The messages held back for a single sender.
+struct ReorderBuffer {
+    /// The next sequence number to be delivered.
+    next: u32,
+
+    /// The messages that arrived early.
+    ///
+    /// The message at index `i` has the sequence number `next + i`, so the
+    /// first entry is always empty when there are messages waiting.
+    pending: VecDeque<Option<PendingMessage>>,
+}
+impl ReorderBuffer {
+    /// Returns the time at which the oldest waiting message was received.
+    fn oldest(&self) -> Option<Instant> {
+        self.pending
+            .iter()
+            .filter_map(|m| m.as_ref().map(|m| m.meta.received))
+            .min()
+    }
+
+    /// Forwards all messages up to the next missing one.
+    fn release_ready(&mut self, forward: &mut FnMut(&str, &[u8], &MessageMeta)) {
+        while let Some(&Some(_)) = self.pending.front() {
+            if let Some(Some(m)) = self.pending.pop_front() {
+                forward(&m.channel, &m.message, &m.meta);
+            }
+            self.next = self.next.wrapping_add(1);
+        }
+    }
+
+    /// Forwards all of the waiting messages, skipping the missing ones.
+    fn release_all(&mut self, forward: &mut FnMut(&str, &[u8], &MessageMeta)) {
+        for m in self.pending.drain(..).flatten() {
+            forward(&m.channel, &m.message, &m.meta);
+        }
+    }
+}
+
+/// A message that is being held back.
+#[derive(Clone)]
+struct PendingMessage {
+    /// The channel the message was published on.
+    channel: String,
+
+    /// The encoded message.
+    message: Vec<u8>,
+
+    /// How the message was received.
+    meta: MessageMeta,
+}
+
+/// The state shared between the provider and the backend thread about the
+/// thread exiting.
+#[derive(Default)]
+struct BackendExit {
+    /// Set once the backend thread has exited.
+    exited: bool,
+
+    /// The function to call once the backend thread exits.
+    hook: Option<Box<FnOnce() + Send>>,
+}
+
+/// Counts a datagram that was dropped.
+///
+/// Always returns `false`, since nothing was forwarded.
+fn dropped(stats: &Mutex<Stats>) -> bool {
+    stats.lock().expect("Statistics were poisoned").datagrams_dropped += 1;
+    false
+}
+
+/// Calls `send` until it succeeds, retrying transient errors up to `retries`
+/// times.
+///
+/// The delay before the first retry is `backoff`, and it doubles after each
+/// one, up to `MAX_PUBLISH_BACKOFF`. Other errors, such as a datagram that is too large, are returned
+/// immediately, as is the last error once the retries run out.
+fn send_with_retry<F>(retries: usize, backoff: Duration, mut send: F) -> io::Result<usize>
+where
+    F: FnMut() -> io::Result<usize>,
+{
+    let mut delay = backoff;
+    let mut attempt = 0;
+    loop {
+        match send() {
+            Err(ref e) if attempt < retries && is_transient(e) => {
+                debug!("Resending a datagram after a transient error: {}", e);
+                thread::sleep(delay);
+                if delay < MAX_PUBLISH_BACKOFF {
+                    delay = cmp::min(delay * 2, MAX_PUBLISH_BACKOFF);
+                }
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Returns true if sending again may succeed, e.g., once a non-blocking
+/// socket's buffer has room.
+fn is_transient(err: &io::Error) -> bool {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted | io::ErrorKind::TimedOut => true,
+        _ => is_out_of_buffers(err),
+    }
+}
+
+/// Returns true if the system ran out of buffers for outgoing packets, which
+/// happens when sending faster than the interface can keep up.
+#[cfg(unix)]
+fn is_out_of_buffers(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(::libc::ENOBUFS)
+}
+
+/// Returns true if the system ran out of buffers for outgoing packets, which
+/// happens when sending faster than the interface can keep up.
+#[cfg(windows)]
+fn is_out_of_buffers(err: &io::Error) -> bool {
+    // WSAENOBUFS
+    err.raw_os_error() == Some(10055)
+}
+
+/// Other platforms aren't known to report this separately.
+#[cfg(not(any(unix, windows)))]
+fn is_out_of_buffers(_err: &io::Error) -> bool {
+    false
+}
+
+/// Parses a size in bytes from the LCM URL.
+fn parse_size(key: &str, value: &str) -> Result<usize, InitError> {
+    value
+        .parse()
+        .map_err(|_| InitError::InvalidSize(key.into()))
+}
+
+/// Compresses a message before it is sent, when the "compress" option is set.
+#[cfg(feature = "compression")]
+fn compress(message: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(message)?;
+    encoder.finish()
+}
+
+/// Decompresses a received message, when the "compress" option is set.
+///
+/// Messages that would decompress to more than `max_size` bytes are rejected
+/// without being decompressed any further.
+#[cfg(feature = "compression")]
+fn decompress(message: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut decompressed = Vec::new();
+    ZlibDecoder::new(message)
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+    if decompressed.len() > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the decompressed message is too large",
+        ));
+    }
+    Ok(decompressed)
+}
+
+#[cfg(not(feature = "compression"))]
+fn compress(_: &[u8]) -> io::Result<Vec<u8>> {
+    unreachable!("The \"compress\" option requires the \"compression\" feature")
+}
+
+#[cfg(not(feature = "compression"))]
+fn decompress(_: &[u8], _: usize) -> io::Result<Vec<u8>> {
+    unreachable!("The \"compress\" option requires the \"compression\" feature")
+}
+
+/// Asks for the socket's receive buffer to hold `size` bytes.
+///
+/// The system may grant a different size, e.g., Linux doubles the request
+/// and caps it at `net.core.rmem_max`, so the actual size is logged.
+fn set_recv_buffer_size(socket: &UdpSocket, size: usize) -> Result<(), InitError> {
+    use net2::UdpSocketExt;
+
+    let res = if size > i32::MAX as usize {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "The size is too large"))
+    } else {
+        socket.set_recv_buffer_size(size)
+    };
+    res.map_err(|source| InitError::RecvBufSizeFailed { size, source })?;
+
+    let granted = socket.recv_buffer_size()?;
+    info!(
+        "Requested a receive buffer of {} bytes and was given {} bytes",
+        size, granted
+    );
+    Ok(())
+}
+
+/// Sets the time-to-live, or the hop limit for IPv6, of outgoing multicast
+/// packets sent to `addr`.
+fn set_multicast_ttl(socket: &UdpSocket, addr: SocketAddr, ttl: u32) -> io::Result<()> {
+    use net2::UdpSocketExt;
+
+    if addr.is_ipv6() {
+        socket.set_multicast_hops_v6(ttl)
+    } else {
+        socket.set_multicast_ttl_v4(ttl)
+    }
+}
+
+/// Selects the interface that outgoing multicast packets to `addr` are sent
+/// on.
+///
+/// For IPv4, a TTL of 0 means the packets should never leave this host, so
+/// they are sent on the loopback interface. Otherwise, the system picks the
+/// interface. For IPv6, a hop limit of 0 already keeps them on this host, and
+/// they are sent on the interface `if_index`.
+fn set_multicast_interface(
+    socket: &UdpSocket,
+    addr: SocketAddr,
+    ttl: u32,
+    if_index: u32,
+) -> io::Result<()> {
+    use net2::UdpSocketExt;
+
+    if addr.is_ipv6() {
+        debug!("Sending multicast packets on interface {}", if_index);
+        return socket.set_multicast_if_v6(if_index);
+    }
+
+    let interface = if ttl == 0 {
+        Ipv4Addr::new(127, 0, 0, 1)
+    } else {
+        Ipv4Addr::new(0, 0, 0, 0)
+    };
+    debug!("Sending multicast packets on {}", interface);
+    socket.set_multicast_if_v4(&interface)
+}
+
+/// Parses a boolean option from the LCM URL.
+fn parse_flag(key: &str, value: &str) -> Result<bool, InitError> {
+    value
+        .parse()
+        .map_err(|_| InitError::InvalidFlag(key.into()))
+}
+
+/// Parses a datagram the way the backend does, without needing a socket.
+///
+/// Returns true if the datagram is well formed. This is an entry point for
+/// fuzzing.
+#[cfg(fuzzing)]
+pub fn fuzz_parse_datagram(datagram: &[u8]) -> bool {
+    Datagram::parse(datagram).is_some()
+}
+
+/// Runs the datagrams through a backend as if they had all arrived from the
+/// same sender, and returns the number of messages that were forwarded.
+///
+/// This is an entry point for fuzzing the reassembly of fragments. The
+/// backend's socket is bound to the loopback interface but never read.
+#[cfg(fuzzing)]
+pub fn fuzz_process_datagrams(datagrams: &[&[u8]], dedup: bool) -> usize {
+    let socket = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind a socket");
+    let (notify_tx, _notify_rx) = mpsc::sync_channel(1);
+    let (_subscribe_tx, subscribe_rx) = mpsc::channel();
+    let (_unsubscribe_tx, unsubscribe_rx) = mpsc::channel();
+    let mut backend = Backend::new(socket, notify_tx, subscribe_rx, unsubscribe_rx, dedup, false)
+        .expect("Failed to create the backend");
+    backend.subscriptions.push((
+        Subscription(0),
+        ::regex::Regex::new("").expect("The empty expression is valid"),
+        Box::new(|_: &str, _: &[u8], _: &MessageMeta| Ok(true)),
+    ));
+
+    let sender = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 7667);
+    datagrams
+        .iter()
+        .filter(|datagram| backend.process_datagram(datagram, sender, Instant::now()))
+        .count()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use log;
+    use regex::Regex;
+    use std::cell::RefCell;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A logger that records the warnings logged on each thread.
+    struct WarningLogger;
+    impl log::Log for WarningLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Warn
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                WARNINGS.with(|w| w.borrow_mut().push(record.args().to_string()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: WarningLogger = WarningLogger;
+    thread_local! {
+        static WARNINGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Validates the URL and creates a provider from it, the same way `Lcm`
+    /// does, and returns the warnings that were logged about its options.
+    fn provider_warnings(url: &str) -> Vec<String> {
+        // Another test may have installed the logger already.
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Warn);
+        WARNINGS.with(|w| w.borrow_mut().clear());
+
+        let url = Url::parse(url).unwrap();
+        UdpmProvider::validate_url(&url).unwrap();
+        let (_, subscribe_rx) = mpsc::channel();
+        let (_, unsubscribe_rx) = mpsc::channel();
+        UdpmProvider::new(&url, subscribe_rx, unsubscribe_rx).unwrap();
+        WARNINGS.with(|w| {
+            w.borrow()
+                .iter()
+                .filter(|m| m.contains("option"))
+                .cloned()
+                .collect()
+        })
+    }
+
+    /// Creates a backend that is not connected to anything, along with a
+    /// counter of the messages received on the "TEST" channel.
+    fn backend(dedup: bool) -> (Backend, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (notify_tx, _) = mpsc::sync_channel(1);
+        let (_, subscribe_rx) = mpsc::channel();
+        let (_, unsubscribe_rx) = mpsc::channel();
+        let mut backend =
+            Backend::new(socket, notify_tx, subscribe_rx, unsubscribe_rx, dedup, false).unwrap();
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        backend.subscriptions.push((
+            Subscription(0),
+            Regex::new("^TEST$").unwrap(),
+            Box::new(move |_: &str, _: &[u8], _: &MessageMeta| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(true)
+            }),
+        ));
+
+        (backend, count)
+    }
+
+    fn short_datagram(sequence_number: u32) -> Vec<u8> {
+        let mut buf = [0u8; MAX_DATAGRAM_SIZE];
+        let size = write_small_datagram(&mut buf, sequence_number, "TEST", b"payload");
+        buf[..size].to_vec()
+    }
+
+    fn sender() -> SocketAddr {
+        "192.0.2.1:7667".parse().unwrap()
+    }
+
+    #[test]
+    fn parse_short_datagram() {
+        let mut buf = [0u8; MAX_DATAGRAM_SIZE];
+        let size = write_small_datagram(&mut buf, 0xDEAD_BEEF, "CHANNEL", &[1, 2, 3]);
+
+        assert_eq!(
+            ShortDatagram::parse(&buf[..size]),
+            Some(ShortDatagram {
+                sequence_number: 0xDEAD_BEEF,
+                channel: "CHANNEL",
+                message: &[1, 2, 3],
+            })
+        );
+    }
+
+    #[test]
+    fn long_channel_name() {
+        let (mut backend, count) = backend(false);
+        let mut datagram = Vec::new();
+        datagram.write_u32::<NetworkEndian>(SHORT_HEADER_MAGIC).unwrap();
+        datagram.write_u32::<NetworkEndian>(0).unwrap();
+        datagram.extend(vec![b'A'; MAX_DATAGRAM_SIZE - SMALL_HEADER_SIZE]);
+
+        assert_eq!(ShortDatagram::parse(&datagram), None);
+        assert!(!backend.process_datagram(&datagram, sender(), Instant::now()));
+
+        // Terminating the name doesn't help if it is still too long.
+        datagram[SMALL_HEADER_SIZE + MAX_CHANNEL_NAME_LENGTH + 1] = 0;
+        assert_eq!(ShortDatagram::parse(&datagram), None);
+
+        // The longest allowed name is fine.
+        datagram[SMALL_HEADER_SIZE + MAX_CHANNEL_NAME_LENGTH] = 0;
+        let parsed = ShortDatagram::parse(&datagram).unwrap();
+        assert_eq!(parsed.channel.len(), MAX_CHANNEL_NAME_LENGTH);
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    /// Builds a fragment datagram with the given header fields.
+    fn fragment_datagram(
+        sequence_number: u32,
+        payload_size: u32,
+        fragment_offset: u32,
+        fragment_number: u16,
+        n_fragments: u16,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut datagram = Vec::new();
+        datagram.write_u32::<NetworkEndian>(LONG_HEADER_MAGIC).unwrap();
+        datagram.write_u32::<NetworkEndian>(sequence_number).unwrap();
+        datagram.write_u32::<NetworkEndian>(payload_size).unwrap();
+        datagram.write_u32::<NetworkEndian>(fragment_offset).unwrap();
+        datagram.write_u16::<NetworkEndian>(fragment_number).unwrap();
+        datagram.write_u16::<NetworkEndian>(n_fragments).unwrap();
+        datagram.extend(payload);
+        datagram
+    }
+
+    #[test]
+    fn parse_truncated_headers() {
+        let short = short_datagram(0);
+        for len in 0..SMALL_HEADER_SIZE {
+            assert_eq!(Datagram::parse(&short[..len]), None);
+        }
+
+        let fragment = fragment_datagram(0, 7, 0, 0, 1, b"TEST\0payload");
+        for len in 0..FRAG_HEADER_SIZE {
+            assert_eq!(Datagram::parse(&fragment[..len]), None);
+        }
+        assert!(Datagram::parse(&fragment).is_some());
+    }
+
+    #[test]
+    fn truncated_datagrams() {
+        let (mut backend, count) = backend(false);
+        let short = short_datagram(0);
+        let fragment = fragment_datagram(0, 7, 0, 0, 1, b"TEST\0payload");
+
+        // Anything cut off before the end of the channel name is dropped.
+        // After that, only the payload is truncated, which the backend can't
+        // tell apart from a shorter message.
+        let cases = [(&short, SMALL_HEADER_SIZE), (&fragment, FRAG_HEADER_SIZE)];
+        for &(datagram, header_size) in &cases {
+            let name_end = header_size + "TEST".len() + 1;
+            for len in 0..datagram.len() {
+                let forwarded =
+                    backend.process_datagram(&datagram[..len], sender(), Instant::now());
+                assert_eq!(forwarded, len >= name_end, "Datagram truncated to {} bytes", len);
+            }
+        }
+
+        let dropped = SMALL_HEADER_SIZE + FRAG_HEADER_SIZE + 2 * ("TEST".len() + 1);
+        assert_eq!(backend.stats.lock().unwrap().datagrams_dropped as usize, dropped);
+        assert_eq!(count.load(Ordering::SeqCst), short.len() + fragment.len() - dropped);
+    }
+
+    #[test]
+    fn drop_truncated_datagram() {
+        let (mut backend, count) = backend(false);
+        let datagram = short_datagram(0);
+
+        assert!(backend.process_received(&datagram, true, sender(), Instant::now()));
+        backend.drop_truncated = true;
+        assert!(!backend.process_received(&datagram, true, sender(), Instant::now()));
+        assert!(backend.process_received(&datagram, false, sender(), Instant::now()));
+
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        assert_eq!(backend.stats.lock().unwrap().datagrams_dropped, 1);
+    }
+
+    #[test]
+    fn drop_truncated_fragments() {
+        let (mut backend, count) = backend(false);
+        let first = fragment_datagram(0, 8, 0, 0, 2, b"TEST\0abcd");
+        let last = fragment_datagram(0, 8, 4, 1, 2, b"efg");
+
+        assert!(!backend.process_datagram(&first, sender(), Instant::now()));
+        assert!(backend.process_datagram(&last, sender(), Instant::now()));
+
+        backend.drop_truncated = true;
+        assert!(!backend.process_datagram(&first, sender(), Instant::now()));
+        assert!(!backend.process_datagram(&last, sender(), Instant::now()));
+
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert_eq!(backend.stats.lock().unwrap().datagrams_dropped, 1);
+    }
+
+    #[test]
+    fn max_reassemblies() {
+        let (mut backend, count) = backend(false);
+        backend.max_reassemblies = 2;
+        let first = |seq| fragment_datagram(seq, 8, 0, 0, 2, b"TEST\0abcd");
+        let last = |seq| fragment_datagram(seq, 8, 4, 1, 2, b"efgh");
+
+        // Starting a third message drops the first, which can't be completed.
+        for seq in 0..3 {
+            assert!(!backend.process_datagram(&first(seq), sender(), Instant::now()));
+        }
+        assert_eq!(backend.fragments[&sender()].len(), 2);
+        assert!(backend.process_datagram(&last(2), sender(), Instant::now()));
+        assert!(backend.process_datagram(&last(1), sender(), Instant::now()));
+        assert!(!backend.fragments.contains_key(&sender()));
+        assert!(!backend.process_datagram(&last(0), sender(), Instant::now()));
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        // The orphaned last fragment of the first message is still held,
+        // but doesn't count against other senders.
+        let other = "192.0.2.2:7667".parse().unwrap();
+        assert!(!backend.process_datagram(&first(0), other, Instant::now()));
+        assert!(!backend.process_datagram(&first(1), other, Instant::now()));
+        assert_eq!(backend.fragments[&other].len(), 2);
+        assert_eq!(backend.fragments[&sender()].len(), 1);
+    }
+
+    #[test]
+    fn invalid_fragment_numbers() {
+        let (mut backend, count) = backend(false);
+
+        // A message without any fragments, and a fragment past the last one.
+        assert_eq!(Datagram::parse(&fragment_datagram(0, 0, 0, 0, 0, b"TEST\0")), None);
+        assert_eq!(Datagram::parse(&fragment_datagram(0, 4, 0, 2, 2, b"data")), None);
+
+        // An empty message with sequence number zero matches the state of a
+        // newly created fragment buffer.
+        let empty = fragment_datagram(0, 0, 0, 0, 1, b"TEST\0");
+        assert!(backend.process_datagram(&empty, sender(), Instant::now()));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn fragment_offset_overflow() {
+        let (mut backend, count) = backend(false);
+        let datagram = fragment_datagram(0, 16, u32::MAX, 1, 2, b"data");
+        assert!(!backend.process_datagram(&datagram, sender(), Instant::now()));
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn long_channel_name_in_fragment() {
+        let (mut backend, count) = backend(false);
+        let mut datagram = Vec::new();
+        datagram.write_u32::<NetworkEndian>(LONG_HEADER_MAGIC).unwrap();
+        datagram.write_u32::<NetworkEndian>(0).unwrap();
+        datagram.write_u32::<NetworkEndian>(10).unwrap();
+        datagram.write_u32::<NetworkEndian>(0).unwrap();
+        datagram.write_u16::<NetworkEndian>(0).unwrap();
+        datagram.write_u16::<NetworkEndian>(1).unwrap();
+        datagram.extend(vec![b'A'; MAX_DATAGRAM_SIZE - FRAG_HEADER_SIZE]);
+
+        assert!(!backend.process_datagram(&datagram, sender(), Instant::now()));
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn duplicate_short_datagram() {
+        let (mut backend, count) = backend(true);
+
+        assert!(backend.process_datagram(&short_datagram(7), sender(), Instant::now()));
+        assert!(!backend.process_datagram(&short_datagram(7), sender(), Instant::now()));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // Other sequence numbers and other senders are still delivered.
+        assert!(backend.process_datagram(&short_datagram(8), sender(), Instant::now()));
+        let other = "192.0.2.2:7667".parse().unwrap();
+        assert!(backend.process_datagram(&short_datagram(7), other, Instant::now()));
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn duplicates_without_dedup() {
+        let (mut backend, count) = backend(false);
+
+        assert!(backend.process_datagram(&short_datagram(7), sender(), Instant::now()));
+        assert!(backend.process_datagram(&short_datagram(7), sender(), Instant::now()));
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn dedup_window() {
+        let mut deduplicator = Deduplicator::default();
+
+        for i in 0..DEDUP_WINDOW_SIZE as u32 + 1 {
+            assert!(deduplicator.insert(sender(), i));
+        }
+        assert!(!deduplicator.contains(sender(), 0));
+        assert!(deduplicator.contains(sender(), 1));
+    }
+
+    /// Records the order in which messages are released by a `Reorderer`.
+    fn push_all(reorderer: &mut Reorderer, sequence_numbers: &[u32], now: Instant) -> Vec<u8> {
+        let mut released = Vec::new();
+        for &n in sequence_numbers {
+            let meta = MessageMeta {
+                received: now,
+                sender: sender(),
+                fragmented: false,
+                fragment_count: 1,
+            };
+            reorderer.push(n, "TEST", &[n as u8], meta, &mut |_, m, _| released.push(m[0]));
+        }
+        released
+    }
+
+    #[test]
+    fn reorder_messages() {
+        let mut reorderer = Reorderer::new(Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert_eq!(push_all(&mut reorderer, &[10], now), [10]);
+        assert_eq!(push_all(&mut reorderer, &[12, 14], now), []);
+        assert_eq!(push_all(&mut reorderer, &[11], now), [11, 12]);
+        assert_eq!(push_all(&mut reorderer, &[13], now), [13, 14]);
+        assert_eq!(push_all(&mut reorderer, &[15], now), [15]);
+    }
+
+    #[test]
+    fn reorder_timeout() {
+        let timeout = Duration::from_millis(10);
+        let mut reorderer = Reorderer::new(timeout);
+        let now = Instant::now();
+
+        assert_eq!(push_all(&mut reorderer, &[0, 2, 3], now), [0]);
+
+        let mut released = Vec::new();
+        reorderer.release_expired(now, &mut |_, m, _| released.push(m[0]));
+        assert!(released.is_empty());
+        reorderer.release_expired(now + timeout, &mut |_, m, _| released.push(m[0]));
+        assert_eq!(released, [2, 3]);
+
+        // The skipped message is still delivered if it arrives late.
+        assert_eq!(push_all(&mut reorderer, &[1, 4], now), [1, 4]);
+    }
+
+    #[test]
+    fn reorder_outside_window() {
+        let mut reorderer = Reorderer::new(Duration::from_secs(60));
+        let now = Instant::now();
+        let far = REORDER_WINDOW_SIZE as u32 + 2;
+
+        assert_eq!(push_all(&mut reorderer, &[0, 2], now), [0]);
+        assert_eq!(push_all(&mut reorderer, &[far], now), [2, far as u8]);
+        assert_eq!(push_all(&mut reorderer, &[far + 1], now), [far as u8 + 1]);
+    }
+
+    #[test]
+    fn ordered_backend() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (notify_tx, _) = mpsc::sync_channel(1);
+        let (_, subscribe_rx) = mpsc::channel();
+        let (_, unsubscribe_rx) = mpsc::channel();
+        let mut backend =
+            Backend::new(socket, notify_tx, subscribe_rx, unsubscribe_rx, false, true).unwrap();
+
+        let received = Arc::new(::std::sync::Mutex::new(Vec::new()));
+        let record = received.clone();
+        backend.subscriptions.push((
+            Subscription(0),
+            Regex::new("^TEST$").unwrap(),
+            Box::new(move |_: &str, m: &[u8], _: &MessageMeta| {
+                record.lock().unwrap().push(m[0]);
+                Ok(true)
+            }),
+        ));
+
+        let datagram = |n: u32| {
+            let mut buf = [0u8; MAX_DATAGRAM_SIZE];
+            let size = write_small_datagram(&mut buf, n, "TEST", &[n as u8]);
+            buf[..size].to_vec()
+        };
+
+        assert!(backend.process_datagram(&datagram(0), sender(), Instant::now()));
+        assert!(!backend.process_datagram(&datagram(2), sender(), Instant::now()));
+        assert!(!backend.process_datagram(&datagram(3), sender(), Instant::now()));
+        assert!(backend.process_datagram(&datagram(1), sender(), Instant::now()));
+        assert_eq!(*received.lock().unwrap(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn unknown_option_warning() {
+        let warnings = provider_warnings("udpm://239.255.76.67:7677?ttl=0&dedup=true");
+        assert!(warnings.is_empty());
+
+        let warnings = provider_warnings("udpm://239.255.76.67:7677?tll=1");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("\"tll\""));
+        assert!(warnings[0].contains("ttl"));
+
+        // The warning comes from validating the URL, even without a provider.
+        WARNINGS.with(|w| w.borrow_mut().clear());
+        let url = Url::parse("udpm://239.255.76.67:7677?tll=1").unwrap();
+        UdpmProvider::validate_url(&url).unwrap();
+        assert_eq!(WARNINGS.with(|w| w.borrow().len()), 1);
+    }
+
+    #[test]
+    fn stats() {
+        let (mut backend, _) = backend(false);
+        backend.process_datagram(&short_datagram(0), sender(), Instant::now());
+        backend.process_datagram(&short_datagram(1), sender(), Instant::now());
+        backend.process_datagram(&[0, 0, 0, 0, 0, 0, 0, 0], sender(), Instant::now());
+
+        let stats = backend.stats.lock().unwrap();
+        assert_eq!(stats.datagrams_dropped, 1);
+        assert_eq!(stats.messages_received.get("TEST"), Some(&2));
+        assert_eq!(stats.messages_received.len(), 1);
+        assert_eq!(stats.last_received.len(), 1);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn backend_thread_name() {
+        use std::fs;
+
+        let (_, subscribe_rx) = mpsc::channel();
+        let (_, unsubscribe_rx) = mpsc::channel();
+        let url = Url::parse("udpm://239.255.76.67:7683?ttl=0").unwrap();
+        let _provider = UdpmProvider::new(&url, subscribe_rx, unsubscribe_rx).unwrap();
+
+        // Linux truncates thread names to 15 bytes. The thread names itself
+        // once it starts, so give it a moment.
+        let expected = &BACKEND_THREAD_NAME[..15];
+        let named = || {
+            fs::read_dir("/proc/self/task").unwrap().any(|task| {
+                let comm = fs::read_to_string(task.unwrap().path().join("comm")).unwrap();
+                comm.trim_end() == expected
+            })
+        };
+        for _ in 0..100 {
+            if named() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("No thread named \"{}\"", expected);
+    }
+
+    #[test]
+    fn fragment_counts() {
+        let small = MAX_DATAGRAM_SIZE - SMALL_HEADER_SIZE - 5;
+        let available = MAX_DATAGRAM_SIZE - FRAG_HEADER_SIZE;
+        assert_eq!(fragment_count("TEST", 0), 1);
+        assert_eq!(fragment_count("TEST", small), 1);
+        assert_eq!(fragment_count("TEST", small + 1), 2);
+        assert_eq!(fragment_count("TEST", 2 * available - 6), 2);
+        assert_eq!(fragment_count("TEST", 2 * available - 5), 3);
+        assert_eq!(fragment_count("LONGER_CHANNEL", 2 * available - 5), 3);
+        assert_eq!(fragment_count("LONGER_CHANNEL", 2 * available - 16), 2);
+    }
+
+    #[test]
+    fn unsubscribe_without_traffic() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (notify_tx, _) = mpsc::sync_channel(1);
+        let (subscribe_tx, subscribe_rx) = mpsc::channel();
+        let (unsubscribe_tx, unsubscribe_rx) = mpsc::channel();
+        let mut backend =
+            Backend::new(socket, notify_tx, subscribe_rx, unsubscribe_rx, false, false).unwrap();
+
+        let pattern = Regex::new("^TEST$").unwrap();
+        for id in 0..2 {
+            let subscription: SubscribeMsg = (
+                Subscription(id),
+                pattern.clone(),
+                Box::new(|_: &str, _: &[u8], _: &MessageMeta| Ok(true)),
+            );
+            subscribe_tx.send(subscription).unwrap();
+        }
+        unsubscribe_tx.send(Subscription(0)).unwrap();
+
+        backend.update_subscriptions();
+        assert_eq!(backend.subscriptions.len(), 1);
+        assert_eq!(backend.subscriptions[0].0, Subscription(1));
+    }
+
+    #[test]
+    fn backend_exit_hook() {
+        let (subscribe_tx, subscribe_rx) = mpsc::channel();
+        let (_, unsubscribe_rx) = mpsc::channel();
+        let url = Url::parse("udpm://239.255.76.67:7692?ttl=0").unwrap();
+        let mut provider = UdpmProvider::new(&url, subscribe_rx, unsubscribe_rx).unwrap();
+
+        let (exit_tx, exit_rx) = mpsc::channel();
+        provider.on_backend_exit(Box::new(move || exit_tx.send(()).unwrap()));
+        let subscription: SubscribeMsg = (
+            Subscription(0),
+            Regex::new("^TEST$").unwrap(),
+            Box::new(|_: &str, _: &[u8], _: &MessageMeta| Ok(true)),
+        );
+        subscribe_tx.send(subscription).unwrap();
+
+        // Once the provider is gone, the backend exits as soon as it tries to
+        // notify it of a message.
+        drop(provider);
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.send_to(&short_datagram(0), "127.0.0.1:7692").unwrap();
+        exit_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compression_round_trip() {
+        let message: Vec<u8> = (0..10_000).map(|i| (i % 16) as u8).collect();
+        let compressed = compress(&message).unwrap();
+        assert!(compressed.len() < message.len() / 10);
+        assert_eq!(decompress(&compressed, message.len()).unwrap(), message);
+
+        let empty = compress(&[]).unwrap();
+        assert_eq!(decompress(&empty, 0).unwrap(), []);
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn decompress_too_large() {
+        let compressed = compress(&[0; 10_000]).unwrap();
+        assert!(decompress(&compressed, 9_999).is_err());
+        assert!(decompress(&[1, 2, 3], 100).is_err());
+    }
+
+    #[test]
+    fn multicast_join_failed() {
+        // Joining a unicast address as a multicast group always fails.
+        let (_, subscribe_rx) = mpsc::channel();
+        let (_, unsubscribe_rx) = mpsc::channel();
+        let url = Url::parse("udpm://239.255.76.67:7687?ttl=0&groups=192.0.2.1").unwrap();
+        match UdpmProvider::new(&url, subscribe_rx, unsubscribe_rx) {
+            Err(e @ InitError::MulticastJoinFailed { .. }) => {
+                assert!(e.to_string().contains("192.0.2.1"));
+                assert!(e.to_string().contains("network interface"));
+            }
+            Err(e) => panic!("Unexpected error: {}", e),
+            Ok(_) => panic!("Joined a unicast address"),
+        }
+    }
+
+    #[test]
+    fn loopback_interface() {
+        use net2::UdpBuilder;
+
+        let listener = UdpBuilder::new_v4()
+            .unwrap()
+            .reuse_address(true)
+            .unwrap()
+            .bind("0.0.0.0:7707")
+            .unwrap();
+        listener
+            .join_multicast_v4(&Ipv4Addr::new(239, 255, 76, 67), &Ipv4Addr::new(127, 0, 0, 1))
+            .unwrap();
+        listener.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+
+        let (_, subscribe_rx) = mpsc::channel();
+        let (_, unsubscribe_rx) = mpsc::channel();
+        let url = Url::parse("udpm://239.255.76.67:7707?ttl=0").unwrap();
+        let mut provider = UdpmProvider::new(&url, subscribe_rx, unsubscribe_rx).unwrap();
+        provider.publish("LOOPBACK", &[1, 2, 3]).unwrap();
+
+        // Packets sent on the loopback interface come from a loopback address.
+        let mut buf = [0u8; 64];
+        let (len, sender) = listener.recv_from(&mut buf).unwrap();
+        assert!(sender.ip().is_loopback());
+        match Datagram::parse(&buf[..len]).unwrap() {
+            Datagram::Short(ref datagram) => assert_eq!(datagram.message, [1, 2, 3]),
+            Datagram::Fragment(_) => panic!("Unexpected fragment"),
+        }
+    }
+
+    #[test]
+    fn set_sequence_number() {
+        use net2::UdpBuilder;
+
+        let listener = UdpBuilder::new_v4()
+            .unwrap()
+            .reuse_address(true)
+            .unwrap()
+            .bind("0.0.0.0:7714")
+            .unwrap();
+        listener
+            .join_multicast_v4(&Ipv4Addr::new(239, 255, 76, 67), &Ipv4Addr::new(127, 0, 0, 1))
+            .unwrap();
+        listener.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+
+        let (_, subscribe_rx) = mpsc::channel();
+        let (_, unsubscribe_rx) = mpsc::channel();
+        let url = Url::parse("udpm://239.255.76.67:7714?ttl=0").unwrap();
+        let mut provider = UdpmProvider::new(&url, subscribe_rx, unsubscribe_rx).unwrap();
+        provider.set_sequence_number(u32::MAX).unwrap();
+        provider.publish("SEQUENCE", &[7; 2 * MAX_DATAGRAM_SIZE]).unwrap();
+        assert_eq!(provider.sequence_number(), Some(0));
+
+        let mut buf = [0u8; MAX_DATAGRAM_SIZE];
+        for fragment_number in 0..fragment_count("SEQUENCE", 2 * MAX_DATAGRAM_SIZE) {
+            let len = listener.recv(&mut buf).unwrap();
+            match Datagram::parse(&buf[..len]).unwrap() {
+                Datagram::Fragment(ref datagram) => {
+                    assert_eq!(datagram.sequence_number, u32::MAX);
+                    assert_eq!(datagram.fragment_number as usize, fragment_number);
+                }
+                Datagram::Short(_) => panic!("Unexpected short datagram"),
+            }
+        }
+    }
+
+    #[test]
+    fn recv_buf_size() {
+        let (_, subscribe_rx) = mpsc::channel();
+        let (_, unsubscribe_rx) = mpsc::channel();
+        let url = Url::parse("udpm://239.255.76.67:7713?ttl=0&recv_buf_size=4096").unwrap();
+        let provider = UdpmProvider::new(&url, subscribe_rx, unsubscribe_rx).unwrap();
+
+        // Linux doubles the requested size.
+        let granted = net2::UdpSocketExt::recv_buffer_size(&provider.socket).unwrap();
+        assert!((4096..=2 * 4096).contains(&granted));
+
+        let (_, subscribe_rx) = mpsc::channel();
+        let (_, unsubscribe_rx) = mpsc::channel();
+        let url = Url::parse("udpm://239.255.76.67:7713?recv_buf_size=large").unwrap();
+        match UdpmProvider::new(&url, subscribe_rx, unsubscribe_rx) {
+            Err(InitError::InvalidSize(ref key)) if key == "recv_buf_size" => {}
+            Err(e) => panic!("Unexpected error: {:?}", e),
+            Ok(_) => panic!("Unexpectedly created a provider"),
+        }
+    }
+
+    #[test]
+    fn ipv6_group() {
+        if UdpSocket::bind("[::1]:0").is_err() {
+            // IPv6 is disabled on this host.
+            return;
+        }
+
+        let (_, subscribe_rx) = mpsc::channel();
+        let (_, unsubscribe_rx) = mpsc::channel();
+        // A private group with a TTL of zero keeps the test off the network.
+        let url = Url::parse("udpm://[ff02::4c4d]:7711?ttl=0").unwrap();
+        let mut provider = UdpmProvider::new(&url, subscribe_rx, unsubscribe_rx).unwrap();
+        assert_eq!(
+            provider.multicast_info().unwrap().group,
+            "ff02::4c4d".parse::<IpAddr>().unwrap()
+        );
+        match provider.publish("IPV6", &[1, 2, 3]) {
+            Ok(()) => {}
+            // There is no IPv6 multicast route on this host.
+            #[cfg(unix)]
+            Err(PublishError::IoError(ref e)) if e.raw_os_error() == Some(::libc::ENETUNREACH) => {}
+            Err(e) => panic!("Failed to publish: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn oversized_fragmented_message() {
+        let (mut backend, count) = backend(false);
+        backend.max_message_size = 1024 * 1024;
+
+        let mut datagram = Vec::new();
+        datagram.write_u32::<NetworkEndian>(LONG_HEADER_MAGIC).unwrap();
+        datagram.write_u32::<NetworkEndian>(0).unwrap();
+        datagram.write_u32::<NetworkEndian>(100 * 1024 * 1024).unwrap();
+        datagram.write_u32::<NetworkEndian>(0).unwrap();
+        datagram.write_u16::<NetworkEndian>(0).unwrap();
+        datagram.write_u16::<NetworkEndian>(2).unwrap();
+        datagram.extend(b"TEST\0payload");
+
+        assert!(!backend.process_datagram(&datagram, sender(), Instant::now()));
+        assert!(backend.fragments.is_empty());
+        assert_eq!(backend.stats.lock().unwrap().datagrams_dropped, 1);
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn fragment_past_end_of_message() {
+        let (mut backend, count) = backend(false);
+
+        let mut datagram = Vec::new();
+        datagram.write_u32::<NetworkEndian>(LONG_HEADER_MAGIC).unwrap();
+        datagram.write_u32::<NetworkEndian>(0).unwrap();
+        datagram.write_u32::<NetworkEndian>(4).unwrap();
+        datagram.write_u32::<NetworkEndian>(0).unwrap();
+        datagram.write_u16::<NetworkEndian>(0).unwrap();
+        datagram.write_u16::<NetworkEndian>(2).unwrap();
+        datagram.extend(b"TEST\0payload");
+
+        assert!(!backend.process_datagram(&datagram, sender(), Instant::now()));
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn retries_transient_errors() {
+        let mut attempts = 0;
+        let res = send_with_retry(3, Duration::from_millis(1), || {
+            attempts += 1;
+            if attempts < 3 {
+                Err(io::ErrorKind::WouldBlock.into())
+            } else {
+                Ok(10)
+            }
+        });
+        assert_eq!(res.unwrap(), 10);
+        assert_eq!(attempts, 3);
+
+        // Once the retries run out, the last error is returned.
+        let mut attempts = 0;
+        let res = send_with_retry(2, Duration::from_millis(1), || {
+            attempts += 1;
+            Err(io::ErrorKind::WouldBlock.into())
+        });
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn retries_when_out_of_buffers() {
+        let mut attempts = 0;
+        let res = send_with_retry(1, Duration::from_millis(1), || {
+            attempts += 1;
+            if attempts < 2 {
+                Err(io::Error::from_raw_os_error(::libc::ENOBUFS))
+            } else {
+                Ok(10)
+            }
+        });
+        assert_eq!(res.unwrap(), 10);
+    }
+
+    #[test]
+    fn permanent_errors_are_not_retried() {
+        let mut attempts = 0;
+        let res = send_with_retry(3, Duration::from_millis(1), || {
+            attempts += 1;
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "Message too long"))
+        });
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(attempts, 1);
+    }
+}
@@ -21,18 +21,47 @@ pub use lcm_derive::*;
 extern crate log;
 
 extern crate byteorder;
+#[cfg(feature = "compression")]
+extern crate flate2;
 #[macro_use]
 extern crate failure;
+#[cfg(unix)]
+extern crate libc;
 extern crate net2;
 extern crate regex;
 extern crate url;
 
+#[cfg(all(test, loom))]
+extern crate loom;
+
+#[cfg(test)]
+#[macro_use]
+mod test_util;
+
 mod utils;
 
 pub mod error;
 
 mod lcm;
-pub use lcm::{Lcm, Subscription};
+pub use lcm::{available_providers, default_url, resolve_url, validate_url, Capabilities, Latest,
+              Lcm, MessageMeta, MulticastInfo, ReplayOptions, Stats, Subscription};
+
+/// Entry points for fuzzing the code that handles untrusted input.
+///
+/// These only exist when building with `--cfg fuzzing`, which `cargo fuzz`
+/// sets. See the `fuzz` directory.
+#[cfg(all(fuzzing, feature = "udpm"))]
+#[doc(hidden)]
+pub use lcm::{fuzz_parse_datagram, fuzz_process_datagrams};
 
 mod message;
-pub use message::{Marshall, Message};
+pub use message::{content_hash, decode, encode, Bytes, Marshall, Message, Raw};
+
+mod reader;
+pub use reader::MessageReader;
+
+mod router;
+pub use router::TypedRouter;
+
+mod logfile;
+pub use logfile::{read_log_messages, LogEvent, LogMessages, LogReader};
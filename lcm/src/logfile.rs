@@ -0,0 +1,372 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::marker::PhantomData;
+use std::path::Path;
+use byteorder::ReadBytesExt;
+use regex::Regex;
+
+use {Marshall, Message};
+use error::*;
+
+/// The word at the start of every event in an LCM log file.
+const SYNC_WORD: u32 = 0xEDA1_DA01;
+
+/// A single event read from an LCM log file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogEvent {
+    /// The position of the event in the log, as recorded by the logger.
+    pub event_number: i64,
+    /// When the message was received, in microseconds since the Unix epoch.
+    pub timestamp: i64,
+    /// The channel the message was received on.
+    pub channel: String,
+    /// The encoded message, including its hash.
+    pub data: Vec<u8>,
+}
+
+/// Reads the events from a log file written by `lcm-logger`.
+///
+/// Reading stops cleanly when the stream ends between events, or after the
+/// first error.
+pub struct LogReader<R> {
+    /// The stream the events are read from.
+    reader: R,
+
+    /// The number of unexpected bytes that may be skipped before an event.
+    max_padding: usize,
+
+    /// Set once the end of the stream or an error has been reached.
+    done: bool,
+}
+impl<R: BufRead> LogReader<R> {
+    /// Creates a reader for the events in the stream.
+    pub fn new(reader: R) -> Self {
+        LogReader {
+            reader,
+            max_padding: 0,
+            done: false,
+        }
+    }
+
+    /// Allows up to `max_padding` bytes of padding or garbage before each
+    /// event, such as separators added by other tools.
+    ///
+    /// When an event doesn't start where it is expected, the reader scans
+    /// ahead for the next sync word. Padding at the end of the stream is
+    /// also skipped. By default, no padding is allowed.
+    pub fn max_padding(mut self, max_padding: usize) -> Self {
+        self.max_padding = max_padding;
+        self
+    }
+
+    /// Returns the underlying stream.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads up to and including the next sync word, skipping at most
+    /// `max_padding` bytes before it.
+    ///
+    /// Returns false if the stream ended cleanly, either between events or
+    /// within the allowed padding.
+    fn find_sync_word(&mut self) -> Result<bool, DecodeError> {
+        let mut window = 0u32;
+        let mut read = 0;
+        loop {
+            let byte = match self.reader.read_u8() {
+                Ok(byte) => byte,
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof
+                    && (read == 0 || read <= self.max_padding) =>
+                {
+                    if read > 0 {
+                        debug!("Skipped {} bytes of padding at the end of the log", read);
+                    }
+                    return Ok(false);
+                }
+                Err(e) => return Err(e.into()),
+            };
+            window = (window << 8) | u32::from(byte);
+            read += 1;
+
+            if read >= 4 {
+                if window == SYNC_WORD {
+                    if read > 4 {
+                        debug!("Skipped {} bytes of padding in the log", read - 4);
+                    }
+                    return Ok(true);
+                }
+                if read - 4 >= self.max_padding {
+                    return Err(DecodeError::InvalidSyncWord(window));
+                }
+            }
+        }
+    }
+
+    /// Reads the rest of the event after its sync word.
+    fn read_event(&mut self) -> Result<LogEvent, DecodeError> {
+        let event_number = i64::decode(&mut self.reader)?;
+        let timestamp = i64::decode(&mut self.reader)?;
+        let channel_len = i32::decode(&mut self.reader)?;
+        let data_len = i32::decode(&mut self.reader)?;
+
+        let channel = String::from_utf8(self.read_bytes(channel_len)?)
+            .map_err(DecodeError::Utf8Error)?;
+        let data = self.read_bytes(data_len)?;
+
+        Ok(LogEvent {
+            event_number,
+            timestamp,
+            channel,
+            data,
+        })
+    }
+
+    /// Reads a field of the given length.
+    fn read_bytes(&mut self, len: i32) -> Result<Vec<u8>, DecodeError> {
+        if len < 0 {
+            return Err(DecodeError::InvalidSize(i64::from(len)));
+        }
+
+        // As with `Bytes`, avoid trusting the length before the data arrives.
+        let mut bytes = Vec::new();
+        (&mut self.reader).take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len as usize {
+            return Err(DecodeError::IoError(io::ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(bytes)
+    }
+}
+impl<R: BufRead> Iterator for LogReader<R> {
+    type Item = Result<LogEvent, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        // Finding the sync word also tells the end of the stream apart from
+        // a truncated event.
+        match self.find_sync_word() {
+            Ok(true) => {}
+            Ok(false) => {
+                self.done = true;
+                return None;
+            }
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+
+        let res = self.read_event();
+        self.done = res.is_err();
+        Some(res)
+    }
+}
+
+/// Reads the messages of one type from a log file.
+///
+/// Only events on channels matching the regular expression are considered,
+/// and events that hold a different type of message are skipped. Each item
+/// is the time the message was logged, in microseconds since the Unix epoch,
+/// along with the message. Reading stops after an error in the log itself,
+/// but a message that fails to decode only produces an error for that event.
+pub fn read_log_messages<M, P>(path: P, channel: &str) -> Result<LogMessages<M>, Error>
+where
+    M: Message,
+    P: AsRef<Path>,
+{
+    let channel = Regex::new(channel).map_err(SubscribeError::from)?;
+    let file = File::open(path.as_ref()).map_err(|e| Error::Log(e.into()))?;
+    Ok(LogMessages {
+        events: LogReader::new(BufReader::new(file)),
+        channel,
+        _message: PhantomData,
+    })
+}
+
+/// The messages of one type in a log file, as returned by
+/// `read_log_messages`.
+pub struct LogMessages<M> {
+    /// The events in the log.
+    events: LogReader<BufReader<File>>,
+
+    /// The channels whose events are decoded.
+    channel: Regex,
+
+    _message: PhantomData<fn() -> M>,
+}
+impl<M: Message> Iterator for LogMessages<M> {
+    type Item = Result<(i64, M), DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for event in &mut self.events {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => return Some(Err(e)),
+            };
+            if !self.channel.is_match(&event.channel) {
+                continue;
+            }
+
+            match M::decode_with_hash(&mut &event.data[..]) {
+                Ok(message) => return Some(Ok((event.timestamp, message))),
+                Err(DecodeError::HashMismatch { .. }) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+}
+
+/// Writes an event the way `lcm-logger` does.
+#[cfg(any(test, feature = "file"))]
+pub fn write_event(writer: &mut io::Write, event: &LogEvent) -> io::Result<()> {
+    use byteorder::{NetworkEndian, WriteBytesExt};
+
+    writer.write_u32::<NetworkEndian>(SYNC_WORD)?;
+    writer.write_i64::<NetworkEndian>(event.event_number)?;
+    writer.write_i64::<NetworkEndian>(event.timestamp)?;
+    writer.write_i32::<NetworkEndian>(event.channel.len() as i32)?;
+    writer.write_i32::<NetworkEndian>(event.data.len() as i32)?;
+    writer.write_all(event.channel.as_bytes())?;
+    writer.write_all(&event.data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn event(event_number: i64, channel: &str, data: &[u8]) -> LogEvent {
+        LogEvent {
+            event_number,
+            timestamp: 1_500_000_000_000_000 + event_number * 1000,
+            channel: channel.into(),
+            data: data.to_owned(),
+        }
+    }
+
+    #[test]
+    fn two_events() {
+        let events = vec![event(0, "FIRST", &[1, 2, 3]), event(1, "SECOND", &[])];
+        let mut stream = Vec::new();
+        for e in &events {
+            write_event(&mut stream, e).unwrap();
+        }
+
+        let read: Vec<LogEvent> = LogReader::new(Cursor::new(stream))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, events);
+    }
+
+    #[test]
+    fn invalid_sync_word() {
+        let mut stream = Vec::new();
+        write_event(&mut stream, &event(0, "FIRST", &[1, 2, 3])).unwrap();
+        stream[0] = 0;
+
+        let mut reader = LogReader::new(Cursor::new(stream));
+        match reader.next() {
+            Some(Err(DecodeError::InvalidSyncWord(0x00A1_DA01))) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert!(reader.next().is_none());
+    }
+
+    // Two message types that share an encoding but not a hash.
+    test_message!(Count, 0x1234_5678);
+    test_message!(Other, 0x8765_4321);
+
+    #[test]
+    fn read_log_messages() {
+        use std::{env, fs, process};
+
+        let events = vec![
+            event(0, "COUNT_A", &Count(1).encode_with_hash().unwrap()),
+            event(1, "OTHER", &Count(2).encode_with_hash().unwrap()),
+            event(2, "COUNT_B", &Other(3).encode_with_hash().unwrap()),
+            event(3, "COUNT_B", &Count(4).encode_with_hash().unwrap()),
+            event(4, "COUNT_A", &[0, 0, 0, 0, 0x12, 0x34, 0x56, 0x78, 0]),
+        ];
+        let mut stream = Vec::new();
+        for e in &events {
+            write_event(&mut stream, e).unwrap();
+        }
+        let path = env::temp_dir().join(format!("lcm_logfile_{}.lcm", process::id()));
+        fs::write(&path, stream).unwrap();
+
+        let messages: Vec<_> = super::read_log_messages::<Count, _>(&path, "^COUNT_")
+            .unwrap()
+            .collect();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(messages.len(), 3);
+        match messages[0] {
+            Ok((timestamp, Count(1))) => assert_eq!(timestamp, events[0].timestamp),
+            _ => panic!("Unexpected first message"),
+        }
+        match messages[1] {
+            Ok((timestamp, Count(4))) => assert_eq!(timestamp, events[3].timestamp),
+            _ => panic!("Unexpected second message"),
+        }
+        assert!(messages[2].is_err());
+    }
+
+    #[test]
+    fn read_log_messages_errors() {
+        match super::read_log_messages::<Count, _>("/nonexistent/log.lcm", ".*") {
+            Err(Error::Log(DecodeError::IoError(_))) => {}
+            Err(e) => panic!("Unexpected error: {}", e),
+            Ok(_) => panic!("Opened a missing file"),
+        }
+        match super::read_log_messages::<Count, _>("/nonexistent/log.lcm", "(") {
+            Err(Error::Subscribe(_)) => {}
+            Err(e) => panic!("Unexpected error: {}", e),
+            Ok(_) => panic!("Accepted an invalid pattern"),
+        }
+    }
+
+    #[test]
+    fn padding_between_events() {
+        let events = vec![event(0, "FIRST", &[1, 2, 3]), event(1, "SECOND", &[4])];
+        let mut stream = Vec::new();
+        write_event(&mut stream, &events[0]).unwrap();
+        // Garbage that includes the start of a sync word.
+        stream.extend(&[b'\n', 0xED, 0xA1, 0x00, 0xED]);
+        write_event(&mut stream, &events[1]).unwrap();
+        stream.extend(b"\n\n");
+
+        let read: Vec<LogEvent> = LogReader::new(Cursor::new(&stream[..]))
+            .max_padding(8)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, events);
+
+        // The padding isn't allowed by default.
+        let mut reader = LogReader::new(Cursor::new(&stream[..]));
+        assert_eq!(reader.next().unwrap().unwrap(), events[0]);
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+
+        // Nor is more of it than the limit.
+        let mut reader = LogReader::new(Cursor::new(&stream[..])).max_padding(4);
+        assert_eq!(reader.next().unwrap().unwrap(), events[0]);
+        assert!(reader.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn truncated_event() {
+        let mut stream = Vec::new();
+        write_event(&mut stream, &event(0, "FIRST", &[1, 2, 3])).unwrap();
+        write_event(&mut stream, &event(1, "SECOND", &[4, 5, 6])).unwrap();
+        let len = stream.len();
+        stream.truncate(len - 2);
+
+        let mut reader = LogReader::new(Cursor::new(stream));
+        assert_eq!(reader.next().unwrap().unwrap().channel, "FIRST");
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+}
@@ -1,4 +1,7 @@
-use std::io::{Read, Write};
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
 use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
 
 use error::*;
@@ -21,10 +24,15 @@ pub trait Message: Marshall {
     /// The message hash for this type.
     const HASH: u64;
 
+    /// Returns the number of bytes this message takes when encoded along with
+    /// its hash, as done by `encode_with_hash`.
+    fn encoded_size(&self) -> usize {
+        Self::HASH.size() + self.size()
+    }
+
     /// Encodes a message into a buffer, with the message hash at the beginning.
     fn encode_with_hash(&self) -> Result<Vec<u8>, EncodeError> {
-        let size = Self::HASH.size() + self.size();
-        let mut buffer = Vec::with_capacity(size);
+        let mut buffer = Vec::with_capacity(self.encoded_size());
         Self::HASH.encode(&mut buffer)?;
         self.encode(&mut buffer)?;
         Ok(buffer)
@@ -39,6 +47,91 @@ pub trait Message: Marshall {
         }
         Marshall::decode(buffer)
     }
+
+    /// Decodes a message from a buffer, skipping the hash at the beginning
+    /// without checking it.
+    ///
+    /// This is an escape hatch for accepting messages from peers using a
+    /// different version of the type whose layout is known to be compatible.
+    /// Nothing checks that it actually is, so a message of any other type is
+    /// silently decoded as garbage or fails with an unrelated error. Prefer
+    /// `decode_with_hash` whenever possible.
+    fn decode_ignoring_hash(mut buffer: &mut Read) -> Result<Self, DecodeError> {
+        let _hash: u64 = Marshall::decode(&mut buffer)?;
+        Marshall::decode(buffer)
+    }
+
+    /// Decodes the start of a message, returning it along with the trailing
+    /// bytes that were not part of it. The hash is not checked.
+    ///
+    /// This allows an older type to read messages from a newer version that
+    /// only adds fields at the end of the struct. As with
+    /// `decode_ignoring_hash`, nothing checks that the layouts actually
+    /// agree, so this should only be used when that is known to be the case.
+    fn decode_prefix(mut buffer: &[u8]) -> Result<(Self, &[u8]), DecodeError> {
+        let message = Self::decode_ignoring_hash(&mut buffer)?;
+        Ok((message, buffer))
+    }
+
+    /// Writes the message, along with its hash, to a file.
+    ///
+    /// The file contains exactly what `encode_with_hash` returns, and is
+    /// replaced if it already exists.
+    fn encode_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), EncodeError> {
+        fs::write(path, self.encode_with_hash()?)?;
+        Ok(())
+    }
+
+    /// Reads a message written by `encode_to_file`, checking its hash.
+    fn decode_from_file<P: AsRef<Path>>(path: P) -> Result<Self, DecodeError> {
+        let bytes = fs::read(path)?;
+        Self::decode_with_hash(&mut &bytes[..])
+    }
+}
+
+/// Encodes a message along with its hash, exactly as `Lcm::publish` sends it.
+///
+/// This is the same as `Message::encode_with_hash`, and can be used to
+/// marshall messages without an `Lcm` instance.
+pub fn encode<M: Message>(message: &M) -> Result<Vec<u8>, EncodeError> {
+    message.encode_with_hash()
+}
+
+/// Decodes a message from bytes that start with its hash, such as those
+/// produced by `encode`.
+///
+/// This is the same as `Message::decode_with_hash`, and can be used to
+/// marshall messages without an `Lcm` instance. Any bytes after the message
+/// are ignored.
+pub fn decode<M: Message>(mut bytes: &[u8]) -> Result<M, DecodeError> {
+    M::decode_with_hash(&mut bytes)
+}
+
+/// Hashes the encoded contents of a message, without its type hash.
+///
+/// Messages with equal contents always have the same hash, which makes
+/// this useful for detecting changes or deduplicating messages. It uses
+/// 64-bit FNV-1a, which is fast but not suitable for hash tables that are
+/// exposed to untrusted input.
+pub fn content_hash<M: Marshall>(message: &M) -> Result<u64, EncodeError> {
+    let mut hasher = Fnv1a(0xcbf2_9ce4_8422_2325);
+    message.encode(&mut hasher)?;
+    Ok(hasher.0)
+}
+
+/// The state of a 64-bit FNV-1a hash, updated by writing bytes to it.
+struct Fnv1a(u64);
+impl Write for Fnv1a {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &b in buf {
+            self.0 = (self.0 ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 macro_rules! impl_marshall {
@@ -62,6 +155,8 @@ macro_rules! impl_marshall {
 }
 
 impl_marshall!(u8, read_u8, write_u8);
+impl_marshall!(u16, read_u16, write_u16, NetworkEndian);
+impl_marshall!(u32, read_u32, write_u32, NetworkEndian);
 impl_marshall!(u64, read_u64, write_u64, NetworkEndian);
 
 impl_marshall!(i8, read_i8, write_i8);
@@ -129,10 +224,285 @@ impl Marshall for String {
     }
 }
 
+/// An opaque blob of bytes, such as compressed or otherwise serialized data.
+///
+/// It is encoded as an `i32` count followed by the bytes. This is only a
+/// convention for hand-written types; it matches an `int32_t` length member
+/// followed by a `byte` array of that length in an LCM type specification.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bytes(pub Vec<u8>);
+impl From<Vec<u8>> for Bytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Bytes(bytes)
+    }
+}
+impl Marshall for Bytes {
+    fn encode(&self, buffer: &mut Write) -> Result<(), EncodeError> {
+        if self.0.len() > i32::MAX as usize {
+            return Err(EncodeError::BlobTooLong(self.0.len()));
+        }
+        (self.0.len() as i32).encode(buffer)?;
+        buffer.write_all(&self.0)?;
+        Ok(())
+    }
+
+    fn decode(buffer: &mut Read) -> Result<Self, DecodeError> {
+        let len = i32::decode(buffer)?;
+        if len < 0 {
+            return Err(DecodeError::InvalidSize(i64::from(len)));
+        }
+
+        // Reading through `take` only allocates as the bytes arrive, so an
+        // invalid length can't cause a huge allocation up front.
+        let mut bytes = Vec::new();
+        buffer.take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len as usize {
+            return Err(DecodeError::IoError(io::ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(Bytes(bytes))
+    }
+
+    fn size(&self) -> usize {
+        ::std::mem::size_of::<i32>() + self.0.len()
+    }
+}
+
+/// A message whose type is only known at runtime, such as one being
+/// forwarded by a bridge between networks.
+///
+/// Unlike other messages, the hash is stored in each instance instead of
+/// `Message::HASH`. `encode_with_hash` sends `hash` followed by `data`
+/// verbatim, and `decode_with_hash` accepts any hash and records it, so a
+/// `Raw` can be published or subscribed to on any channel. The `Marshall`
+/// methods only cover `data`, and leave `hash` as zero when decoding.
+///
+/// Decoding reads to the end of the buffer, so a `Raw` can only be a whole
+/// message, never a field of another message.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Raw {
+    /// The hash of the message type that `data` was encoded from.
+    pub hash: u64,
+    /// The encoded message, without its hash.
+    pub data: Vec<u8>,
+}
+impl Marshall for Raw {
+    fn encode(&self, buffer: &mut Write) -> Result<(), EncodeError> {
+        buffer.write_all(&self.data)?;
+        Ok(())
+    }
+
+    fn decode(buffer: &mut Read) -> Result<Self, DecodeError> {
+        let mut data = Vec::new();
+        buffer.read_to_end(&mut data)?;
+        Ok(Raw { hash: 0, data })
+    }
+
+    fn size(&self) -> usize {
+        self.data.len()
+    }
+}
+impl Message for Raw {
+    const HASH: u64 = 0;
+
+    fn encode_with_hash(&self) -> Result<Vec<u8>, EncodeError> {
+        let mut buffer = Vec::with_capacity(self.encoded_size());
+        self.hash.encode(&mut buffer)?;
+        self.encode(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn decode_with_hash(mut buffer: &mut Read) -> Result<Self, DecodeError> {
+        let hash = u64::decode(&mut buffer)?;
+        let Raw { data, .. } = Marshall::decode(buffer)?;
+        Ok(Raw { hash, data })
+    }
+
+    fn decode_ignoring_hash(buffer: &mut Read) -> Result<Self, DecodeError> {
+        Self::decode_with_hash(buffer)
+    }
+}
+
+impl<T: Marshall, const N: usize> Marshall for [T; N] {
+    fn encode(&self, buffer: &mut Write) -> Result<(), EncodeError> {
+        for item in self {
+            item.encode(buffer)?;
+        }
+        Ok(())
+    }
+
+    fn decode(buffer: &mut Read) -> Result<Self, DecodeError> {
+        // Collecting into a `Vec` first means that the elements decoded so
+        // far are dropped normally if a later one fails.
+        let mut items = Vec::with_capacity(N);
+        for _ in 0..N {
+            items.push(T::decode(buffer)?);
+        }
+        Ok(<[T; N]>::try_from(items)
+            .ok()
+            .expect("Decoded the wrong number of array elements"))
+    }
+
+    fn size(&self) -> usize {
+        self.iter().map(Marshall::size).sum()
+    }
+}
+
+/// Tuples are encoded as their elements in order, like the members of a
+/// struct.
+///
+/// This is convenient for ad-hoc payloads, but a tuple has no hash, so it
+/// should only be used inside a message and not published on its own.
+macro_rules! impl_marshall_tuple {
+    ( $( $name:ident : $index:tt ),+ ) => {
+        impl<$( $name: Marshall ),+> Marshall for ( $( $name, )+ ) {
+            fn encode(&self, buffer: &mut Write) -> Result<(), EncodeError> {
+                $( self.$index.encode(buffer)?; )+
+                Ok(())
+            }
+
+            fn decode(buffer: &mut Read) -> Result<Self, DecodeError> {
+                Ok(( $( $name::decode(buffer)?, )+ ))
+            }
+
+            fn size(&self) -> usize {
+                0 $( + self.$index.size() )+
+            }
+        }
+    };
+}
+
+impl_marshall_tuple!(A: 0, B: 1);
+impl_marshall_tuple!(A: 0, B: 1, C: 2);
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    /// A minimal message type for testing.
+    struct Greeting(String);
+    impl Marshall for Greeting {
+        fn encode(&self, buffer: &mut Write) -> Result<(), EncodeError> {
+            self.0.encode(buffer)
+        }
+
+        fn decode(buffer: &mut Read) -> Result<Self, DecodeError> {
+            Ok(Greeting(String::decode(buffer)?))
+        }
+
+        fn size(&self) -> usize {
+            self.0.size()
+        }
+    }
+    impl Message for Greeting {
+        const HASH: u64 = 0x0123_4567_89AB_CDEF;
+    }
+
+    #[test]
+    fn encoded_size() {
+        let message = Greeting("Hello, world!".into());
+        let encoded = message.encode_with_hash().unwrap();
+        assert_eq!(message.encoded_size(), encoded.len());
+        assert_eq!(message.encoded_size(), 8 + 4 + 14);
+    }
+
+    #[test]
+    fn decode_ignoring_hash() {
+        let mut buffer = Vec::new();
+        0xFEDC_BA98_7654_3210u64.encode(&mut buffer).unwrap();
+        String::from("Hello, world!").encode(&mut buffer).unwrap();
+
+        assert!(Greeting::decode_with_hash(&mut &buffer[..]).is_err());
+        let decoded = Greeting::decode_ignoring_hash(&mut &buffer[..]).unwrap();
+        assert_eq!(decoded.0, "Hello, world!");
+    }
+
+    #[test]
+    fn free_functions() {
+        let encoded = encode(&Greeting("Hello, world!".into())).unwrap();
+        assert_eq!(encoded, Greeting("Hello, world!".into()).encode_with_hash().unwrap());
+
+        let decoded: Greeting = decode(&encoded).unwrap();
+        assert_eq!(decoded.0, "Hello, world!");
+        assert!(decode::<Greeting>(&encoded[1..]).is_err());
+    }
+
+    #[test]
+    fn round_trip_file() {
+        use std::{env, process};
+
+        let path = env::temp_dir().join(format!("lcm_message_{}.bin", process::id()));
+        Greeting("Hello".into()).encode_to_file(&path).unwrap();
+        let decoded = Greeting::decode_from_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(decoded.unwrap().0, "Hello");
+    }
+
+    #[test]
+    fn content_hashes() {
+        let hash = |s: &str| content_hash(&Greeting(s.into())).unwrap();
+        assert_eq!(hash("Hello, world!"), hash("Hello, world!"));
+        assert_ne!(hash("Hello, world!"), hash("Hello, world?"));
+
+        // The FNV-1a hash of nothing is its offset basis, and the hash of
+        // "a" is a known test vector.
+        assert_eq!(content_hash(&[0u8; 0]).unwrap(), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(content_hash(&b'a').unwrap(), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn decode_prefix() {
+        let mut buffer = Vec::new();
+        0xFEDC_BA98_7654_3210u64.encode(&mut buffer).unwrap();
+        String::from("Hello, world!").encode(&mut buffer).unwrap();
+        42i32.encode(&mut buffer).unwrap();
+        1.5f64.encode(&mut buffer).unwrap();
+
+        let (decoded, rest) = Greeting::decode_prefix(&buffer).unwrap();
+        assert_eq!(decoded.0, "Hello, world!");
+        assert_eq!(rest.len(), 4 + 8);
+        assert_eq!(i32::decode(&mut &rest[..4]).unwrap(), 42);
+    }
+
+    #[test]
+    fn round_trip_bytes() {
+        for &len in &[0, 3, 1 << 20] {
+            let blob = Bytes((0..len).map(|i| i as u8).collect());
+            let mut buffer = Vec::new();
+            blob.encode(&mut buffer).unwrap();
+            assert_eq!(buffer.len(), blob.size());
+            assert_eq!(buffer.len(), 4 + len);
+
+            let decoded = Bytes::decode(&mut &buffer[..]).unwrap();
+            assert_eq!(decoded, blob);
+        }
+    }
+
+    #[test]
+    fn decode_truncated_bytes() {
+        let mut buffer: &[u8] = &[0, 0, 0, 4, 1, 2];
+        assert!(Bytes::decode(&mut buffer).is_err());
+
+        let mut buffer: &[u8] = &[255, 255, 255, 255];
+        assert!(Bytes::decode(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn round_trip_raw() {
+        let greeting = Greeting("Hello".into());
+        let raw: Raw = decode(&encode(&greeting).unwrap()).unwrap();
+        assert_eq!(raw.hash, Greeting::HASH);
+        assert_eq!(raw.data, {
+            let mut data = Vec::new();
+            greeting.encode(&mut data).unwrap();
+            data
+        });
+
+        let bytes = encode(&raw).unwrap();
+        assert_eq!(bytes.len(), raw.encoded_size());
+        let decoded: Greeting = decode(&bytes).unwrap();
+        assert_eq!(decoded.0, "Hello");
+    }
+
     #[test]
     fn decode_string() {
         let s: String = "Hello, world!".into();
@@ -149,4 +519,76 @@ mod test {
         let decoded = String::decode(&mut buffer);
         assert!(decoded.is_err());
     }
+
+    #[test]
+    fn round_trip_byte_array() {
+        let array: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+        let mut buffer = Vec::new();
+        array.encode(&mut buffer).unwrap();
+        assert_eq!(buffer.len(), array.size());
+        assert_eq!(buffer, array);
+
+        let decoded = <[u8; 16]>::decode(&mut buffer.as_slice()).unwrap();
+        assert_eq!(decoded, array);
+    }
+
+    #[test]
+    fn round_trip_unsigned() {
+        let mut buffer = Vec::new();
+        0xabu8.encode(&mut buffer).unwrap();
+        0xabcdu16.encode(&mut buffer).unwrap();
+        0xabcd_ef01u32.encode(&mut buffer).unwrap();
+        assert_eq!(buffer, [0xab, 0xab, 0xcd, 0xab, 0xcd, 0xef, 0x01]);
+        assert_eq!(0u8.size() + 0u16.size() + 0u32.size(), 7);
+
+        let mut buffer = buffer.as_slice();
+        assert_eq!(u8::decode(&mut buffer).unwrap(), 0xab);
+        assert_eq!(u16::decode(&mut buffer).unwrap(), 0xabcd);
+        assert_eq!(u32::decode(&mut buffer).unwrap(), 0xabcd_ef01);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn round_trip_double_array() {
+        let array = [1.5f64, -2.0, 0.0, ::std::f64::consts::PI];
+        let mut buffer = Vec::new();
+        array.encode(&mut buffer).unwrap();
+        assert_eq!(buffer.len(), 32);
+        assert_eq!(array.size(), 32);
+
+        let decoded = <[f64; 4]>::decode(&mut buffer.as_slice()).unwrap();
+        assert_eq!(decoded, array);
+    }
+
+    #[test]
+    fn round_trip_pair() {
+        let pair = (7i32, String::from("seven"));
+        let mut buffer = Vec::new();
+        pair.encode(&mut buffer).unwrap();
+        assert_eq!(buffer.len(), pair.size());
+        assert_eq!(buffer.len(), 4 + 4 + 6);
+
+        let decoded = <(i32, String)>::decode(&mut buffer.as_slice()).unwrap();
+        assert_eq!(decoded, pair);
+    }
+
+    #[test]
+    fn round_trip_triple() {
+        let triple = (true, -1.5f64, [1u8, 2, 3]);
+        let mut buffer = Vec::new();
+        triple.encode(&mut buffer).unwrap();
+        assert_eq!(buffer.len(), triple.size());
+        assert_eq!(buffer[..1], [1]);
+        assert_eq!(buffer[9..], [1, 2, 3]);
+
+        let decoded = <(bool, f64, [u8; 3])>::decode(&mut buffer.as_slice()).unwrap();
+        assert_eq!(decoded, triple);
+        assert!(<(bool, f64, [u8; 3])>::decode(&mut &buffer[..10]).is_err());
+    }
+
+    #[test]
+    fn decode_short_array() {
+        let mut buffer: &[u8] = &[0, 0, 0, 1, 0, 0];
+        assert!(<[i32; 2]>::decode(&mut buffer).is_err());
+    }
 }
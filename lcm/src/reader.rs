@@ -0,0 +1,98 @@
+use std::io::BufRead;
+use std::marker::PhantomData;
+
+use Message;
+use error::*;
+
+/// Reads consecutive messages of a single type from a stream.
+///
+/// Each message is expected to be framed the way `Message::encode_with_hash`
+/// produces it, with the hash immediately followed by the message. Reading
+/// stops cleanly when the stream ends between messages, or after the first
+/// error.
+pub struct MessageReader<R, M> {
+    /// The stream the messages are read from.
+    reader: R,
+
+    /// Set once the end of the stream or an error has been reached.
+    done: bool,
+
+    _message: PhantomData<fn() -> M>,
+}
+impl<R: BufRead, M: Message> MessageReader<R, M> {
+    /// Creates a reader for the messages in the stream.
+    pub fn new(reader: R) -> Self {
+        MessageReader {
+            reader,
+            done: false,
+            _message: PhantomData,
+        }
+    }
+
+    /// Returns the underlying stream.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+impl<R: BufRead, M: Message> Iterator for MessageReader<R, M> {
+    type Item = Result<M, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        // Look ahead to tell the end of the stream apart from a truncated
+        // message.
+        match self.reader.fill_buf() {
+            Ok(buf) if buf.is_empty() => {
+                self.done = true;
+                return None;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e.into()));
+            }
+        }
+
+        let res = M::decode_with_hash(&mut self.reader);
+        self.done = res.is_err();
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    test_message!(
+        /// A minimal message type for testing.
+        #[derive(Debug, PartialEq)]
+        Count,
+        0x1234_5678
+    );
+
+    #[test]
+    fn two_messages() {
+        let mut stream = Count(1).encode_with_hash().unwrap();
+        stream.extend(Count(2).encode_with_hash().unwrap());
+
+        let messages: Vec<Count> = MessageReader::new(Cursor::new(stream))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(messages, [Count(1), Count(2)]);
+    }
+
+    #[test]
+    fn truncated_message() {
+        let mut stream = Count(1).encode_with_hash().unwrap();
+        stream.extend(&Count(2).encode_with_hash().unwrap()[..10]);
+
+        let mut reader = MessageReader::<_, Count>::new(Cursor::new(stream));
+        assert_eq!(reader.next().unwrap().unwrap(), Count(1));
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use {Marshall, Message};
+
+/// A handler that decodes a message and passes it to a user callback.
+type Handler<'a> = Box<FnMut(&str, &[u8]) + 'a>;
+
+/// Dispatches the messages on a channel to a handler for their type.
+///
+/// Each handler is registered for the hash of its message type. When a
+/// message arrives, the leading eight bytes are read as the hash and used to
+/// pick the handler, so a single channel can carry several message types.
+/// Messages whose hash has no handler are ignored.
+///
+/// Use `Lcm::subscribe_router` to attach a router to a channel.
+pub struct TypedRouter<'a> {
+    /// The handlers, keyed by the hash of the type they decode.
+    handlers: HashMap<u64, Handler<'a>>,
+}
+impl<'a> TypedRouter<'a> {
+    /// Creates a router with no handlers.
+    pub fn new() -> Self {
+        TypedRouter {
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Registers a handler for messages of type `M`.
+    ///
+    /// Registering a second handler for the same type replaces the first.
+    pub fn route<M, F>(&mut self, mut callback: F) -> &mut Self
+    where
+        M: Message,
+        F: FnMut(&str, M) + 'a,
+    {
+        let handler = move |channel: &str, bytes: &[u8]| {
+            match M::decode_with_hash(&mut &bytes[..]) {
+                Ok(message) => callback(channel, message),
+                Err(e) => warn!("Failed to decode message on {}: {}", channel, e),
+            }
+        };
+        self.handlers.insert(M::HASH, Box::new(handler));
+        self
+    }
+
+    /// Passes an encoded message to the handler for its hash.
+    ///
+    /// Returns `true` if a handler was found.
+    pub fn dispatch(&mut self, channel: &str, bytes: &[u8]) -> bool {
+        let hash = match u64::decode(&mut &bytes[..]) {
+            Ok(hash) => hash,
+            Err(_) => {
+                debug!("Message on {} is too short to have a hash", channel);
+                return false;
+            }
+        };
+
+        match self.handlers.get_mut(&hash) {
+            Some(handler) => {
+                handler(channel, bytes);
+                true
+            }
+            None => {
+                debug!("No handler for hash {:#018x} on {}", hash, channel);
+                false
+            }
+        }
+    }
+}
+impl<'a> Default for TypedRouter<'a> {
+    fn default() -> Self {
+        TypedRouter::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Two message types that share an encoding but not a hash.
+    test_message!(Left, 0x1111_1111);
+    test_message!(Right, 0x2222_2222);
+
+    #[test]
+    fn routes_by_hash() {
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let mut router = TypedRouter::new();
+        {
+            let left = received.clone();
+            let right = received.clone();
+            router
+                .route(move |_: &str, m: Left| left.borrow_mut().push(("left", m.0)))
+                .route(move |_: &str, m: Right| right.borrow_mut().push(("right", m.0)));
+        }
+
+        assert!(router.dispatch("BOTH", &Left(1).encode_with_hash().unwrap()));
+        assert!(router.dispatch("BOTH", &Right(2).encode_with_hash().unwrap()));
+        assert!(router.dispatch("BOTH", &Left(3).encode_with_hash().unwrap()));
+        assert_eq!(*received.borrow(), [("left", 1), ("right", 2), ("left", 3)]);
+    }
+
+    #[test]
+    fn unknown_and_short_messages() {
+        let mut router = TypedRouter::new();
+        router.route(|_: &str, _: Left| panic!("Unexpected message"));
+
+        let mut unknown = Vec::new();
+        0x3333_3333u64.encode(&mut unknown).unwrap();
+        1i32.encode(&mut unknown).unwrap();
+        assert!(!router.dispatch("BOTH", &unknown));
+        assert!(!router.dispatch("BOTH", &[0x11, 0x11]));
+    }
+}
@@ -0,0 +1,32 @@
+//! Helpers shared by the unit tests.
+
+/// Defines a message type that wraps an `i32`, for tests that need a message
+/// but don't care what is in it.
+///
+/// Every type made this way has the same encoding, so giving them different
+/// hashes makes it easy to check that messages are told apart by their hash.
+macro_rules! test_message {
+    ($(#[$attr:meta])* $name:ident, $hash:expr) => {
+        $(#[$attr])*
+        struct $name(i32);
+        impl $crate::Marshall for $name {
+            fn encode(
+                &self,
+                buffer: &mut ::std::io::Write,
+            ) -> Result<(), $crate::error::EncodeError> {
+                $crate::Marshall::encode(&self.0, buffer)
+            }
+
+            fn decode(buffer: &mut ::std::io::Read) -> Result<Self, $crate::error::DecodeError> {
+                Ok($name($crate::Marshall::decode(buffer)?))
+            }
+
+            fn size(&self) -> usize {
+                $crate::Marshall::size(&self.0)
+            }
+        }
+        impl $crate::Message for $name {
+            const HASH: u64 = $hash;
+        }
+    };
+}
@@ -0,0 +1,200 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Creates a new multi-producer channel, returning the sender/receiver halves.
+///
+/// This behaves like `spsc::channel`, except that the sender can be cloned.
+/// Sending to a full channel drops the oldest message. It is backed by a
+/// mutex, so the SPSC channel should be preferred when there is only a single
+/// producer.
+pub fn channel<T>(size: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(size > 0, "size must be greater than zero");
+
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(VecDeque::with_capacity(size)),
+        capacity: size,
+        closed: AtomicBool::new(false),
+    });
+    (
+        Sender {
+            inner: shared.clone(),
+        },
+        Receiver { inner: shared },
+    )
+}
+
+/// The state shared between the halves of the channel.
+struct Shared<T> {
+    /// The queued items, oldest first.
+    queue: Mutex<VecDeque<T>>,
+    /// Number of items the queue is able to store.
+    capacity: usize,
+    /// Set once the receiver has been dropped.
+    closed: AtomicBool,
+}
+
+/// The receiving half of the channel.
+pub struct Receiver<T> {
+    /// The state shared with the senders.
+    inner: Arc<Shared<T>>,
+}
+impl<T> Receiver<T> {
+    /// Returns the next item in the channel.
+    pub fn recv(&self) -> Option<T> {
+        self.inner
+            .queue
+            .lock()
+            .expect("Fan-in queue was poisoned")
+            .pop_front()
+    }
+
+    /// Returns the maximum number of items that this queue can hold.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity
+    }
+}
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.closed.store(true, Ordering::Release);
+    }
+}
+
+/// The sending half of the channel.
+pub struct Sender<T> {
+    /// The state shared with the receiver and the other senders.
+    inner: Arc<Shared<T>>,
+}
+impl<T> Sender<T> {
+    /// Pushes an item into the channel.
+    ///
+    /// If the queue is full, this will remove the oldest item and replace it
+    /// with the new one. The oldest item is dropped after the lock has been
+    /// released.
+    pub fn send(&self, item: T) {
+        let oldest = {
+            let mut queue = self.inner.queue.lock().expect("Fan-in queue was poisoned");
+            let oldest = if queue.len() == self.inner.capacity {
+                queue.pop_front()
+            } else {
+                None
+            };
+            queue.push_back(item);
+            oldest
+        };
+        drop(oldest);
+    }
+
+    /// Returns true if the receiving end of the channel is closed.
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::Acquire)
+    }
+}
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    #[test]
+    fn overwriting() {
+        const LIMIT: usize = 3;
+        let (p, c) = super::channel(LIMIT);
+
+        for x in 0..LIMIT + 2 {
+            p.send(x);
+        }
+
+        for x in 2..LIMIT + 2 {
+            assert_eq!(c.recv(), Some(x));
+        }
+        assert_eq!(c.recv(), None);
+    }
+
+    #[test]
+    fn closed() {
+        let (p, c) = super::channel::<u32>(1);
+        let p2 = p.clone();
+        assert!(!p.is_closed());
+
+        drop(c);
+        assert!(p.is_closed());
+        assert!(p2.is_closed());
+    }
+
+    #[test]
+    fn two_producers() {
+        const ITEMS: usize = 1000;
+        let (p, c) = super::channel(2 * ITEMS);
+
+        let producers: Vec<_> = (0..2)
+            .map(|id| {
+                let p = p.clone();
+                thread::spawn(move || {
+                    for x in 0..ITEMS {
+                        p.send((id, x));
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        // Each producer's items arrive in the order they were sent.
+        let mut next = [0, 0];
+        while let Some((id, x)) = c.recv() {
+            assert_eq!(x, next[id]);
+            next[id] += 1;
+        }
+        assert_eq!(next, [ITEMS, ITEMS]);
+    }
+
+    #[test]
+    fn two_producers_overwriting() {
+        const LIMIT: usize = 8;
+        let (p, c) = super::channel(LIMIT);
+
+        let producers: Vec<_> = (0..2)
+            .map(|id| {
+                let p = p.clone();
+                thread::spawn(move || {
+                    for x in 0..10_000 {
+                        p.send((id, x));
+                    }
+                })
+            })
+            .collect();
+
+        // Items from each producer are never received out of order, even
+        // while older ones are being overwritten.
+        let mut prev = [None, None];
+        let mut check = |(id, x): (usize, usize)| {
+            if let Some(p) = prev[id] {
+                assert!(x > p);
+            }
+            prev[id] = Some(x);
+        };
+        for _ in 0..10_000 {
+            if let Some(item) = c.recv() {
+                check(item);
+            }
+        }
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut remaining = 0;
+        while let Some(item) = c.recv() {
+            check(item);
+            remaining += 1;
+        }
+        assert!(remaining <= LIMIT);
+    }
+}
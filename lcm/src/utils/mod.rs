@@ -1 +1,5 @@
 pub mod spsc;
+
+// No provider has multiple producers yet, so nothing uses this.
+#[allow(dead_code)]
+pub mod fanin;
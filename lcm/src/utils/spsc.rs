@@ -1,7 +1,14 @@
 use std::cell::Cell;
+use std::mem::ManuallyDrop;
 use std::sync::Arc;
+use std::{cmp, mem, ptr};
+
+// The atomics are swapped out when model checking the queue with loom. See the
+// `model_check` test module at the bottom for how to run it.
+#[cfg(not(all(test, loom)))]
 use std::sync::atomic::{spin_loop_hint, AtomicUsize, Ordering};
-use std::{mem, ptr};
+#[cfg(all(test, loom))]
+use loom::sync::atomic::{spin_loop_hint, AtomicUsize, Ordering};
 
 /// Creates a new asynchronous channel, returning the sender/receiver halves.
 ///
@@ -41,6 +48,14 @@ impl<T> Receiver<T> {
     pub fn capacity(&self) -> usize {
         (*self.inner).capacity
     }
+
+    /// Returns true if there are no items in the channel.
+    ///
+    /// The sender may add items at any time, so this can be out of date as
+    /// soon as it returns.
+    pub fn is_empty(&self) -> bool {
+        (*self.inner).len() == 0
+    }
 }
 unsafe impl<T: Send> Send for Receiver<T> {}
 //impl<T> !Sync for Receiver<T> { }
@@ -72,6 +87,16 @@ impl<T> Sender<T> {
     pub fn is_closed(&self) -> bool {
         Arc::strong_count(&self.inner) < 2
     }
+
+    /// Returns the number of items that can be sent before the oldest items
+    /// start being overwritten.
+    ///
+    /// The receiver may take items at any time, so this is only a lower bound.
+    // Not used by any of the providers yet, but available for backpressure.
+    #[allow(dead_code)]
+    pub fn remaining(&self) -> usize {
+        (*self.inner).capacity - (*self.inner).len()
+    }
 }
 unsafe impl<T: Send> Send for Sender<T> {}
 //impl<T> !Sync for Sender<T> { }
@@ -166,52 +191,99 @@ impl<T> RingBuffer<T> {
         // head to the time the consumer does the CAS. For 32bit systems, this
         // is super unlikely. For 64bit systems, this is basically impossible.
         for _ in 0..(1 + self.capacity / 2) {
-            // Get the current head.
-            let head = self.head.load(Ordering::Acquire);
-
-            // Check to see if we think we're full
-            if head >= self.shadow_tail.get() {
-                // Double check to see if we're really full
-                self.shadow_tail.set(self.tail.load(Ordering::Acquire));
-
-                debug_assert!(
-                    head <= self.shadow_tail.get(),
-                    "head is further than shadow tail"
-                );
-
-                if head == self.shadow_tail.get() {
-                    // We are really, for real, full
-                    return None;
-                }
-            }
-
-            // We have at least one!
-            let val = self.load(head);
-
-            // Make sure that the data we loaded was actually valid and, if it was,
-            // increment the head
-            if self.head
-                .compare_and_swap(head, head.wrapping_add(1), Ordering::Release)
-                == head
-            {
-                return Some(val);
+            match self.try_pop() {
+                Pop::Empty => return None,
+                Pop::Item(val) => return Some(val),
+                Pop::Contended => {}
             }
         }
 
-        // At this point, we give up and acquire the lock
+        // At this point, we give up and acquire the lock. The producer may
+        // already be past the lock check for its current push, so it can
+        // still beat us once more. After that it waits for the lock to be
+        // released, so this loop runs at most twice.
         debug_assert_eq!(
             self.giveup_lock.load(Ordering::Relaxed),
             0,
             "recursive giveup"
         );
-        self.giveup_lock.store(1, Ordering::Acquire);
-        let val = self.pop();
+        self.giveup_lock.store(1, Ordering::SeqCst);
+        let val = loop {
+            match self.try_pop() {
+                Pop::Empty => break None,
+                Pop::Item(val) => break Some(val),
+                Pop::Contended => {}
+            }
+        };
         self.giveup_lock.store(0, Ordering::Release);
-        assert!(val.is_some(), "gave up on an empty queue"); // Curious to see this ever happen
 
+        // This can be `None` even though the producer just moved the head of a
+        // full queue: it may not have published its new item yet. That item
+        // is picked up by the next call.
         val
     }
 
+    /// Returns the number of items in the queue.
+    fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+
+        // The producer may have overwritten items since the head was loaded,
+        // which makes the queue look longer than it can be.
+        cmp::min(tail.wrapping_sub(head), self.capacity)
+    }
+
+    /// Makes a single attempt at taking the next item in the queue.
+    fn try_pop(&self) -> Pop<T> {
+        // Get the current head.
+        let head = self.head.load(Ordering::Acquire);
+
+        // Check to see if we think we're empty
+        if head >= self.shadow_tail.get() {
+            // Double check to see if we're really empty
+            self.shadow_tail.set(self.tail.load(Ordering::Acquire));
+
+            debug_assert!(
+                head <= self.shadow_tail.get(),
+                "head is further than shadow tail"
+            );
+
+            if head == self.shadow_tail.get() {
+                // We are really, for real, empty
+                return Pop::Empty;
+            }
+        }
+
+        // We have at least one! The producer may be overwriting it while we
+        // read it, in which case it also drops it, so our copy must not be
+        // dropped unless we manage to claim it.
+        let val = ManuallyDrop::new(self.load(head));
+
+        // Make sure that the data we loaded was actually valid and, if it was,
+        // increment the head
+        match self.advance_head(head) {
+            Ok(_) => Pop::Item(ManuallyDrop::into_inner(val)),
+            Err(_) => Pop::Contended,
+        }
+    }
+
+    /// Moves the head from `head` to the next item, unless someone else
+    /// already moved it.
+    ///
+    /// Both the consumer and the producer move the head, so on failure this
+    /// returns the head that the other one left behind.
+    #[inline]
+    fn advance_head(&self, head: usize) -> Result<usize, usize> {
+        // A failed attempt does not publish anything, so the failure ordering
+        // can be relaxed. This matches the orderings `compare_and_swap` used.
+        self.head.compare_exchange(
+            head,
+            head.wrapping_add(1),
+            Ordering::Release,
+            Ordering::Relaxed,
+        )
+    }
+
     /// Pushes an item onto the queue.
     ///
     /// If the queue is full, this will remove the oldest item and replace it
@@ -240,23 +312,22 @@ impl<T> RingBuffer<T> {
 
                 // Try to move the head up one
                 let head = self.shadow_head.get();
-                let old_head =
-                    self.head
-                        .compare_and_swap(head, head.wrapping_add(1), Ordering::Release);
-
-                if head != old_head {
-                    // The consumer managed to pop at least one value
-                    debug_assert!(old_head > head, "head decreased");
-                    self.shadow_head.set(old_head);
-                } else {
-                    // We manually moved the head, so we know the limit is at least one more
-                    self.shadow_head.set(head.wrapping_add(1));
-
-                    // We also need to drop the old value before we overwrite it
-                    let conv_offset = (head % self.capacity) as isize;
-                    debug_assert!(conv_offset >= 0, "converted offset does not fit in usize");
-                    unsafe {
-                        ptr::drop_in_place(self.data.offset(conv_offset));
+                match self.advance_head(head) {
+                    Err(old_head) => {
+                        // The consumer managed to pop at least one value
+                        debug_assert!(old_head > head, "head decreased");
+                        self.shadow_head.set(old_head);
+                    }
+                    Ok(_) => {
+                        // We manually moved the head, so we know the limit is at least one more
+                        self.shadow_head.set(head.wrapping_add(1));
+
+                        // We also need to drop the old value before we overwrite it
+                        let conv_offset = (head % self.capacity) as isize;
+                        debug_assert!(conv_offset >= 0, "converted offset does not fit in usize");
+                        unsafe {
+                            ptr::drop_in_place(self.data.offset(conv_offset));
+                        }
                     }
                 }
             }
@@ -268,6 +339,11 @@ impl<T> RingBuffer<T> {
     }
 
     /// Stores an item into the buffer.
+    ///
+    /// The slot must be logically empty, i.e., it has never been written or
+    /// its item has since been moved out by `pop` or dropped by `push`. The
+    /// item is written without dropping whatever bytes are already there,
+    /// since those belong to an item that is either gone or owned elsewhere.
     #[inline]
     fn store(&self, offset: usize, item: T) {
         let conv_offset = (offset % self.capacity) as isize;
@@ -310,8 +386,21 @@ impl<T> Drop for RingBuffer<T> {
     }
 }
 
-#[cfg(test)]
+/// The result of a single attempt at popping an item from the queue.
+enum Pop<T> {
+    /// The queue was empty.
+    Empty,
+    /// The item was taken from the queue.
+    Item(T),
+    /// The producer overwrote the item before it could be taken.
+    Contended,
+}
+
+#[cfg(all(test, not(loom)))]
 mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicIsize, Ordering};
+
     #[test]
     fn basic_in_out() {
         const LIMIT: usize = 3;
@@ -345,6 +434,56 @@ mod test {
         assert_eq!(c.recv(), None);
     }
 
+    #[test]
+    fn single_slot() {
+        let (p, c) = super::channel(1);
+
+        for x in 0..10 {
+            p.send(x);
+        }
+
+        assert_eq!(c.recv(), Some(9));
+        assert_eq!(c.recv(), None);
+    }
+
+    #[test]
+    fn lost_head_race() {
+        use super::*;
+
+        // The producer overwrites the item that the consumer is about to
+        // claim, so the consumer's attempt to move the head must fail without
+        // moving it again.
+        let buffer = RingBuffer::new(1);
+        buffer.push(1);
+        let head = buffer.head.load(Ordering::Acquire);
+        buffer.push(2);
+
+        assert_eq!(buffer.advance_head(head), Err(head + 1));
+        assert_eq!(buffer.head.load(Ordering::Acquire), head + 1);
+        assert_eq!(buffer.pop(), Some(2));
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn remaining() {
+        const LIMIT: usize = 5;
+        let (p, c) = super::channel(LIMIT);
+        assert_eq!(p.remaining(), LIMIT);
+
+        for x in 0..3 {
+            p.send(x);
+        }
+        assert_eq!(p.remaining(), LIMIT - 3);
+
+        c.recv().unwrap();
+        assert_eq!(p.remaining(), LIMIT - 2);
+
+        for x in 0..LIMIT {
+            p.send(x);
+        }
+        assert_eq!(p.remaining(), 0);
+    }
+
     #[test]
     fn hammer_time() {
         use std::thread;
@@ -439,6 +578,148 @@ mod test {
         done_p.send(()).unwrap();
     }
 
+    /// An item that keeps track of how many copies of it are alive, so that
+    /// items dropped twice or never dropped are noticed.
+    struct Tracked(usize, Arc<AtomicIsize>);
+    impl Tracked {
+        fn new(x: usize, live: &Arc<AtomicIsize>) -> Tracked {
+            live.fetch_add(1, Ordering::SeqCst);
+            Tracked(x, live.clone())
+        }
+    }
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            let prev = self.1.fetch_sub(1, Ordering::SeqCst);
+            assert!(prev > 0, "item dropped twice");
+        }
+    }
+
+    #[test]
+    fn drops_overwritten_items() {
+        const LIMIT: usize = 3;
+        let live = Arc::new(AtomicIsize::new(0));
+        let (p, c) = super::channel(LIMIT);
+
+        // Push past capacity, so the oldest items are overwritten.
+        for x in 0..2 * LIMIT {
+            p.send(Tracked::new(x, &live));
+        }
+        assert_eq!(live.load(Ordering::SeqCst), LIMIT as isize);
+
+        // Popped items belong to the caller.
+        let first = c.recv().unwrap();
+        assert_eq!(first.0, LIMIT);
+        assert_eq!(live.load(Ordering::SeqCst), LIMIT as isize);
+        drop(first);
+        assert_eq!(live.load(Ordering::SeqCst), LIMIT as isize - 1);
+
+        // The remaining items are dropped along with the channel.
+        drop(p);
+        drop(c);
+        assert_eq!(live.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn drops_after_wrapping() {
+        const LIMIT: usize = 3;
+        let live = Arc::new(AtomicIsize::new(0));
+        let (p, c) = super::channel(LIMIT);
+
+        // Keep the buffer partly full while wrapping around it several times,
+        // so every slot is reused after its item was popped.
+        let mut next = 0;
+        for _ in 0..5 * LIMIT {
+            p.send(Tracked::new(next, &live));
+            p.send(Tracked::new(next + 1, &live));
+            next += 2;
+
+            let v = c.recv().unwrap();
+            assert_eq!(live.load(Ordering::SeqCst), 2);
+            drop(v);
+            let v = c.recv().unwrap();
+            assert_eq!(v.0, next - 1);
+            drop(v);
+            assert_eq!(live.load(Ordering::SeqCst), 0);
+        }
+
+        p.send(Tracked::new(next, &live));
+        drop(p);
+        drop(c);
+        assert_eq!(live.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn drops_heap_items() {
+        const LIMIT: usize = 4;
+        let (p, c) = super::channel(LIMIT);
+
+        for x in 0..3 * LIMIT + 1 {
+            p.send(vec![x.to_string(); x]);
+        }
+
+        assert_eq!(c.recv(), Some(vec![(2 * LIMIT + 1).to_string(); 2 * LIMIT + 1]));
+    }
+
+    /// Runs a producer and consumer against each other, pausing for the given
+    /// number of spins between each send and receive.
+    ///
+    /// The last item is never overwritten, so the consumer always sees it.
+    fn stress(limit: usize, producer_spins: usize, consumer_spins: usize) {
+        use std::thread;
+
+        const ITEMS: usize = 20_000;
+        let live = Arc::new(AtomicIsize::new(0));
+        let (p, c) = super::channel(limit);
+
+        let producer = {
+            let live = live.clone();
+            thread::spawn(move || {
+                for x in 1..ITEMS + 1 {
+                    p.send(Tracked::new(x, &live));
+                    for _ in 0..producer_spins {
+                        ::std::hint::spin_loop();
+                    }
+                }
+            })
+        };
+
+        let mut prev = 0;
+        while prev < ITEMS {
+            if let Some(v) = c.recv() {
+                assert!(v.0 > prev);
+                prev = v.0;
+            }
+            for _ in 0..consumer_spins {
+                ::std::hint::spin_loop();
+            }
+        }
+
+        producer.join().unwrap();
+        drop(c);
+        assert_eq!(live.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn stress_single_slot() {
+        stress(1, 0, 0);
+        stress(1, 0, 50);
+        stress(1, 50, 0);
+    }
+
+    #[test]
+    fn stress_small_queue() {
+        stress(2, 0, 0);
+        stress(3, 0, 50);
+        stress(3, 50, 0);
+    }
+
+    #[test]
+    fn stress_large_queue() {
+        stress(64, 0, 0);
+        stress(64, 0, 50);
+        stress(64, 50, 0);
+    }
+
     #[test]
     fn padding() {
         // Before Rust 1.24, using `#[repr(C, packed)]` did not require blocks
@@ -459,3 +740,70 @@ mod test {
         assert_eq!(size_of::<RingBuffer<u32>>(), total_size);
     }
 }
+
+/// Model checks the queue with loom.
+///
+/// Run with `RUSTFLAGS="--cfg loom" cargo test -p lcm --release spsc::model_check`.
+#[cfg(all(test, loom))]
+mod model_check {
+    use loom;
+    use loom::thread;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicIsize, Ordering};
+
+    /// An item that keeps track of how many copies of it are alive.
+    struct Tracked(usize, Arc<AtomicIsize>);
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            let prev = self.1.fetch_sub(1, Ordering::SeqCst);
+            assert!(prev > 0, "item dropped twice");
+        }
+    }
+
+    /// Sends `items` items into a queue of the given size while another
+    /// thread receives until it sees the last one.
+    ///
+    /// The consumer yields whenever the queue is empty, which is what gives
+    /// loom a chance to interleave the two threads.
+    fn model(limit: usize, items: usize) {
+        loom::model(move || {
+            let live = Arc::new(AtomicIsize::new(0));
+            let (p, c) = super::channel(limit);
+
+            let producer = {
+                let live = live.clone();
+                thread::spawn(move || {
+                    for x in 1..items + 1 {
+                        live.fetch_add(1, Ordering::SeqCst);
+                        p.send(Tracked(x, live.clone()));
+                    }
+                })
+            };
+
+            let mut prev = 0;
+            while prev < items {
+                match c.recv() {
+                    Some(v) => {
+                        assert!(v.0 > prev);
+                        prev = v.0;
+                    }
+                    None => thread::yield_now(),
+                }
+            }
+
+            producer.join().unwrap();
+            drop(c);
+            assert_eq!(live.load(Ordering::SeqCst), 0);
+        });
+    }
+
+    #[test]
+    fn single_slot() {
+        model(1, 3);
+    }
+
+    #[test]
+    fn two_slots() {
+        model(2, 4);
+    }
+}
@@ -7,5 +7,14 @@ fn main() {
         .filter_map(Result::ok)
         .collect();
 
-    lcm_gen::generate(&files).expect("Failed to generate bindings for LCM types");
+    let mut config = lcm_gen::Config {
+        content_hash: true,
+        reset: true,
+        builder: true,
+        byte_conversions: true,
+        ..lcm_gen::Config::default()
+    };
+    config
+        .generate(&files)
+        .expect("Failed to generate bindings for LCM types");
 }
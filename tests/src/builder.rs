@@ -0,0 +1,35 @@
+#[test]
+fn build_point2d_list() {
+    let message = ::Point2dList::builder()
+        .points(vec![[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]])
+        .build();
+    assert_eq!(message.npoints, 3);
+    assert_eq!(message.points, [[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]]);
+
+    let empty = ::Point2dList::builder().build();
+    assert_eq!(empty.npoints, 0);
+    assert!(empty.points.is_empty());
+}
+
+#[test]
+fn build_round_trip() {
+    // The length always matches the array, so the message encodes.
+    let message = ::Point2dList::builder().points(vec![[1.0, 2.0]]).build();
+    let encoded = ::lcm::encode(&message).unwrap();
+    let decoded: ::Point2dList = ::lcm::decode(&encoded).unwrap();
+    assert_eq!(decoded.npoints, 1);
+    assert_eq!(decoded.points, [[1.0, 2.0]]);
+}
+
+#[test]
+fn build_nested() {
+    let message = ::NestedArrays::builder().build();
+    for row in &message.names {
+        assert!(row.iter().all(String::is_empty));
+    }
+    for row in &message.grid {
+        for pose in row {
+            assert_eq!((pose.x, pose.y, pose.theta), (0.0, 0.0, 0.0));
+        }
+    }
+}
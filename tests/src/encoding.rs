@@ -0,0 +1,125 @@
+use lcm;
+use std::convert::TryFrom;
+
+#[test]
+fn temperature_round_trip() {
+    let message = ::Temperature {
+        utime: 1_500_000_000_000_000,
+        degCelsius: 21.5,
+    };
+
+    let encoded = lcm::encode(&message).unwrap();
+    assert_eq!(encoded.len(), 8 + 8 + 8);
+    let decoded: ::Temperature = lcm::decode(&encoded).unwrap();
+
+    assert_eq!(decoded.utime, message.utime);
+    assert_eq!(decoded.degCelsius, message.degCelsius);
+}
+
+#[test]
+fn point2d_list_round_trip() {
+    let message = ::Point2dList {
+        npoints: 2,
+        points: vec![[1.0, 2.0], [3.0, 4.0]],
+    };
+
+    let encoded = lcm::encode(&message).unwrap();
+    let decoded: ::Point2dList = lcm::decode(&encoded).unwrap();
+
+    assert_eq!(decoded.npoints, 2);
+    assert_eq!(decoded.points, message.points);
+}
+
+#[test]
+fn decode_wrong_type() {
+    let encoded = lcm::encode(&::Point2dList {
+        npoints: 0,
+        points: vec![],
+    }).unwrap();
+    assert!(lcm::decode::<::Temperature>(&encoded).is_err());
+}
+
+#[test]
+fn byte_conversions() {
+    let message = ::Point2dList {
+        npoints: 2,
+        points: vec![[1.0, 2.0], [3.0, 4.0]],
+    };
+
+    let encoded = Vec::<u8>::try_from(&message).unwrap();
+    assert_eq!(encoded, lcm::encode(&message).unwrap());
+    let decoded = ::Point2dList::try_from(&encoded[..]).unwrap();
+    assert_eq!(decoded.npoints, 2);
+    assert_eq!(decoded.points, message.points);
+
+    assert!(::Temperature::try_from(&encoded[..]).is_err());
+}
+
+#[test]
+fn byte_conversion_errors() {
+    let message = ::Point2dList {
+        npoints: 3,
+        points: vec![[1.0, 2.0]],
+    };
+    match Vec::<u8>::try_from(&message) {
+        Err(lcm::error::EncodeError::SizeMismatch { expected: 3, found: 1, .. }) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+
+    let mut encoded = lcm::encode(&::Point2dList {
+        npoints: 1,
+        points: vec![[1.0, 2.0]],
+    }).unwrap();
+    encoded.push(0);
+    match ::Point2dList::try_from(&encoded[..]) {
+        Err(lcm::error::DecodeError::TrailingBytes(1)) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn content_hash() {
+    let message = |degCelsius| ::Temperature {
+        utime: 1_500_000_000_000_000,
+        degCelsius,
+    };
+
+    let hash = message(21.5).content_hash().unwrap();
+    assert_eq!(message(21.5).content_hash().unwrap(), hash);
+    assert_ne!(message(21.6).content_hash().unwrap(), hash);
+}
+
+#[test]
+fn decode_truncated_array() {
+    let encoded = lcm::encode(&::Point2dList {
+        npoints: 3,
+        points: vec![[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]],
+    }).unwrap();
+
+    match lcm::decode::<::Point2dList>(&encoded[..encoded.len() - 8]) {
+        Err(lcm::error::DecodeError::Truncated {
+            field: "points",
+            size_var: "npoints",
+            expected: 3,
+        }) => {}
+        res => panic!("Unexpected result: {:?}", res.map(|m| m.points)),
+    }
+}
+
+#[test]
+fn point2d_list_file_round_trip() {
+    use lcm::Message;
+    use std::{env, fs, process};
+
+    let message = ::Point2dList {
+        npoints: 2,
+        points: vec![[1.0, 2.0], [3.0, 4.0]],
+    };
+    let path = env::temp_dir().join(format!("lcm_point2d_list_{}.bin", process::id()));
+
+    message.encode_to_file(&path).unwrap();
+    let decoded = ::Point2dList::decode_from_file(&path);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(decoded.unwrap().points, message.points);
+}
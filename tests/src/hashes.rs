@@ -9,4 +9,8 @@ fn hashes() {
     assert_eq!(::MyStruct::HASH, 0x4fab8e09620e9ec9);
     assert_eq!(::Point2dList::HASH, 0x4f85d1e7da2fc594);
     assert_eq!(::Temperature::HASH, 0xa07fa3d64cbea6ea);
+
+    // This one hasn't been checked against the C implementation, so it only
+    // catches changes to the hash.
+    assert_eq!(::Blob::HASH, 0x0bf63d68bdf86d1c);
 }
@@ -6,5 +6,15 @@ extern crate lcm_derive;
 
 include!(concat!(env!("OUT_DIR"), "/mod.rs"));
 
+#[cfg(test)]
+mod builder;
+#[cfg(test)]
+mod encoding;
 #[cfg(test)]
 mod hashes;
+#[cfg(test)]
+mod reset;
+#[cfg(test)]
+mod size;
+#[cfg(test)]
+mod unsigned;
@@ -0,0 +1,36 @@
+#[test]
+fn reset_vectors() {
+    let mut message = ::Point2dList {
+        npoints: 2,
+        points: vec![[1.0, 2.0], [3.0, 4.0]],
+    };
+
+    message.reset();
+    assert_eq!(message.npoints, 0);
+    assert!(message.points.is_empty());
+    assert!(message.points.capacity() >= 2);
+}
+
+#[test]
+fn reset_nested() {
+    let pose = ::Pose {
+        x: 1.0,
+        y: 2.0,
+        theta: 3.0,
+    };
+    let names = || ["a".to_string(), "b".to_string(), "c".to_string()];
+    let mut message = ::NestedArrays {
+        names: [names(), names()],
+        grid: [[pose.clone(), pose.clone()], [pose.clone(), pose]],
+    };
+
+    message.reset();
+    for row in &message.names {
+        assert!(row.iter().all(String::is_empty));
+    }
+    for row in &message.grid {
+        for pose in row {
+            assert_eq!((pose.x, pose.y, pose.theta), (0.0, 0.0, 0.0));
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use lcm::Marshall;
+
+fn nested_arrays() -> ::NestedArrays {
+    let pose = |x| ::Pose {
+        x,
+        y: 2.0 * x,
+        theta: 0.5,
+    };
+
+    ::NestedArrays {
+        names: [
+            ["a".into(), "bb".into(), "ccc".into()],
+            ["".into(), "eeeee".into(), "ffffff".into()],
+        ],
+        grid: [[pose(1.0), pose(2.0)], [pose(3.0), pose(4.0)]],
+    }
+}
+
+#[test]
+fn nested_arrays_size() {
+    let message = nested_arrays();
+
+    // Each string is a 4 byte length, the bytes, and a null terminator.
+    let names_size: usize = [1, 2, 3, 0, 5, 6].iter().map(|len| 4 + len + 1).sum();
+    // Each pose is three doubles.
+    let grid_size = 4 * 3 * 8;
+
+    let mut buffer = Vec::new();
+    message.encode(&mut buffer).unwrap();
+
+    assert_eq!(buffer.len(), names_size + grid_size);
+    assert_eq!(message.size(), buffer.len());
+}
+
+#[test]
+fn nested_arrays_round_trip() {
+    let message = nested_arrays();
+
+    let mut buffer = Vec::new();
+    message.encode(&mut buffer).unwrap();
+    let decoded = ::NestedArrays::decode(&mut buffer.as_slice()).unwrap();
+
+    assert_eq!(decoded.names, message.names);
+    for (row, expected_row) in decoded.grid.iter().zip(message.grid.iter()) {
+        for (pose, expected) in row.iter().zip(expected_row.iter()) {
+            assert_eq!(pose.x, expected.x);
+            assert_eq!(pose.y, expected.y);
+            assert_eq!(pose.theta, expected.theta);
+        }
+    }
+}
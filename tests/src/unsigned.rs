@@ -0,0 +1,46 @@
+use lcm;
+
+/// A hand-written message using each of the unsigned types.
+#[derive(Clone, Debug, Message, PartialEq)]
+struct Counters {
+    flags: u8,
+    port: u16,
+    total: u32,
+    count: u8,
+    #[lcm(length = "count")]
+    samples: Vec<u16>,
+    history: [u32; 2],
+}
+
+#[test]
+fn unsigned_round_trip() {
+    let message = Counters {
+        flags: 0xff,
+        port: 7667,
+        total: 0xdead_beef,
+        count: 3,
+        samples: vec![1, 2, 0xffff],
+        history: [0, u32::MAX],
+    };
+
+    let encoded = lcm::encode(&message).unwrap();
+    assert_eq!(encoded.len(), 8 + 1 + 2 + 4 + 1 + 3 * 2 + 2 * 4);
+    assert_eq!(encoded[8..15], [0xff, 0x1d, 0xf3, 0xde, 0xad, 0xbe, 0xef]);
+    let decoded: Counters = lcm::decode(&encoded).unwrap();
+    assert_eq!(decoded, message);
+}
+
+#[test]
+fn byte_round_trip() {
+    let message = ::Blob {
+        size: 3,
+        data: vec![0, 0x7f, 0xff],
+        checksum: 0x80,
+    };
+
+    let encoded = lcm::encode(&message).unwrap();
+    assert_eq!(encoded[8..], [0, 0, 0, 3, 0, 0x7f, 0xff, 0x80]);
+    let decoded: ::Blob = lcm::decode(&encoded).unwrap();
+    assert_eq!(decoded.data, message.data);
+    assert_eq!(decoded.checksum, message.checksum);
+}